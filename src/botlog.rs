@@ -0,0 +1,171 @@
+use commands::CommandError;
+
+use rand::{self, Rng};
+use typemap;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::ChannelId;
+use serenity::utils::Colour;
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW_SECS: u64 = 60;
+
+/// Keeps count of how many messages were sent recently, so we can back off when too much is happening.
+struct RateLimiter {
+    max: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+    suppressed: usize,
+}
+
+impl RateLimiter {
+    fn new(max: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            max: max,
+            window: window,
+            sent: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// Checks if a message can be sent now. Returns how many messages were
+    /// suppressed since the last one that got through, or `None` if this
+    /// one should be suppressed too.
+    fn allow(&mut self, now: Instant) -> Option<usize> {
+        while self.sent.front().map_or(false, |&t| now.duration_since(t) >= self.window) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.max {
+            self.suppressed += 1;
+            return None;
+        }
+
+        self.sent.push_back(now);
+        Some(::std::mem::replace(&mut self.suppressed, 0))
+    }
+}
+
+/// Posts embeds about what the bot is doing to a channel, for keeping an eye on it from Discord.
+pub struct BotLog {
+    channel_id: ChannelId,
+    limiter: Mutex<RateLimiter>,
+    connected_shards: Mutex<HashSet<u64>>,
+}
+
+impl typemap::Key for BotLog {
+    type Value = Arc<BotLog>;
+}
+
+/// Generates a short id for an error, so it can be found in the logs.
+pub fn error_id() -> String {
+    format!("{:08x}", rand::thread_rng().gen::<u32>())
+}
+
+/// Fetches the bot log from the client data, if one is set up.
+pub fn get(ctx: &Context) -> Option<Arc<BotLog>> {
+    let data = ctx.data.lock();
+    data.get::<BotLog>().map(Arc::clone)
+}
+
+impl BotLog {
+    pub fn new(channel_id: ChannelId, max_per_minute: usize) -> BotLog {
+        BotLog {
+            channel_id: channel_id,
+            limiter: Mutex::new(RateLimiter::new(max_per_minute, Duration::from_secs(RATE_WINDOW_SECS))),
+            connected_shards: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Posts an embed to the log channel, unless too many have been posted recently.
+    pub fn post(&self, title: &str, description: &str, colour: Colour) {
+        let suppressed = match self.limiter.lock().unwrap().allow(Instant::now()) {
+            Some(n) => n,
+            None => return,
+        };
+
+        let result = self.channel_id.send_message(|m| m.embed(|e| {
+            let e = e.title(title)
+                .description(description)
+                .colour(colour)
+                .timestamp(&::chrono::Utc::now());
+            if suppressed > 0 {
+                e.footer(|f| f.text(&format!("{} earlier event(s) were not posted to avoid flooding", suppressed)))
+            }
+            else {
+                e
+            }
+        }));
+
+        // Errors only go to the local log, posting them here could loop forever.
+        if let Err(e) = result {
+            warn!("Could not post to the bot log: {}", e);
+        }
+    }
+
+    pub fn started(&self) {
+        self.post("Starting up", &format!("plankboat {} is starting.", env!("CARGO_PKG_VERSION")), Colour::blue());
+    }
+
+    pub fn shutting_down(&self) {
+        self.post("Shutting down", "plankboat is shutting down.", Colour::blue());
+    }
+
+    pub fn shard_ready(&self, shard_id: u64) {
+        if self.connected_shards.lock().unwrap().insert(shard_id) {
+            self.post("Shard connected", &format!("Shard {} connected.", shard_id), Colour::dark_green());
+        }
+        else {
+            self.post("Shard reconnected", &format!("Shard {} lost its session and reconnected.", shard_id), Colour::orange());
+        }
+    }
+
+    pub fn shard_resumed(&self, shard_id: u64) {
+        self.post("Shard resumed", &format!("Shard {} was disconnected and resumed its session.", shard_id), Colour::orange());
+    }
+
+    pub fn guild_joined(&self, name: &str, id: u64, members: u64) {
+        self.post("Joined guild", &format!("{} ({}), {} members", name, id, members), Colour::dark_green());
+    }
+
+    pub fn guild_left(&self, name: &str, id: u64) {
+        self.post("Left guild", &format!("{} ({})", name, id), Colour::dark_grey());
+    }
+
+    pub fn guild_unavailable(&self, id: u64) {
+        self.post("Guild unavailable", &format!("Guild {} became unavailable.", id), Colour::orange());
+    }
+
+    pub fn command_error(&self, error_id: &str, command: &str, msg: &Message, error: &CommandError) {
+        let place = match msg.guild_id() {
+            Some(guild_id) => format!("guild {}, channel {}", guild_id, msg.channel_id),
+            None => format!("DM channel {}", msg.channel_id),
+        };
+        self.post(
+            &format!("Command error {}", error_id),
+            &format!("`{}` by {} in {}:\n{}", command, msg.author.tag(), place, error),
+            Colour::red());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_counts_suppressed() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert_eq!(limiter.allow(start), Some(0));
+        assert_eq!(limiter.allow(start), Some(0));
+        assert_eq!(limiter.allow(start + Duration::from_secs(1)), None);
+        assert_eq!(limiter.allow(start + Duration::from_secs(2)), None);
+        assert_eq!(limiter.allow(start + Duration::from_secs(60)), Some(2));
+        assert_eq!(limiter.allow(start + Duration::from_secs(61)), Some(0));
+        assert_eq!(limiter.allow(start + Duration::from_secs(62)), None);
+    }
+}
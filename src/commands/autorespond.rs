@@ -0,0 +1,659 @@
+use commands::{Command, CommandError, CommandResult, Middleware, MessageState, Flow, database};
+use commands::{guild_only, guild_admin_only, parse_period, parse_reaction, sanitize_mentions};
+use commands::group::CommandGroup;
+use commands::owner::format_duration;
+use commands::tags::{Variables, render};
+use db;
+
+use chrono::Utc;
+use rand;
+use regex::{self, Regex, RegexBuilder};
+use rusqlite;
+use rusqlite::Connection;
+use typemap;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::{Colour, parse_channel};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The longest pattern a trigger can have.
+const MAX_PATTERN_LEN: usize = 200;
+
+/// Limits for compiled regexes, so a pattern can't use up lots of memory or time.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const REGEX_DFA_SIZE_LIMIT: usize = 2 << 20;
+
+/// Leaves room below the message length limit for the template variables.
+const MAX_RESPONSE_LEN: usize = 1500;
+
+/// How many triggers a guild can have.
+const MAX_TRIGGERS: usize = 50;
+
+/// Leaves some room below Discord's 2000 character limit.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+/// How a trigger matches messages.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Match {
+    /// The pattern appears as a whole word, ignoring case.
+    Word,
+    /// The pattern appears anywhere, ignoring case.
+    Contains,
+    /// The pattern is a regex.
+    Regex,
+}
+
+impl Match {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Match::Word => "word",
+            Match::Contains => "contains",
+            Match::Regex => "regex",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Match> {
+        match name {
+            "word" => Some(Match::Word),
+            "contains" => Some(Match::Contains),
+            "regex" => Some(Match::Regex),
+            _ => None,
+        }
+    }
+}
+
+/// What a trigger does when it matches.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Response {
+    Text,
+    React,
+    Embed,
+}
+
+impl Response {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Response::Text => "text",
+            Response::React => "react",
+            Response::Embed => "embed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Response> {
+        match name {
+            "text" => Some(Response::Text),
+            "react" => Some(Response::React),
+            "embed" => Some(Response::Embed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub id: i64,
+    pub match_kind: Match,
+    pub pattern: String,
+    pub response_kind: Response,
+    pub response: String,
+    /// Seconds before the trigger can fire again, 0 for no cooldown.
+    pub cooldown: i64,
+    /// The channels the trigger works in, or every channel if empty.
+    pub channels: Vec<ChannelId>,
+}
+
+/// Compiles the pattern of a trigger into a regex, returning the reason if it's too big or invalid.
+pub fn compile(match_kind: Match, pattern: &str) -> Result<Regex, String> {
+    if pattern.is_empty() || pattern.chars().count() > MAX_PATTERN_LEN {
+        return Err(format!("Patterns have to be between 1 and {} characters long.", MAX_PATTERN_LEN));
+    }
+
+    let source = match match_kind {
+        Match::Word => format!(r"(?i)(?:^|\W){}(?:\W|$)", regex::escape(pattern)),
+        Match::Contains => format!("(?i){}", regex::escape(pattern)),
+        Match::Regex => pattern.to_string(),
+    };
+    RegexBuilder::new(&source)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(_) => "That regex is too big.".to_string(),
+            e => format!("That's not a valid regex: {}", e),
+        })
+}
+
+/// Adds a trigger to a guild, returning its id. The id in `trigger` is ignored.
+pub fn add_trigger(conn: &Connection, guild_id: GuildId, trigger: &Trigger, now: i64) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO auto_responses (guild_id, match_kind, pattern, response_kind, response, cooldown, created_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        &[&(guild_id.0 as i64), &trigger.match_kind.name(), &trigger.pattern, &trigger.response_kind.name(),
+          &trigger.response, &trigger.cooldown, &now])?;
+    let id = conn.last_insert_rowid();
+    set_channels(conn, guild_id, id, &trigger.channels)?;
+    Ok(id)
+}
+
+pub fn guild_triggers(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Vec<Trigger>> {
+    let mut channels: HashMap<i64, Vec<ChannelId>> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT c.response_id, c.channel_id FROM auto_response_channels c \
+         JOIN auto_responses r ON r.id = c.response_id WHERE r.guild_id = ?1")?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64)], |row| (row.get(0), row.get(1)))?;
+    for row in rows {
+        let (id, channel_id): (i64, i64) = row?;
+        channels.entry(id).or_insert_with(Vec::new).push(ChannelId(channel_id as u64));
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT id, match_kind, pattern, response_kind, response, cooldown FROM auto_responses \
+         WHERE guild_id = ?1 ORDER BY id")?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64)], |row| {
+        let id: i64 = row.get(0);
+        let match_kind: String = row.get(1);
+        let response_kind: String = row.get(3);
+        Trigger {
+            id: id,
+            match_kind: Match::parse(&match_kind).unwrap_or(Match::Contains),
+            pattern: row.get(2),
+            response_kind: Response::parse(&response_kind).unwrap_or(Response::Text),
+            response: row.get(4),
+            cooldown: row.get(5),
+            channels: channels.remove(&id).unwrap_or_default(),
+        }
+    })?;
+    rows.collect()
+}
+
+pub fn delete_trigger(conn: &Connection, guild_id: GuildId, id: i64) -> rusqlite::Result<bool> {
+    let n = conn.execute("DELETE FROM auto_responses WHERE guild_id = ?1 AND id = ?2", &[&(guild_id.0 as i64), &id])?;
+    if n > 0 {
+        conn.execute("DELETE FROM auto_response_channels WHERE response_id = ?1", &[&id])?;
+    }
+    Ok(n > 0)
+}
+
+pub fn set_cooldown(conn: &Connection, guild_id: GuildId, id: i64, cooldown: i64) -> rusqlite::Result<bool> {
+    let n = conn.execute("UPDATE auto_responses SET cooldown = ?3 WHERE guild_id = ?1 AND id = ?2",
+        &[&(guild_id.0 as i64), &id, &cooldown])?;
+    Ok(n > 0)
+}
+
+/// Restricts a trigger to some channels, or lets it work everywhere if `channels` is empty.
+pub fn set_channels(conn: &Connection, guild_id: GuildId, id: i64, channels: &[ChannelId]) -> rusqlite::Result<bool> {
+    let exists = conn.query_row("SELECT COUNT(*) FROM auto_responses WHERE guild_id = ?1 AND id = ?2",
+        &[&(guild_id.0 as i64), &id], |row| row.get::<_, i64>(0))? > 0;
+    if !exists {
+        return Ok(false);
+    }
+
+    conn.execute("DELETE FROM auto_response_channels WHERE response_id = ?1", &[&id])?;
+    for channel_id in channels {
+        conn.execute("INSERT OR IGNORE INTO auto_response_channels (response_id, channel_id) VALUES (?1, ?2)",
+            &[&id, &(channel_id.0 as i64)])?;
+    }
+    Ok(true)
+}
+
+/// A trigger along with its compiled pattern.
+pub struct Compiled {
+    pub trigger: Trigger,
+    regex: Regex,
+}
+
+impl Compiled {
+    pub fn matches(&self, channel_id: ChannelId, content: &str) -> bool {
+        (self.trigger.channels.is_empty() || self.trigger.channels.contains(&channel_id)) && self.regex.is_match(content)
+    }
+}
+
+/// The compiled triggers of every guild that has sent a message since they were last changed,
+/// and when each trigger last fired.
+pub struct AutoResponses {
+    guilds: RwLock<HashMap<GuildId, Arc<Vec<Compiled>>>>,
+    last_fired: Mutex<HashMap<i64, Instant>>,
+}
+
+impl typemap::Key for AutoResponses {
+    type Value = Arc<AutoResponses>;
+}
+
+impl AutoResponses {
+    pub fn new() -> AutoResponses {
+        AutoResponses {
+            guilds: RwLock::new(HashMap::new()),
+            last_fired: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Gets the compiled triggers of a guild, loading them from the database if they aren't cached.
+    pub fn triggers(&self, conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Arc<Vec<Compiled>>> {
+        if let Some(triggers) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(Arc::clone(triggers));
+        }
+
+        let triggers: Vec<Compiled> = guild_triggers(conn, guild_id)?.into_iter()
+            .filter_map(|trigger| match compile(trigger.match_kind, &trigger.pattern) {
+                Ok(regex) => Some(Compiled { trigger: trigger, regex: regex }),
+                Err(e) => {
+                    warn!("Skipping auto-response {} in guild {}: {}", trigger.id, guild_id, e);
+                    None
+                }
+            })
+            .collect();
+        let triggers = Arc::new(triggers);
+        self.guilds.write().unwrap().insert(guild_id, Arc::clone(&triggers));
+        Ok(triggers)
+    }
+
+    /// Forgets the cached triggers of a guild after they've been changed.
+    pub fn invalidate(&self, guild_id: GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+
+    /// Checks that a trigger isn't cooling down, and starts its cooldown if it isn't.
+    pub fn try_fire(&self, trigger: &Trigger, now: Instant) -> bool {
+        let mut last_fired = self.last_fired.lock().unwrap();
+        if trigger.cooldown > 0 {
+            if let Some(&last) = last_fired.get(&trigger.id) {
+                if now.duration_since(last) < Duration::from_secs(trigger.cooldown as u64) {
+                    return false;
+                }
+            }
+        }
+        last_fired.insert(trigger.id, now);
+        if last_fired.len() > 10_000 {
+            last_fired.retain(|_, &mut last| now.duration_since(last) < Duration::from_secs(24 * 60 * 60));
+        }
+        true
+    }
+}
+
+fn auto_responses(ctx: &Context) -> Option<Arc<AutoResponses>> {
+    let data = ctx.data.lock();
+    data.get::<AutoResponses>().map(Arc::clone)
+}
+
+fn respond(msg: &Message, trigger: &Trigger) -> Result<(), CommandError> {
+    let vars = Variables {
+        user: msg.author.mention(),
+        channel: msg.channel_id.mention(),
+        args: String::new(),
+    };
+    match trigger.response_kind {
+        Response::Text => {
+            msg.channel_id.say(&sanitize_mentions(&render(&trigger.response, &vars, &mut rand::thread_rng())))?;
+        },
+        Response::React => msg.react(parse_reaction(&trigger.response))?,
+        Response::Embed => {
+            let text = render(&trigger.response, &vars, &mut rand::thread_rng());
+            msg.channel_id.send_message(|m| m.embed(|e| e.description(&text).colour(Colour::blurple())))?;
+        },
+    }
+    Ok(())
+}
+
+/// Answers messages that match one of the auto-response triggers of their guild.
+/// Only the first matching trigger fires, and messages that are commands are left alone.
+pub struct AutoResponder;
+
+impl Middleware for AutoResponder {
+    fn before(&self, ctx: &mut Context, msg: &Message, state: &mut MessageState) -> Result<Flow, CommandError> {
+        let guild_id = match msg.guild_id() {
+            Some(id) if state.args.is_none() && !state.from_interaction && !msg.content.is_empty() => id,
+            _ => return Ok(Flow::Continue),
+        };
+        let (responses, db) = match (auto_responses(ctx), db::get(ctx)) {
+            (Some(responses), Some(db)) => (responses, db),
+            _ => return Ok(Flow::Continue),
+        };
+
+        let triggers = responses.triggers(&db.lock().unwrap(), guild_id)?;
+        let now = Instant::now();
+        let matched = triggers.iter().find(|t| t.matches(msg.channel_id, &msg.content));
+        if let Some(compiled) = matched {
+            if responses.try_fire(&compiled.trigger, now) {
+                respond(msg, &compiled.trigger)?;
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Runs a change to the triggers of a guild and drops the cached ones.
+fn change<T, F>(ctx: &Context, guild_id: GuildId, f: F) -> Result<T, CommandError>
+    where F: FnOnce(&Connection) -> rusqlite::Result<T>
+{
+    let result = f(&database(ctx)?.lock().unwrap())?;
+    if let Some(responses) = auto_responses(ctx) {
+        responses.invalidate(guild_id);
+    }
+    Ok(result)
+}
+
+fn describe(trigger: &Trigger) -> String {
+    let mut text = format!("`{}` {} `{}` → {} `{}`", trigger.id, trigger.match_kind.name(), trigger.pattern,
+        trigger.response_kind.name(), trigger.response.replace('\n', " ").chars().take(50).collect::<String>());
+    if trigger.cooldown > 0 {
+        text.push_str(&format!(", cooldown {}", format_duration(Duration::from_secs(trigger.cooldown as u64))));
+    }
+    if !trigger.channels.is_empty() {
+        let channels: Vec<String> = trigger.channels.iter().map(|c| c.mention()).collect();
+        text.push_str(&format!(", only in {}", channels.join(" ")));
+    }
+    text
+}
+
+fn parse_id(msg: &Message, args: &[String], usage: &str) -> Result<Option<i64>, CommandError> {
+    match args.get(1).and_then(|id| id.parse().ok()) {
+        Some(id) => Ok(Some(id)),
+        None => {
+            msg.reply(&format!("Usage: `{} {}`", args[0], usage))?;
+            Ok(None)
+        }
+    }
+}
+
+pub struct AddTrigger;
+
+impl Command for AddTrigger {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let kinds = (args.get(1).and_then(|k| Match::parse(&k.to_lowercase())),
+                     args.get(3).and_then(|k| Response::parse(&k.to_lowercase())));
+        let (match_kind, response_kind) = match kinds {
+            (Some(m), Some(r)) if args.len() > 4 => (m, r),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let pattern = args[2].clone();
+        let response = args[4..].join(" ");
+        if let Err(reason) = compile(match_kind, &pattern) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        if response.chars().count() > MAX_RESPONSE_LEN {
+            msg.reply(&format!("Responses can be at most {} characters long.", MAX_RESPONSE_LEN))?;
+            return Ok(());
+        }
+        if response_kind == Response::React && msg.react(parse_reaction(&response)).is_err() {
+            msg.reply("I can't react with that, reactions have to be a single emoji.")?;
+            return Ok(());
+        }
+
+        let trigger = Trigger {
+            id: 0,
+            match_kind: match_kind,
+            pattern: pattern,
+            response_kind: response_kind,
+            response: response,
+            cooldown: 0,
+            channels: Vec::new(),
+        };
+        let added = change(ctx, guild_id, |conn| {
+            if guild_triggers(conn, guild_id)?.len() >= MAX_TRIGGERS {
+                return Ok(None);
+            }
+            add_trigger(conn, guild_id, &trigger, Utc::now().timestamp()).map(Some)
+        })?;
+        match added {
+            Some(id) => msg.reply(&format!("Added auto-response {}.", id))?,
+            None => msg.reply(&format!("This server already has {} auto-responses, which is as many as it can have.", MAX_TRIGGERS))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Adds an auto-response. Text and embed responses can use {user}, {channel} and {random:a|b|c}."
+    }
+
+    fn usage(&self) -> &str {
+        "<word|contains|regex> <pattern> <text|react|embed> <response>"
+    }
+}
+
+pub struct RemoveTrigger;
+
+impl Command for RemoveTrigger {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let id = match parse_id(msg, args, self.usage())? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        if change(ctx, guild_id, |conn| delete_trigger(conn, guild_id, id))? {
+            msg.reply(&format!("Removed auto-response {}.", id))?;
+        }
+        else {
+            msg.reply(&format!("There's no auto-response {}.", id))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Removes an auto-response."
+    }
+
+    fn usage(&self) -> &str {
+        "<id>"
+    }
+}
+
+pub struct ListTriggers;
+
+impl Command for ListTriggers {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let triggers = guild_triggers(&database(ctx)?.lock().unwrap(), guild_id)?;
+        if triggers.is_empty() {
+            msg.channel_id.say("This server doesn't have any auto-responses.")?;
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        for (i, trigger) in triggers.iter().enumerate() {
+            let line = sanitize_mentions(&describe(trigger));
+            if i > 0 {
+                text.push('\n');
+            }
+            if text.len() + line.len() > MAX_MESSAGE_LEN {
+                text.push_str(&format!("…and {} more", triggers.len() - i));
+                break;
+            }
+            text.push_str(&line);
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the auto-responses in this server."
+    }
+}
+
+pub struct SetCooldown;
+
+impl Command for SetCooldown {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let id = match parse_id(msg, args, self.usage())? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let cooldown = match args.get(2).map(|c| c.to_lowercase()) {
+            Some(ref c) if c == "off" => Some(0),
+            Some(c) => c.parse::<i64>().ok().filter(|&c| c >= 0).or_else(|| parse_period(&c)),
+            None => None,
+        };
+        let cooldown = match cooldown {
+            Some(cooldown) => cooldown,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        if !change(ctx, guild_id, |conn| set_cooldown(conn, guild_id, id, cooldown))? {
+            msg.reply(&format!("There's no auto-response {}.", id))?;
+        }
+        else if cooldown == 0 {
+            msg.reply(&format!("Auto-response {} no longer has a cooldown.", id))?;
+        }
+        else {
+            msg.reply(&format!("Auto-response {} now has a cooldown of {}.", id, format_duration(Duration::from_secs(cooldown as u64))))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets how long an auto-response waits before it can fire again."
+    }
+
+    fn usage(&self) -> &str {
+        "<id> <seconds, a period like 5m, or off>"
+    }
+}
+
+pub struct SetChannels;
+
+impl Command for SetChannels {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let id = match parse_id(msg, args, self.usage())? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let channels: Option<Vec<ChannelId>> = args[2..].iter().map(|c| parse_channel(c).map(ChannelId)).collect();
+        let channels = match channels {
+            Some(channels) => channels,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        if !change(ctx, guild_id, |conn| set_channels(conn, guild_id, id, &channels))? {
+            msg.reply(&format!("There's no auto-response {}.", id))?;
+        }
+        else if channels.is_empty() {
+            msg.reply(&format!("Auto-response {} now works in every channel.", id))?;
+        }
+        else {
+            let mentions: Vec<String> = channels.iter().map(|c| c.mention()).collect();
+            msg.reply(&format!("Auto-response {} now only works in {}.", id, mentions.join(" ")))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Restricts an auto-response to some channels. Without any channels it works everywhere."
+    }
+
+    fn usage(&self) -> &str {
+        "<id> [#channel...]"
+    }
+}
+
+pub fn autorespond_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Manages the messages the bot answers automatically in this server.");
+    group.add_command("add", AddTrigger);
+    group.add_command("remove", RemoveTrigger);
+    group.add_command("list", ListTriggers);
+    group.add_command("cooldown", SetCooldown);
+    group.add_command("channels", SetChannels);
+    group.set_default("list");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_check(guild_admin_only);
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn trigger(match_kind: Match, pattern: &str) -> Trigger {
+        Trigger {
+            id: 0,
+            match_kind: match_kind,
+            pattern: pattern.to_string(),
+            response_kind: Response::Text,
+            response: "hi".to_string(),
+            cooldown: 0,
+            channels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn compile_patterns() {
+        let word = compile(Match::Word, "hello").unwrap();
+        assert!(word.is_match("Hello there"));
+        assert!(word.is_match("well, hello!"));
+        assert!(!word.is_match("helloooo"));
+
+        let contains = compile(Match::Contains, "a.b").unwrap();
+        assert!(contains.is_match("xxA.Bxx"));
+        assert!(!contains.is_match("axb"));
+
+        assert!(compile(Match::Regex, "^h(i|ey)$").unwrap().is_match("hey"));
+        assert!(compile(Match::Regex, "(").is_err());
+        assert!(compile(Match::Regex, r"\w{1000}\w{1000}").is_err());
+        assert!(compile(Match::Regex, &"a".repeat(MAX_PATTERN_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn store_triggers() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        let mut t = trigger(Match::Word, "hello");
+        t.channels = vec![ChannelId(5)];
+        let first = add_trigger(&conn, guild, &t, 100).unwrap();
+        let second = add_trigger(&conn, guild, &trigger(Match::Regex, "bye"), 100).unwrap();
+        add_trigger(&conn, GuildId(2), &trigger(Match::Contains, "x"), 100).unwrap();
+
+        assert!(set_cooldown(&conn, guild, second, 60).unwrap());
+        assert!(!set_cooldown(&conn, GuildId(2), second, 60).unwrap());
+        let triggers = guild_triggers(&conn, guild).unwrap();
+        assert_eq!(triggers.len(), 2);
+        assert_eq!(triggers[0].channels, vec![ChannelId(5)]);
+        assert_eq!(triggers[1].match_kind, Match::Regex);
+        assert_eq!(triggers[1].cooldown, 60);
+
+        assert!(set_channels(&conn, guild, first, &[]).unwrap());
+        assert!(guild_triggers(&conn, guild).unwrap()[0].channels.is_empty());
+        assert!(delete_trigger(&conn, guild, first).unwrap());
+        assert!(!delete_trigger(&conn, guild, first).unwrap());
+    }
+
+    #[test]
+    fn triggers_cool_down() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        let mut t = trigger(Match::Word, "hello");
+        t.channels = vec![ChannelId(5)];
+        t.cooldown = 60;
+        add_trigger(&conn, guild, &t, 100).unwrap();
+
+        let responses = AutoResponses::new();
+        let triggers = responses.triggers(&conn, guild).unwrap();
+        assert!(triggers[0].matches(ChannelId(5), "oh hello"));
+        assert!(!triggers[0].matches(ChannelId(6), "oh hello"));
+
+        let now = Instant::now();
+        assert!(responses.try_fire(&triggers[0].trigger, now));
+        assert!(!responses.try_fire(&triggers[0].trigger, now + Duration::from_secs(30)));
+        assert!(responses.try_fire(&triggers[0].trigger, now + Duration::from_secs(61)));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,308 @@
+use commands::{Command, CommandResult, database, guild_only, user_name};
+use commands::owner::format_duration;
+
+use chrono::Utc;
+use rusqlite;
+use rusqlite::{Connection, TransactionBehavior};
+
+use serenity;
+use serenity::client::Context;
+use serenity::http::HttpError;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::utils::parse_username;
+
+use std::time::Duration;
+
+/// What the currency is called in messages.
+pub const CURRENCY: &str = "coins";
+
+/// How much `daily` gives, and how long to wait between claims.
+const DAILY_AMOUNT: i64 = 100;
+const DAILY_COOLDOWN: i64 = 24 * 60 * 60;
+
+/// The most that can be bet or given at once, which keeps balances far away from overflowing.
+pub const MAX_AMOUNT: i64 = 1_000_000_000;
+
+pub fn balance(conn: &Connection, guild_id: GuildId, user_id: UserId) -> rusqlite::Result<i64> {
+    let mut stmt = conn.prepare("SELECT balance FROM balances WHERE guild_id = ?1 AND user_id = ?2")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+    match rows.next() {
+        Some(row) => Ok(row?.get(0)),
+        None => Ok(0),
+    }
+}
+
+fn credit(conn: &Connection, guild_id: GuildId, user_id: UserId, amount: i64) -> rusqlite::Result<()> {
+    conn.execute("INSERT OR IGNORE INTO balances (guild_id, user_id) VALUES (?1, ?2)", &[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+    conn.execute("UPDATE balances SET balance = balance + ?3 WHERE guild_id = ?1 AND user_id = ?2",
+        &[&(guild_id.0 as i64), &(user_id.0 as i64), &amount])?;
+    Ok(())
+}
+
+/// Takes `amount` from a balance, returning false and changing nothing if the balance is too low.
+fn debit(conn: &Connection, guild_id: GuildId, user_id: UserId, amount: i64) -> rusqlite::Result<bool> {
+    let n = conn.execute("UPDATE balances SET balance = balance - ?3 WHERE guild_id = ?1 AND user_id = ?2 AND balance >= ?3",
+        &[&(guild_id.0 as i64), &(user_id.0 as i64), &amount])?;
+    Ok(n > 0)
+}
+
+/// Gives the daily amount, or returns how many seconds are left until it can be claimed again.
+pub fn claim_daily(conn: &mut Connection, guild_id: GuildId, user_id: UserId, now: i64) -> rusqlite::Result<Result<i64, i64>> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let last: Option<i64> = {
+        let mut stmt = tx.prepare("SELECT last_daily FROM balances WHERE guild_id = ?1 AND user_id = ?2")?;
+        let mut rows = stmt.query(&[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+        match rows.next() {
+            Some(row) => row?.get(0),
+            None => None,
+        }
+    };
+    if let Some(last) = last {
+        if now - last < DAILY_COOLDOWN {
+            return Ok(Err(last + DAILY_COOLDOWN - now));
+        }
+    }
+
+    credit(&tx, guild_id, user_id, DAILY_AMOUNT)?;
+    tx.execute("UPDATE balances SET last_daily = ?3 WHERE guild_id = ?1 AND user_id = ?2",
+        &[&(guild_id.0 as i64), &(user_id.0 as i64), &now])?;
+    let balance = balance(&tx, guild_id, user_id)?;
+    tx.commit()?;
+    Ok(Ok(balance))
+}
+
+/// Moves coins between users, returning false if the sender doesn't have enough.
+pub fn transfer(conn: &mut Connection, guild_id: GuildId, from: UserId, to: UserId, amount: i64) -> rusqlite::Result<bool> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if !debit(&tx, guild_id, from, amount)? {
+        return Ok(false);
+    }
+    credit(&tx, guild_id, to, amount)?;
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Takes a bet, plays the game and pays out what it returns, all at once so a balance can't be bet twice.
+/// Returns the outcome of the game and the new balance, or `None` if the balance is too low for the bet.
+/// `play` runs while the database is locked, so it shouldn't do anything that can fail or take long.
+pub fn wager<T, F>(conn: &mut Connection, guild_id: GuildId, user_id: UserId, bet: i64, play: F) -> rusqlite::Result<Option<(T, i64)>>
+    where F: FnOnce() -> (T, i64)
+{
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if !debit(&tx, guild_id, user_id, bet)? {
+        return Ok(None);
+    }
+    let (outcome, payout) = play();
+    if payout > 0 {
+        credit(&tx, guild_id, user_id, payout)?;
+    }
+    let balance = balance(&tx, guild_id, user_id)?;
+    tx.commit()?;
+    Ok(Some((outcome, balance)))
+}
+
+/// Reads an amount of coins, returning the reason if it's not one that can be used.
+pub fn parse_amount(text: &str) -> Result<i64, String> {
+    match text.parse::<i64>() {
+        Ok(amount) if amount > 0 && amount <= MAX_AMOUNT => Ok(amount),
+        Ok(amount) if amount > MAX_AMOUNT => Err(format!("That's more than the limit of {} {}.", MAX_AMOUNT, CURRENCY)),
+        _ => Err(format!("'{}' isn't a positive amount of {}.", text, CURRENCY)),
+    }
+}
+
+/// Whether a member of a guild is a bot, or `None` if they aren't a member.
+fn is_bot_member(guild_id: GuildId, user_id: UserId) -> Result<Option<bool>, serenity::Error> {
+    let cached = guild_id.find()
+        .and_then(|guild| guild.read().members.get(&user_id).map(|member| member.user.read().bot));
+    if cached.is_some() {
+        return Ok(cached);
+    }
+    match guild_id.member(user_id) {
+        Ok(member) => Ok(Some(member.user.read().bot)),
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res))) if res.status.is_client_error() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub struct DailyCommand;
+
+impl Command for DailyCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let db = database(ctx)?;
+        let claimed = claim_daily(&mut db.lock().unwrap(), msg.guild_id().unwrap(), msg.author.id, Utc::now().timestamp())?;
+        match claimed {
+            Ok(balance) => msg.reply(&format!("You got {} {}, and now have {}.", DAILY_AMOUNT, CURRENCY, balance))?,
+            Err(left) => msg.reply(&format!("You've already claimed today. Come back in {}.", format_duration(Duration::from_secs(left as u64))))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Gives you some coins once a day."
+    }
+
+    fn category(&self) -> &str {
+        "economy"
+    }
+}
+
+pub struct BalanceCommand;
+
+impl Command for BalanceCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let user_id = match args.get(1) {
+            Some(arg) => match parse_username(arg) {
+                Some(id) => UserId(id),
+                None => {
+                    msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                    return Ok(());
+                }
+            },
+            None => msg.author.id,
+        };
+
+        let balance = balance(&database(ctx)?.lock().unwrap(), msg.guild_id().unwrap(), user_id)?;
+        if user_id == msg.author.id {
+            msg.reply(&format!("You have {} {}.", balance, CURRENCY))?;
+        }
+        else {
+            msg.reply(&format!("{} has {} {}.", user_name(user_id), balance, CURRENCY))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows how many coins you or another user have in this server."
+    }
+
+    fn usage(&self) -> &str {
+        "[user]"
+    }
+
+    fn category(&self) -> &str {
+        "economy"
+    }
+}
+
+pub struct GiveCommand;
+
+impl Command for GiveCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let (target, amount) = match (args.get(1).and_then(|a| parse_username(a)), args.get(2)) {
+            (Some(target), Some(amount)) => (UserId(target), amount),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+        let amount = match parse_amount(amount) {
+            Ok(amount) => amount,
+            Err(reason) => {
+                msg.reply(&reason)?;
+                return Ok(());
+            }
+        };
+        if target == msg.author.id {
+            msg.reply("You can't give coins to yourself.")?;
+            return Ok(());
+        }
+        let guild_id = msg.guild_id().unwrap();
+        match is_bot_member(guild_id, target)? {
+            Some(false) => {},
+            Some(true) => {
+                msg.reply("Bots don't need coins.")?;
+                return Ok(());
+            },
+            None => {
+                msg.reply(&format!("{} isn't in this server.", user_name(target)))?;
+                return Ok(());
+            },
+        }
+
+        let db = database(ctx)?;
+        if transfer(&mut db.lock().unwrap(), guild_id, msg.author.id, target, amount)? {
+            msg.reply(&format!("You gave {} {} to {}.", amount, CURRENCY, user_name(target)))?;
+        }
+        else {
+            msg.reply(&format!("You don't have {} {}.", amount, CURRENCY))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Gives some of your coins to another user."
+    }
+
+    fn usage(&self) -> &str {
+        "<user> <amount>"
+    }
+
+    fn category(&self) -> &str {
+        "economy"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    #[test]
+    fn daily_has_a_cooldown() {
+        let mut conn = db::open(":memory:").unwrap();
+        let (guild, user) = (GuildId(1), UserId(2));
+        assert_eq!(claim_daily(&mut conn, guild, user, 1000).unwrap(), Ok(DAILY_AMOUNT));
+        assert_eq!(claim_daily(&mut conn, guild, user, 1000 + 60).unwrap(), Err(DAILY_COOLDOWN - 60));
+        assert_eq!(claim_daily(&mut conn, guild, user, 1000 + DAILY_COOLDOWN).unwrap(), Ok(2 * DAILY_AMOUNT));
+        assert_eq!(claim_daily(&mut conn, GuildId(3), user, 1000).unwrap(), Ok(DAILY_AMOUNT));
+    }
+
+    #[test]
+    fn transfers_need_enough_coins() {
+        let mut conn = db::open(":memory:").unwrap();
+        let (guild, a, b) = (GuildId(1), UserId(2), UserId(3));
+        claim_daily(&mut conn, guild, a, 0).unwrap().unwrap();
+        assert!(!transfer(&mut conn, guild, a, b, DAILY_AMOUNT + 1).unwrap());
+        assert!(!transfer(&mut conn, guild, b, a, 1).unwrap());
+        assert!(transfer(&mut conn, guild, a, b, 40).unwrap());
+        assert_eq!(balance(&conn, guild, a).unwrap(), DAILY_AMOUNT - 40);
+        assert_eq!(balance(&conn, guild, b).unwrap(), 40);
+    }
+
+    #[test]
+    fn wagers_pay_out_once() {
+        let mut conn = db::open(":memory:").unwrap();
+        let (guild, user) = (GuildId(1), UserId(2));
+        claim_daily(&mut conn, guild, user, 0).unwrap().unwrap();
+
+        assert_eq!(wager(&mut conn, guild, user, 60, || ("won", 120)).unwrap(), Some(("won", DAILY_AMOUNT + 60)));
+        assert_eq!(wager(&mut conn, guild, user, 160, || ("lost", 0)).unwrap(), Some(("lost", 0)));
+        let mut played = false;
+        assert_eq!(wager(&mut conn, guild, user, 1, || { played = true; ((), 2) }).unwrap(), None);
+        assert!(!played);
+        assert_eq!(balance(&conn, guild, user).unwrap(), 0);
+    }
+
+    #[test]
+    fn parse_amounts() {
+        assert_eq!(parse_amount("50"), Ok(50));
+        assert!(parse_amount("0").is_err());
+        assert!(parse_amount("-5").is_err());
+        assert!(parse_amount("lots").is_err());
+        assert!(parse_amount(&(MAX_AMOUNT + 1).to_string()).is_err());
+    }
+}
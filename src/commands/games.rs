@@ -1,6 +1,9 @@
 use commands::Command;
 use commands::CommandError;
 use commands::CommandResult;
+use commands::guild_only;
+use commands::database;
+use commands::economy::{CURRENCY, parse_amount, wager};
 use serenity::client::Context;
 use serenity::model::channel::Message;
 use serenity::model::channel::Channel;
@@ -28,61 +31,155 @@ impl DiceRoll {
 
 const MAX_DICE: i32 = 12;
 
+struct Dice {
+    num: i32,
+    sides: i32,
+    add: i32,
+}
+
+impl Dice {
+    /// Rolls the dice, returning the total and the individual throws.
+    fn roll<R: Rng>(&self, rng: &mut R) -> (i32, String) {
+        let mut throws = String::new();
+        let sum = self.add + if self.num > MAX_DICE {
+            throws = "too many dice to list".to_string();
+            Range::new(self.num, self.sides*self.num + 1).ind_sample(rng)
+        }
+        else {
+            let range = Range::new(1, 1+self.sides);
+            let mut sum = 0;
+            for i in 0..self.num {
+                if i != 0 {
+                    throws.push_str(", ");
+                }
+                let throw = range.ind_sample(rng);
+                throws.push_str(&throw.to_string());
+                sum += throw;
+            }
+            sum
+        };
+        (sum, throws)
+    }
+
+    fn show(&self, (sum, throws): &(i32, String)) -> String {
+        if self.num > 1 {
+            format!("{} \n[ {} ]", sum, throws)
+        }
+        else {
+            format!("{}", sum)
+        }
+    }
+}
+
+impl DiceRoll {
+    /// Reads a dice spec, leaving out ones that can't be rolled or whose total wouldn't fit.
+    fn parse(&self, text: &str) -> Option<Dice> {
+        let cap = self.dice_re.captures(text)?;
+
+        let num = match cap.get(1) {
+            Some(m) => m.as_str().parse::<i32>().ok()?,
+            None => 1,
+        };
+
+        let sides = match cap.get(2) {
+            Some(m) => m.as_str().parse::<i32>().ok()?,
+            None => 6,
+        };
+
+        let add = match cap.get(3) {
+            Some(m) => m.as_str().parse::<i32>().ok()?,
+            None => 0,
+        };
+
+        if num < 1 || sides < 2 {
+            return None;
+        }
+        num.checked_mul(sides)?.checked_add(1)?.checked_add(add)?;
+        num.checked_add(add)?;
+        Some(Dice { num, sides, add })
+    }
+}
+
+/// Reads a bet, replying with the reason and returning `None` if it can't be used.
+fn parse_bet(msg: &Message, text: &str) -> Result<Option<i64>, CommandError> {
+    match parse_amount(text) {
+        Ok(bet) => Ok(Some(bet)),
+        Err(reason) => {
+            msg.reply(&reason)?;
+            Ok(None)
+        }
+    }
+}
+
+fn not_enough(msg: &Message, bet: i64) -> CommandResult {
+    msg.reply(&format!("You don't have {} {} to bet.", bet, CURRENCY))?;
+    Ok(())
+}
+
 impl Command for DiceRoll {
-    fn execute(&self, _ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
         if args.len() < 2 { 
             return Err(CommandError::Argument(format!("Invalid number of arguments (expected 2, got {})", args.len())));
         }
 
-        if let Some(cap) = self.dice_re.captures(&args[1]) {
-            let mut rng = rand::thread_rng();
-
-            let num = match cap.get(1) {
-                Some(m) => m.as_str().parse::<i32>(),
-                None => Ok(1),
-            }.unwrap_or(1);
-
-            let sides = match cap.get(2) {
-                Some(m) => m.as_str().parse::<i32>(),
-                None => Ok(6),
-            }.unwrap_or(6);
-            
-            let add = match cap.get(3) {
-                Some(m) => m.as_str().parse::<i32>(),
-                None => Ok(0),
-            }.unwrap_or(0);
-
-            let mut throws = String::new();
-            let sum = add + if num > MAX_DICE {
-                throws = "too many dice to list".to_string();
-                Range::new(num, sides*num).ind_sample(&mut rng)
+        let dice = match self.parse(&args[1]) {
+            Some(dice) => dice,
+            None => return Err(CommandError::Argument(format!("Invalid dice syntax: {}", args[1]))),
+        };
+
+        let bet = match args.get(2) {
+            Some(text) => match parse_bet(msg, text)? {
+                Some(bet) => bet,
+                None => return Ok(()),
+            },
+            None => {
+                msg.reply(&dice.show(&dice.roll(&mut rand::thread_rng())))?;
+                return Ok(());
             }
-            else {
-                let range = Range::new(1, 1+sides);
-                let mut sum = 0;
-                for i in 0..num {
-                    if i != 0 {
-                        throws.push_str(", ");
-                    }
-                    let throw = range.ind_sample(&mut rng);
-                    throws.push_str(&throw.to_string());
-                    sum += throw;
+        };
+
+        // Betting on a roll means trying to beat the bot rolling the same dice.
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        // Anything that could go wrong happens before the database is locked.
+        let mut rng = rand::thread_rng();
+        let (yours, mine) = (dice.roll(&mut rng), dice.roll(&mut rng));
+        let payout = if yours.0 > mine.0 { bet * 2 } else if yours.0 == mine.0 { bet } else { 0 };
+        let db = database(ctx)?;
+        let played = wager(&mut db.lock().unwrap(), msg.guild_id().unwrap(), msg.author.id, bet, || ((yours, mine), payout))?;
+
+        match played {
+            Some(((yours, mine), balance)) => {
+                let outcome = if yours.0 > mine.0 {
+                    format!("You win {} {}!", bet, CURRENCY)
                 }
-                sum
-            };
-            
-            if num > 1 {
-                msg.reply(&format!("{} \n[ {} ]", sum, throws))?;
-            }
-            else {
-                msg.reply(&format!("{}", sum))?;
+                else if yours.0 == mine.0 {
+                    "It's a tie, you get your bet back.".to_string()
+                }
+                else {
+                    format!("You lose {} {}.", bet, CURRENCY)
+                };
+                msg.reply(&format!("You rolled {}\nI rolled {}\n{} You now have {} {}.",
+                    dice.show(&yours), dice.show(&mine), outcome, balance, CURRENCY))?;
+                Ok(())
             }
-            Ok(())
-        }
-        else {
-            Err(CommandError::Argument(format!("Invalid dice syntax: {}", args[1])))
+            None => not_enough(msg, bet),
         }
     }
+
+    fn description(&self) -> &str {
+        "Rolls some dice, e.g. 2d6+3."
+    }
+
+    fn usage(&self) -> &str {
+        "[count]d<sides>[+/-modifier] [bet]"
+    }
+
+    fn category(&self) -> &str {
+        "games"
+    }
 }
 
 
@@ -144,4 +241,188 @@ impl Command for Roulette {
 
         Ok(())
     }
+
+    fn description(&self) -> &str {
+        "Picks a random online member that can see the channel."
+    }
+
+    fn category(&self) -> &str {
+        "games"
+    }
+}
+
+
+pub struct CoinFlip;
+
+impl Command for CoinFlip {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let heads = match args.get(2).map(|s| s.to_lowercase()) {
+            None => true,
+            Some(ref side) if side == "heads" || side == "h" => true,
+            Some(ref side) if side == "tails" || side == "t" => false,
+            Some(_) => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+        let bet = match args.get(1) {
+            Some(text) => match parse_bet(msg, text)? {
+                Some(bet) => bet,
+                None => return Ok(()),
+            },
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let landed_heads = rand::thread_rng().gen::<bool>();
+        let payout = if landed_heads == heads { bet * 2 } else { 0 };
+        let db = database(ctx)?;
+        let played = wager(&mut db.lock().unwrap(), msg.guild_id().unwrap(), msg.author.id, bet, || (landed_heads, payout))?;
+
+        match played {
+            Some((landed_heads, balance)) => {
+                let side = if landed_heads { "heads" } else { "tails" };
+                if landed_heads == heads {
+                    msg.reply(&format!("It's {}! You win {} {} and now have {}.", side, bet, CURRENCY, balance))?;
+                }
+                else {
+                    msg.reply(&format!("It's {}. You lose {} {} and now have {}.", side, bet, CURRENCY, balance))?;
+                }
+                Ok(())
+            }
+            None => not_enough(msg, bet),
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Bets coins on a coin flip, paying double if you call it."
+    }
+
+    fn usage(&self) -> &str {
+        "<bet> [heads|tails]"
+    }
+
+    fn category(&self) -> &str {
+        "games"
+    }
+}
+
+
+const REELS: [&str; 6] = ["🍒", "🍋", "🍊", "🍇", "🔔", "💎"];
+
+/// How much a spin pays back: three of a kind multiplies the bet, a pair returns it.
+fn slots_payout(spin: &[&str; 3], bet: i64) -> i64 {
+    if spin[0] == spin[1] && spin[1] == spin[2] {
+        bet * match spin[0] {
+            "💎" => 30,
+            "🔔" => 15,
+            _ => 8,
+        }
+    }
+    else if spin[0] == spin[1] || spin[1] == spin[2] || spin[0] == spin[2] {
+        bet
+    }
+    else {
+        0
+    }
+}
+
+pub struct Slots;
+
+impl Command for Slots {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let bet = match args.get(1) {
+            Some(text) => match parse_bet(msg, text)? {
+                Some(bet) => bet,
+                None => return Ok(()),
+            },
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let mut rng = rand::thread_rng();
+        let spin = [*rng.choose(&REELS).unwrap(), *rng.choose(&REELS).unwrap(), *rng.choose(&REELS).unwrap()];
+        let payout = slots_payout(&spin, bet);
+        let db = database(ctx)?;
+        let played = wager(&mut db.lock().unwrap(), msg.guild_id().unwrap(), msg.author.id, bet, || ((spin, payout), payout))?;
+
+        match played {
+            Some(((spin, payout), balance)) => {
+                let outcome = if payout > bet {
+                    format!("You win {} {}!", payout - bet, CURRENCY)
+                }
+                else if payout == bet {
+                    "A pair, you get your bet back.".to_string()
+                }
+                else {
+                    format!("You lose {} {}.", bet, CURRENCY)
+                };
+                msg.reply(&format!("[ {} ]\n{} You now have {} {}.", spin.join(" "), outcome, balance, CURRENCY))?;
+                Ok(())
+            }
+            None => not_enough(msg, bet),
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Bets coins on a slot machine. Three of a kind pays 8x, or more for bells and diamonds."
+    }
+
+    fn usage(&self) -> &str {
+        "<bet>"
+    }
+
+    fn category(&self) -> &str {
+        "games"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_pay_out() {
+        assert_eq!(slots_payout(&["💎", "💎", "💎"], 10), 300);
+        assert_eq!(slots_payout(&["🔔", "🔔", "🔔"], 10), 150);
+        assert_eq!(slots_payout(&["🍒", "🍒", "🍒"], 10), 80);
+        assert_eq!(slots_payout(&["🍒", "🍋", "🍒"], 10), 10);
+        assert_eq!(slots_payout(&["🍒", "🍋", "🍊"], 10), 0);
+    }
+
+    #[test]
+    fn parse_dice() {
+        let roll = DiceRoll::new();
+        let dice = roll.parse("2d6+3").unwrap();
+        assert_eq!((dice.num, dice.sides, dice.add), (2, 6, 3));
+        assert!(roll.parse("d20").is_some());
+        assert!(roll.parse("twenty").is_none());
+        assert!(roll.parse("d0").is_none());
+        assert!(roll.parse("0d6").is_none());
+        assert!(roll.parse("20d1").is_none());
+        assert!(roll.parse("13d2147483647").is_none());
+        assert!(roll.parse("99999999999d6").is_none());
+
+        let many = roll.parse("20d2").unwrap();
+        for _ in 0..100 {
+            let (sum, _) = many.roll(&mut rand::thread_rng());
+            assert!(sum >= 20 && sum <= 40);
+        }
+
+        let (sum, throws) = dice.roll(&mut rand::thread_rng());
+        assert!(sum >= 5 && sum <= 15);
+        assert_eq!(throws.split(", ").count(), 2);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use commands::{Command, CommandMap, CommandResult, Check};
+use commands::help;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+
+use std::sync::Arc;
+
+/// A command that routes its first argument to one of its child commands.
+pub struct CommandGroup {
+    description: String,
+    category: String,
+    commands: CommandMap,
+    default: Option<String>,
+    checks: Vec<Check>,
+}
+
+impl CommandGroup {
+    pub fn new(description: &str) -> CommandGroup {
+        CommandGroup {
+            description: description.to_string(),
+            category: "general".to_string(),
+            commands: CommandMap::new(),
+            default: None,
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn add_command<T: Command>(&mut self, name: &str, command: T) {
+        self.commands.insert(name.to_string(), Arc::new(command));
+    }
+
+    /// Sets the subcommand to run when the group is called without a subcommand.
+    /// If the default has a usage, it also gets any arguments that aren't a subcommand name.
+    pub fn set_default(&mut self, name: &str) {
+        self.default = Some(name.to_string());
+    }
+
+    pub fn set_category(&mut self, category: &str) {
+        self.category = category.to_string();
+    }
+
+    /// Adds a check that has to pass for every subcommand in the group.
+    pub fn add_check(&mut self, check: Check) {
+        self.checks.push(check);
+    }
+}
+
+impl Command for CommandGroup {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        for check in self.checks.iter() {
+            if let Err(reason) = check(ctx, msg) {
+                msg.reply(&reason)?;
+                return Ok(());
+            }
+        }
+
+        if let Some(command) = args.get(1).and_then(|name| self.commands.get(name)) {
+            return command.execute(ctx, msg, &args[1..].to_vec());
+        }
+
+        // Extra arguments only go to the default if it says it takes any,
+        // otherwise they're most likely a misspelled subcommand.
+        let default = self.default.as_ref()
+            .and_then(|name| self.commands.get(name))
+            .filter(|command| args.len() < 2 || !command.usage().is_empty());
+        if let Some(command) = default {
+            let mut sub_args = vec![self.default.clone().unwrap()];
+            sub_args.extend_from_slice(&args[1..]);
+            return command.execute(ctx, msg, &sub_args);
+        }
+
+        let mut text = String::new();
+        if let Some(name) = args.get(1) {
+            text.push_str(&format!("Unknown subcommand '{}'.\n", name));
+        }
+        text.push_str(&help::command_help(&args[0], self));
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn usage(&self) -> &str {
+        "<subcommand>"
+    }
+
+    fn subcommands(&self) -> Option<&CommandMap> {
+        Some(&self.commands)
+    }
+
+    fn category(&self) -> &str {
+        &self.category
+    }
+}
@@ -0,0 +1,165 @@
+use commands::{Command, CommandMap, CommandResult};
+use framework::CommandRegistry;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+
+use std::sync::Arc;
+
+const MAX_MESSAGE_LEN: usize = 1900;
+
+fn help_line(name: &str, command: &Command) -> String {
+    let mut line = if command.usage().is_empty() {
+        format!("`{}`", name)
+    }
+    else {
+        format!("`{} {}`", name, command.usage())
+    };
+    if !command.description().is_empty() {
+        line.push_str(" - ");
+        line.push_str(command.description());
+    }
+    line
+}
+
+fn sorted_names(commands: &CommandMap) -> Vec<&String> {
+    let mut names: Vec<&String> = commands.keys().collect();
+    names.sort();
+    names
+}
+
+/// Joins lines into as few messages as possible while keeping each under the message length limit.
+fn split_messages(lines: &[String]) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut text = String::new();
+    for line in lines {
+        if !text.is_empty() && text.len() + 1 + line.len() > MAX_MESSAGE_LEN {
+            messages.push(text);
+            text = String::new();
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(line);
+    }
+    if !text.is_empty() {
+        messages.push(text);
+    }
+    messages
+}
+
+/// Formats the help text for a command, listing its subcommands if it's a group.
+pub fn command_help(name: &str, command: &Command) -> String {
+    let mut text = help_line(name, command);
+    if let Some(subcommands) = command.subcommands() {
+        text.push_str("\nSubcommands:");
+        for sub_name in sorted_names(subcommands) {
+            text.push_str("\n    ");
+            text.push_str(&help_line(&format!("{} {}", name, sub_name), &*subcommands[sub_name]));
+        }
+    }
+    text
+}
+
+pub struct HelpCommand {
+    prefix: String,
+}
+
+impl HelpCommand {
+    pub fn new(prefix: &str) -> HelpCommand {
+        HelpCommand {
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl Command for HelpCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let registry = {
+            let data = ctx.data.lock();
+            match data.get::<CommandRegistry>() {
+                Some(registry) => Arc::clone(registry),
+                None => return Ok(()),
+            }
+        };
+        let commands = registry.read().unwrap();
+
+        if args.len() < 2 {
+            let mut lines = vec!["Commands:".to_string()];
+            for name in sorted_names(&commands) {
+                lines.push(format!("    {}", help_line(&format!("{}{}", self.prefix, name), &*commands[name])));
+            }
+            lines.push(format!("Use `{}help <command>` for more info about a command.", self.prefix));
+            for text in split_messages(&lines) {
+                msg.channel_id.say(&text)?;
+            }
+            return Ok(());
+        }
+
+        let mut command: Option<&Command> = commands.get(&args[1]).map(|c| &**c);
+        for name in args[2..].iter() {
+            command = command
+                .and_then(|c| c.subcommands())
+                .and_then(|s| s.get(name))
+                .map(|c| &**c);
+        }
+
+        if let Some(command) = command {
+            msg.channel_id.say(&command_help(&format!("{}{}", self.prefix, args[1..].join(" ")), command))?;
+        }
+        else {
+            msg.reply(&format!("No such command: {}", args[1..].join(" ")))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the available commands, or shows help for a specific command."
+    }
+
+    fn usage(&self) -> &str {
+        "[command] [subcommand...]"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commands::group::CommandGroup;
+
+    struct Dummy;
+
+    impl Command for Dummy {
+        fn execute(&self, _ctx: &mut Context, _msg: &Message, _args: &Vec<String>) -> CommandResult {
+            Ok(())
+        }
+
+        fn description(&self) -> &str {
+            "Does nothing."
+        }
+    }
+
+    #[test]
+    fn command_help_single() {
+        assert_eq!(command_help("^dummy", &Dummy), "`^dummy` - Does nothing.");
+    }
+
+    #[test]
+    fn command_help_group() {
+        let mut group = CommandGroup::new("A group.");
+        group.add_command("b", Dummy);
+        group.add_command("a", Dummy);
+        assert_eq!(command_help("^g", &group),
+            "`^g <subcommand>` - A group.\nSubcommands:\n    `^g a` - Does nothing.\n    `^g b` - Does nothing.");
+    }
+
+    #[test]
+    fn split_long_help() {
+        let lines: Vec<String> = (0..100).map(|i| format!("{:02} {}", i, "x".repeat(47))).collect();
+        let messages = split_messages(&lines);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|m| m.len() <= MAX_MESSAGE_LEN));
+        assert_eq!(messages.join("\n"), lines.join("\n"));
+        assert_eq!(split_messages(&["a".to_string(), "b".to_string()]), vec!["a\nb".to_string()]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,603 @@
+use commands::{Command, CommandError, CommandResult, Middleware, MessageState, Flow, database, user_name};
+use commands::{guild_only, guild_admin_only};
+use commands::group::CommandGroup;
+use commands::polls::bar;
+use commands::reactionroles::{can_manage, find_assignable_role};
+use db;
+
+use chrono::Utc;
+use rand::{self, Rng};
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::http;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::{Colour, parse_channel, parse_username};
+
+
+/// Messages only give XP once per this many seconds, so spamming doesn't pay off.
+const XP_COOLDOWN: i64 = 60;
+
+/// The XP a message gives is picked at random from this range.
+const MIN_MESSAGE_XP: i64 = 15;
+const MAX_MESSAGE_XP: i64 = 25;
+
+/// How many users are shown on a page of the leaderboard.
+const PAGE_SIZE: i64 = 10;
+
+/// The XP needed to get from `level` to the next one.
+pub fn xp_for_next(level: i64) -> i64 {
+    5 * level * level + 50 * level + 100
+}
+
+/// Works out the level for an amount of XP, along with the XP into that level and the XP the level takes.
+pub fn level_for_xp(xp: i64) -> (i64, i64, i64) {
+    let mut level = 0;
+    let mut rest = xp.max(0);
+    while rest >= xp_for_next(level) {
+        rest -= xp_for_next(level);
+        level += 1;
+    }
+    (level, rest, xp_for_next(level))
+}
+
+pub fn get_xp(conn: &Connection, guild_id: GuildId, user_id: UserId) -> rusqlite::Result<i64> {
+    let mut stmt = conn.prepare("SELECT xp FROM xp WHERE guild_id = ?1 AND user_id = ?2")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+    match rows.next() {
+        Some(row) => Ok(row?.get(0)),
+        None => Ok(0),
+    }
+}
+
+/// Gives XP for a message unless the user got some less than a minute ago. Returns the XP before and after.
+pub fn award_message_xp(conn: &Connection, guild_id: GuildId, user_id: UserId, amount: i64, now: i64) -> rusqlite::Result<Option<(i64, i64)>> {
+    let mut stmt = conn.prepare("SELECT xp, last_message_at FROM xp WHERE guild_id = ?1 AND user_id = ?2")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+    let (xp, last) = match rows.next() {
+        Some(row) => {
+            let row = row?;
+            (row.get::<_, i64>(0), row.get::<_, Option<i64>>(1))
+        },
+        None => (0, None),
+    };
+    if last.map_or(false, |last| now - last < XP_COOLDOWN) {
+        return Ok(None);
+    }
+
+    conn.execute("INSERT OR REPLACE INTO xp (guild_id, user_id, xp, last_message_at) VALUES (?1, ?2, ?3, ?4)",
+        &[&(guild_id.0 as i64), &(user_id.0 as i64), &xp.saturating_add(amount), &now])?;
+    Ok(Some((xp, xp.saturating_add(amount))))
+}
+
+/// Sets the XP of a user, keeping it at 0 or above. Returns the XP before and after.
+pub fn set_xp(conn: &Connection, guild_id: GuildId, user_id: UserId, xp: i64) -> rusqlite::Result<(i64, i64)> {
+    let old = get_xp(conn, guild_id, user_id)?;
+    let new = xp.max(0);
+    conn.execute("INSERT OR IGNORE INTO xp (guild_id, user_id) VALUES (?1, ?2)", &[&(guild_id.0 as i64), &(user_id.0 as i64)])?;
+    conn.execute("UPDATE xp SET xp = ?3 WHERE guild_id = ?1 AND user_id = ?2", &[&(guild_id.0 as i64), &(user_id.0 as i64), &new])?;
+    Ok((old, new))
+}
+
+/// The place of a user on the leaderboard, starting at 1.
+pub fn rank_of(conn: &Connection, guild_id: GuildId, xp: i64) -> rusqlite::Result<i64> {
+    let above: i64 = conn.query_row("SELECT COUNT(*) FROM xp WHERE guild_id = ?1 AND xp > ?2",
+        &[&(guild_id.0 as i64), &xp], |row| row.get(0))?;
+    Ok(above + 1)
+}
+
+pub fn ranked_count(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM xp WHERE guild_id = ?1 AND xp > 0", &[&(guild_id.0 as i64)], |row| row.get(0))
+}
+
+pub fn leaderboard(conn: &Connection, guild_id: GuildId, offset: i64, limit: i64) -> rusqlite::Result<Vec<(UserId, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT user_id, xp FROM xp WHERE guild_id = ?1 AND xp > 0 ORDER BY xp DESC, user_id LIMIT ?2 OFFSET ?3")?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64), &limit, &offset],
+        |row| (UserId(row.get::<_, i64>(0) as u64), row.get(1)))?;
+    rows.collect()
+}
+
+/// Gives a role to everyone that reaches a level, or takes the reward away if `role_id` is `None`.
+pub fn set_reward(conn: &Connection, guild_id: GuildId, level: i64, role_id: Option<RoleId>) -> rusqlite::Result<()> {
+    match role_id {
+        Some(role_id) => conn.execute("INSERT OR REPLACE INTO level_rewards (guild_id, level, role_id) VALUES (?1, ?2, ?3)",
+            &[&(guild_id.0 as i64), &level, &(role_id.0 as i64)])?,
+        None => conn.execute("DELETE FROM level_rewards WHERE guild_id = ?1 AND level = ?2", &[&(guild_id.0 as i64), &level])?,
+    };
+    Ok(())
+}
+
+/// The role rewards of a guild, sorted by level.
+pub fn rewards(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Vec<(i64, RoleId)>> {
+    let mut stmt = conn.prepare("SELECT level, role_id FROM level_rewards WHERE guild_id = ?1 ORDER BY level")?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64)], |row| (row.get(0), RoleId(row.get::<_, i64>(1) as u64)))?;
+    rows.collect()
+}
+
+/// Where level-ups get announced: nowhere, in the channel the message was sent in, or in a set channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Announce {
+    Off,
+    Here,
+    In(ChannelId),
+}
+
+pub fn get_announce(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Announce> {
+    let mut stmt = conn.prepare("SELECT announce, channel_id FROM level_settings WHERE guild_id = ?1")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64)])?;
+    let row = match rows.next() {
+        Some(row) => row?,
+        None => return Ok(Announce::Here),
+    };
+    match (row.get(0), row.get::<_, Option<i64>>(1)) {
+        (false, _) => Ok(Announce::Off),
+        (true, Some(id)) => Ok(Announce::In(ChannelId(id as u64))),
+        (true, None) => Ok(Announce::Here),
+    }
+}
+
+pub fn set_announce(conn: &Connection, guild_id: GuildId, announce: Announce) -> rusqlite::Result<()> {
+    let (on, channel) = match announce {
+        Announce::Off => (false, None),
+        Announce::Here => (true, None),
+        Announce::In(id) => (true, Some(id.0 as i64)),
+    };
+    conn.execute("INSERT OR REPLACE INTO level_settings (guild_id, announce, channel_id) VALUES (?1, ?2, ?3)",
+        &[&(guild_id.0 as i64), &on, &channel])?;
+    Ok(())
+}
+
+/// Hands out the reward roles for the levels a user has reached that they don't have yet.
+/// With `take_higher`, it also takes away the ones for levels above theirs, like after their XP was lowered.
+fn sync_rewards(guild_id: GuildId, user_id: UserId, rewards: &[(i64, RoleId)], level: i64, take_higher: bool) {
+    let guild = match guild_id.find() {
+        Some(guild) => guild,
+        None => return,
+    };
+    let (to_give, to_take): (Vec<(i64, RoleId)>, Vec<(i64, RoleId)>) = {
+        let guild = guild.read();
+        let held = match guild.members.get(&user_id) {
+            Some(member) => member.roles.clone(),
+            None => return,
+        };
+        let own_id = CACHE.read().user.id;
+        rewards.iter()
+            .filter(|&&(reward_level, role_id)| {
+                if reward_level <= level {
+                    !held.contains(&role_id)
+                }
+                else {
+                    take_higher && held.contains(&role_id)
+                }
+            })
+            .filter(|&&(_, role_id)| match guild.roles.get(&role_id).map(|role| can_manage(&guild, own_id, role)) {
+                Some(Ok(())) => true,
+                Some(Err(reason)) => {
+                    warn!("Can't hand out level reward {} in guild {}: {}", role_id, guild_id, reason);
+                    false
+                },
+                None => false,
+            })
+            .partition(|&&(reward_level, _)| reward_level <= level)
+    };
+
+    for (_, role_id) in to_give {
+        if let Err(e) = http::add_member_role(guild_id.0, user_id.0, role_id.0) {
+            warn!("Could not give level reward {} to {}: {}", role_id, user_id, e);
+        }
+    }
+    for (_, role_id) in to_take {
+        if let Err(e) = http::remove_member_role(guild_id.0, user_id.0, role_id.0) {
+            warn!("Could not take away level reward {} from {}: {}", role_id, user_id, e);
+        }
+    }
+}
+
+/// Announces a level-up and hands out the rewards for it.
+fn level_up(msg: &Message, guild_id: GuildId, level: i64, rewards: &[(i64, RoleId)], announce: Announce) -> Result<(), CommandError> {
+    let channel_id = match announce {
+        Announce::Off => None,
+        Announce::Here => Some(msg.channel_id),
+        Announce::In(channel_id) => Some(channel_id),
+    };
+
+    sync_rewards(guild_id, msg.author.id, rewards, level, false);
+    if let Some(channel_id) = channel_id {
+        let mut text = format!("GG {}, you reached level {}!", msg.author.mention(), level);
+        if let Some(&(_, role_id)) = rewards.iter().find(|&&(l, _)| l == level) {
+            let name = role_id.find().map_or(role_id.to_string(), |role| role.name);
+            text.push_str(&format!(" You got the {} role.", name));
+        }
+        channel_id.say(&text)?;
+    }
+    Ok(())
+}
+
+/// Gives XP for messages in guilds, at most once per minute per user.
+pub struct XpTracker;
+
+impl Middleware for XpTracker {
+    fn before(&self, ctx: &mut Context, msg: &Message, state: &mut MessageState) -> Result<Flow, CommandError> {
+        let guild_id = match msg.guild_id() {
+            Some(id) if state.args.is_none() && !state.from_interaction && !msg.author.bot => id,
+            _ => return Ok(Flow::Continue),
+        };
+        let db = match db::get(ctx) {
+            Some(db) => db,
+            None => return Ok(Flow::Continue),
+        };
+
+        let amount = rand::thread_rng().gen_range(MIN_MESSAGE_XP, MAX_MESSAGE_XP + 1);
+        let level_up_to = {
+            let conn = db.lock().unwrap();
+            match award_message_xp(&conn, guild_id, msg.author.id, amount, Utc::now().timestamp())? {
+                Some((old, new)) if level_for_xp(new).0 > level_for_xp(old).0 => {
+                    Some((level_for_xp(new).0, rewards(&conn, guild_id)?, get_announce(&conn, guild_id)?))
+                },
+                _ => None,
+            }
+        };
+
+        if let Some((level, rewards, announce)) = level_up_to {
+            level_up(msg, guild_id, level, &rewards, announce)?;
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+pub struct RankCommand;
+
+impl Command for RankCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let guild_id = msg.guild_id().unwrap();
+        let user_id = match args.get(1) {
+            Some(arg) => match parse_username(arg) {
+                Some(id) => UserId(id),
+                None => {
+                    msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                    return Ok(());
+                }
+            },
+            None => msg.author.id,
+        };
+
+        let (xp, rank, ranked) = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            let xp = get_xp(&conn, guild_id, user_id)?;
+            (xp, rank_of(&conn, guild_id, xp)?, ranked_count(&conn, guild_id)?)
+        };
+        let (level, into, needed) = level_for_xp(xp);
+        let name = user_name(user_id);
+        let place = if xp > 0 { format!("#{} of {}", rank, ranked) } else { "Unranked".to_string() };
+
+        msg.channel_id.send_message(|m| m.embed(|e| {
+            e.title(&format!("{}'s rank", name))
+                .colour(Colour::blurple())
+                .field("Level", &level.to_string(), true)
+                .field("Rank", &place, true)
+                .field("Total XP", &xp.to_string(), true)
+                .field("Next level", &format!("`{}` {}/{} XP", bar(into, needed), into, needed), false)
+        }))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows the level and rank of you or another user in this server."
+    }
+
+    fn usage(&self) -> &str {
+        "[user]"
+    }
+
+    fn category(&self) -> &str {
+        "levels"
+    }
+}
+
+pub struct LeaderboardCommand;
+
+impl Command for LeaderboardCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let guild_id = msg.guild_id().unwrap();
+        let page = match args.get(1).map(|p| p.parse::<i64>()) {
+            Some(Ok(page)) if page > 0 => page,
+            None => 1,
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let (entries, pages) = {
+            let conn = db.lock().unwrap();
+            let total = ranked_count(&conn, guild_id)?;
+            let pages = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+            // Checking the page first keeps the offset from overflowing.
+            if page > pages {
+                drop(conn);
+                if total == 0 {
+                    msg.channel_id.say("Nobody in this server has any XP yet.")?;
+                }
+                else {
+                    msg.reply(&format!("There are only {} page(s).", pages))?;
+                }
+                return Ok(());
+            }
+            (leaderboard(&conn, guild_id, (page - 1) * PAGE_SIZE, PAGE_SIZE)?, pages)
+        };
+
+        let lines: Vec<String> = entries.iter().enumerate()
+            .map(|(i, &(user_id, xp))| format!("**{}.** {} · level {} · {} XP",
+                (page - 1) * PAGE_SIZE + i as i64 + 1, user_name(user_id), level_for_xp(xp).0, xp))
+            .collect();
+        msg.channel_id.send_message(|m| m.embed(|e| {
+            e.title("Leaderboard")
+                .description(&lines.join("\n"))
+                .colour(Colour::gold())
+                .footer(|f| f.text(&format!("Page {} of {}", page, pages)))
+        }))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows who has the most XP in this server."
+    }
+
+    fn usage(&self) -> &str {
+        "[page]"
+    }
+
+    fn category(&self) -> &str {
+        "levels"
+    }
+}
+
+/// Changes the XP of a user, either by an amount or to an amount.
+pub struct AdjustXp {
+    set: bool,
+}
+
+impl Command for AdjustXp {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let target = args.get(1).and_then(|a| parse_username(a)).map(UserId);
+        let amount = args.get(2).and_then(|a| a.parse::<i64>().ok());
+        let (user_id, amount) = match (target, amount) {
+            (Some(user_id), Some(amount)) => (user_id, amount),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let (new, rewards) = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            let xp = if self.set { amount } else { get_xp(&conn, guild_id, user_id)?.saturating_add(amount) };
+            let (_, new) = set_xp(&conn, guild_id, user_id, xp)?;
+            (new, rewards(&conn, guild_id)?)
+        };
+        let level = level_for_xp(new).0;
+        sync_rewards(guild_id, user_id, &rewards, level, true);
+        msg.reply(&format!("{} now has {} XP and is level {}.", user_name(user_id), new, level))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        if self.set {
+            "Sets the XP of a user."
+        }
+        else {
+            "Gives XP to a user, or takes it away with a negative amount."
+        }
+    }
+
+    fn usage(&self) -> &str {
+        "<user> <amount>"
+    }
+}
+
+pub struct SetReward;
+
+impl Command for SetReward {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let name = args[2.min(args.len())..].join(" ");
+        let level = match args.get(1).and_then(|l| l.parse::<i64>().ok()) {
+            Some(level) if level > 0 && !name.is_empty() => level,
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let role_id = if name.eq_ignore_ascii_case("off") {
+            None
+        }
+        else {
+            let guild = match msg.guild() {
+                Some(guild) => guild,
+                None => return Err(CommandError::Other("Guild not in cache".to_string())),
+            };
+            let checked = find_assignable_role(&guild.read(), msg.author.id, &name);
+            match checked {
+                Ok(role_id) => Some(role_id),
+                Err(reason) => {
+                    msg.reply(&reason)?;
+                    return Ok(());
+                }
+            }
+        };
+
+        set_reward(&database(ctx)?.lock().unwrap(), guild_id, level, role_id)?;
+        match role_id {
+            Some(role_id) => msg.reply(&format!("Reaching level {} now gives {}.", level, role_id.mention()))?,
+            None => msg.reply(&format!("Reaching level {} no longer gives a role.", level))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets a role that people get when they reach a level, or takes it off."
+    }
+
+    fn usage(&self) -> &str {
+        "<level> <role|off>"
+    }
+}
+
+pub struct ListRewards;
+
+impl Command for ListRewards {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let rewards = rewards(&database(ctx)?.lock().unwrap(), msg.guild_id().unwrap())?;
+        if rewards.is_empty() {
+            msg.channel_id.say("This server doesn't have any level rewards.")?;
+            return Ok(());
+        }
+
+        let lines: Vec<String> = rewards.iter()
+            .map(|&(level, role_id)| format!("Level {}: {}", level, role_id.find().map_or(role_id.to_string(), |role| role.name)))
+            .collect();
+        msg.channel_id.say(&lines.join("\n"))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the roles people get for reaching levels."
+    }
+}
+
+pub struct SetAnnounce;
+
+impl Command for SetAnnounce {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let announce = match args.get(1).map(|a| a.to_lowercase()) {
+            Some(ref a) if a == "off" => Announce::Off,
+            Some(ref a) if a == "on" => Announce::Here,
+            Some(ref a) => match parse_channel(a) {
+                Some(id) if msg.guild().map_or(false, |g| g.read().channels.contains_key(&ChannelId(id))) => Announce::In(ChannelId(id)),
+                _ => {
+                    msg.reply("That's not a channel in this server.")?;
+                    return Ok(());
+                }
+            },
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        set_announce(&database(ctx)?.lock().unwrap(), msg.guild_id().unwrap(), announce)?;
+        match announce {
+            Announce::Off => msg.reply("Level-ups won't be announced.")?,
+            Announce::Here => msg.reply("Level-ups will be announced where they happen.")?,
+            Announce::In(channel_id) => msg.reply(&format!("Level-ups will be announced in {}.", channel_id.mention()))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets where level-ups are announced: where they happen, in a channel, or not at all."
+    }
+
+    fn usage(&self) -> &str {
+        "<on|off|#channel>"
+    }
+}
+
+pub fn levels_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Sets up levels and changes the XP of users in this server.");
+    group.add_command("rewards", ListRewards);
+    group.add_command("reward", SetReward);
+    group.add_command("give", AdjustXp { set: false });
+    group.add_command("set", AdjustXp { set: true });
+    group.add_command("announce", SetAnnounce);
+    group.set_default("rewards");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_check(guild_admin_only);
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    #[test]
+    fn levels_from_xp() {
+        assert_eq!(level_for_xp(0), (0, 0, 100));
+        assert_eq!(level_for_xp(99), (0, 99, 100));
+        assert_eq!(level_for_xp(100), (1, 0, 155));
+        assert_eq!(level_for_xp(100 + 155 + 10), (2, 10, 220));
+        assert_eq!(level_for_xp(-5), (0, 0, 100));
+    }
+
+    #[test]
+    fn xp_has_a_cooldown() {
+        let conn = db::open(":memory:").unwrap();
+        let (guild, user) = (GuildId(1), UserId(2));
+        assert_eq!(award_message_xp(&conn, guild, user, 20, 1000).unwrap(), Some((0, 20)));
+        assert_eq!(award_message_xp(&conn, guild, user, 20, 1030).unwrap(), None);
+        assert_eq!(award_message_xp(&conn, guild, user, 20, 1060).unwrap(), Some((20, 40)));
+        assert_eq!(set_xp(&conn, guild, user, -10).unwrap(), (40, 0));
+        assert_eq!(award_message_xp(&conn, guild, user, 20, 1070).unwrap(), None);
+        assert_eq!(get_xp(&conn, GuildId(9), user).unwrap(), 0);
+
+        set_xp(&conn, guild, user, i64::max_value() - 5).unwrap();
+        assert_eq!(award_message_xp(&conn, guild, user, 20, 2000).unwrap(), Some((i64::max_value() - 5, i64::max_value())));
+    }
+
+    #[test]
+    fn leaderboard_pages() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        for i in 1..=12 {
+            set_xp(&conn, guild, UserId(i), i as i64 * 10).unwrap();
+        }
+        set_xp(&conn, guild, UserId(50), 0).unwrap();
+        set_xp(&conn, GuildId(2), UserId(60), 1000).unwrap();
+
+        assert_eq!(ranked_count(&conn, guild).unwrap(), 12);
+        assert_eq!(rank_of(&conn, guild, 120).unwrap(), 1);
+        assert_eq!(rank_of(&conn, guild, 10).unwrap(), 12);
+        let first = leaderboard(&conn, guild, 0, PAGE_SIZE).unwrap();
+        assert_eq!(first.len(), 10);
+        assert_eq!(first[0], (UserId(12), 120));
+        assert_eq!(leaderboard(&conn, guild, PAGE_SIZE, PAGE_SIZE).unwrap(), vec![(UserId(2), 20), (UserId(1), 10)]);
+    }
+
+    #[test]
+    fn store_rewards_and_announcements() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        set_reward(&conn, guild, 10, Some(RoleId(3))).unwrap();
+        set_reward(&conn, guild, 5, Some(RoleId(2))).unwrap();
+        set_reward(&conn, guild, 10, Some(RoleId(4))).unwrap();
+        assert_eq!(rewards(&conn, guild).unwrap(), vec![(5, RoleId(2)), (10, RoleId(4))]);
+        set_reward(&conn, guild, 5, None).unwrap();
+        assert_eq!(rewards(&conn, guild).unwrap(), vec![(10, RoleId(4))]);
+
+        assert_eq!(get_announce(&conn, guild).unwrap(), Announce::Here);
+        set_announce(&conn, guild, Announce::In(ChannelId(7))).unwrap();
+        assert_eq!(get_announce(&conn, guild).unwrap(), Announce::In(ChannelId(7)));
+        set_announce(&conn, guild, Announce::Off).unwrap();
+        assert_eq!(get_announce(&conn, guild).unwrap(), Announce::Off);
+    }
+}
\ No newline at end of file
@@ -1,14 +1,41 @@
+use serenity::CACHE;
 use serenity::client::Context;
-use serenity::model::channel::Message;
+use serenity::model::channel::{Message, ReactionType};
+use serenity::model::id::UserId;
+use serenity::model::permissions::Permissions;
+use serenity::utils::parse_emoji;
 use serenity;
 use quick_xml;
 use reqwest;
+use rusqlite;
+use rusqlite::Connection;
+use typemap;
+use typemap::ShareMap;
 
 use std::fmt;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
 
 pub mod games;
 pub mod myanimelist;
+pub mod group;
+pub mod help;
+pub mod owner;
+pub mod settings;
+pub mod stats;
+pub mod modlog;
+pub mod moderation;
+pub mod reminders;
+pub mod time;
+pub mod tags;
+pub mod autorespond;
+pub mod polls;
+pub mod reactionroles;
+pub mod welcome;
+pub mod levels;
+pub mod economy;
+
+use db;
 
 pub type CommandResult = Result<(), CommandError>;
 
@@ -22,6 +49,7 @@ pub enum CommandError {
     Serenity(serenity::Error),
     Xml(quick_xml::Error),
     Reqwest(reqwest::Error),
+    Sqlite(rusqlite::Error),
     Argument(String),
     Other(String),
 }
@@ -38,12 +66,32 @@ impl From<quick_xml::Error> for CommandError {
     }
 }
 
+impl From<rusqlite::Error> for CommandError {
+    fn from(err: rusqlite::Error) -> CommandError {
+        CommandError::Sqlite(err)
+    }
+}
+
 impl From<serenity::Error> for CommandError {
     fn from(err: serenity::Error) -> CommandError {
         CommandError::Serenity(err)
     }
 }
 
+impl CommandError {
+    /// A short name for the kind of error, for logging and stats.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            CommandError::Serenity(_) => "serenity",
+            CommandError::Xml(_) => "xml",
+            CommandError::Reqwest(_) => "reqwest",
+            CommandError::Sqlite(_) => "sqlite",
+            CommandError::Argument(_) => "argument",
+            CommandError::Other(_) => "other",
+        }
+    }
+}
+
 impl fmt::Display for CommandError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -56,6 +104,9 @@ impl fmt::Display for CommandError {
             CommandError::Serenity(ref err) => {
                 write!(f, "Serenity error while executing a command: {}", err)
             },
+            CommandError::Sqlite(ref err) => {
+                write!(f, "Sqlite error while executing a command: {}", err)
+            },
             CommandError::Argument(ref s) => {
                 write!(f, "Invalid arguments to a command: {}", s)
             },
@@ -68,10 +119,239 @@ impl fmt::Display for CommandError {
 
 pub trait Command: Send + Sync + 'static {
     fn execute(&self, _ctx: &mut Context, _msg: &Message, _args: &Vec<String>) -> CommandResult;
+
+    /// Short description of the command, shown by the help command.
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// The arguments the command takes, shown after the command name by the help command.
+    fn usage(&self) -> &str {
+        ""
+    }
+
+    /// The child commands if this is a command group.
+    fn subcommands(&self) -> Option<&CommandMap> {
+        None
+    }
+
+    /// The category of the command, used to turn groups of commands on and off in a guild.
+    fn category(&self) -> &str {
+        "general"
+    }
 }
 
 impl Command for Arc<Command> {
     fn execute(&self, _ctx: &mut Context, _msg: &Message, _args: &Vec<String>) -> CommandResult {
         (**self).execute(_ctx, _msg, _args)
     }
+
+    fn description(&self) -> &str {
+        (**self).description()
+    }
+
+    fn usage(&self) -> &str {
+        (**self).usage()
+    }
+
+    fn subcommands(&self) -> Option<&CommandMap> {
+        (**self).subcommands()
+    }
+
+    fn category(&self) -> &str {
+        (**self).category()
+    }
+}
+
+pub type CommandMap = HashMap<String, Arc<Command>>;
+
+/// Gets the database connection, for commands that can't work without one.
+pub fn database(ctx: &Context) -> Result<Arc<Mutex<Connection>>, CommandError> {
+    db::get(ctx).ok_or_else(|| CommandError::Other("No database connection".to_string()))
+}
+
+/// The name of a user if they're in the cache, or else their ID.
+pub fn user_name(id: UserId) -> String {
+    match CACHE.read().user(id) {
+        Some(user) => user.read().name.clone(),
+        None => id.to_string(),
+    }
+}
+
+/// A check that has to pass before a command gets executed. Returns the reason on failure.
+pub type Check = fn(&Context, &Message) -> Result<(), String>;
+
+pub fn guild_only(_ctx: &Context, msg: &Message) -> Result<(), String> {
+    if msg.guild_id().is_some() {
+        Ok(())
+    }
+    else {
+        Err("That command can only be used in a server.".to_string())
+    }
+}
+
+/// Key for the users that are allowed to use the owner commands.
+pub struct Owners;
+
+impl typemap::Key for Owners {
+    type Value = HashSet<UserId>;
+}
+
+pub fn owner_only(ctx: &Context, msg: &Message) -> Result<(), String> {
+    let data = ctx.data.lock();
+    match data.get::<Owners>() {
+        Some(owners) if owners.contains(&msg.author.id) => Ok(()),
+        _ => Err("Only the owners of the bot can do that.".to_string()),
+    }
+}
+
+pub fn guild_admin_only(_ctx: &Context, msg: &Message) -> Result<(), String> {
+    if is_guild_admin(msg) {
+        Ok(())
+    }
+    else {
+        Err("You need the Manage Server permission to do that.".to_string())
+    }
+}
+
+/// State that gets passed along the middleware chain for a single message.
+pub struct MessageState {
+    /// The parsed command and its arguments, if the message looked like a command.
+    pub args: Option<Vec<String>>,
+    /// The result of the command, set before the `after` middleware gets called.
+    pub result: Option<CommandResult>,
+    /// Arbitrary data that middleware can attach to the message for later middleware.
+    pub annotations: ShareMap,
+    /// Whether the message was made up from a slash command, which middleware that
+    /// reacts to chat messages should leave alone.
+    pub from_interaction: bool,
+}
+
+impl MessageState {
+    pub fn new(args: Option<Vec<String>>) -> MessageState {
+        MessageState {
+            args: args,
+            result: None,
+            annotations: ShareMap::custom(),
+            from_interaction: false,
+        }
+    }
+}
+
+pub enum Flow {
+    /// Keep passing the message along the chain.
+    Continue,
+    /// Stop processing the message. Stopping in `before` skips the command as well.
+    Stop,
+}
+
+/// Gets called for every message, before and after commands are dispatched.
+pub trait Middleware: Send + Sync + 'static {
+    fn before(&self, _ctx: &mut Context, _msg: &Message, _state: &mut MessageState) -> Result<Flow, CommandError> {
+        Ok(Flow::Continue)
+    }
+
+    fn after(&self, _ctx: &mut Context, _msg: &Message, _state: &MessageState) -> Result<Flow, CommandError> {
+        Ok(Flow::Continue)
+    }
+}
+
+/// Finds commands that aren't registered with the framework, like the tags of a guild.
+/// Gets asked when a message starts with the prefix but doesn't name a registered command.
+pub trait CommandSource: Send + Sync + 'static {
+    fn find(&self, ctx: &Context, msg: &Message, name: &str) -> Option<Arc<Command>>;
+}
+
+/// Checks if the author of a message has the Manage Server permission in the guild it was sent in.
+pub fn is_guild_admin(msg: &Message) -> bool {
+    has_permission(msg, Permissions::MANAGE_GUILD)
+}
+
+/// Checks if the author of a message has all of the given permissions in the guild it was sent in.
+pub fn has_permission(msg: &Message, permissions: Permissions) -> bool {
+    if let Some(guild) = msg.guild() {
+        guild.read().member_permissions(msg.author.id).contains(permissions)
+    }
+    else {
+        false
+    }
+}
+
+/// Keeps user-written text from pinging everyone or whole roles.
+pub fn sanitize_mentions(text: &str) -> String {
+    text.replace("@everyone", "@\u{200b}everyone")
+        .replace("@here", "@\u{200b}here")
+        .replace("<@&", "<@\u{200b}&")
+}
+
+/// Reads an emoji given by a user, either a custom emoji like `<:name:id>` or a unicode one.
+pub fn parse_reaction(text: &str) -> ReactionType {
+    // parse_emoji doesn't know about animated emoji, which start with `<a:` instead of `<:`.
+    if text.starts_with("<a:") {
+        if let Some(emoji) = parse_emoji(&format!("<{}", &text[2..])) {
+            return ReactionType::Custom {
+                animated: true,
+                id: emoji.id,
+                name: Some(emoji.name),
+            };
+        }
+    }
+
+    match parse_emoji(text) {
+        Some(emoji) => ReactionType::from(emoji),
+        None => ReactionType::from(text),
+    }
+}
+
+/// Gets the text after the first `words` words of a message, keeping its quotes and line breaks.
+pub fn text_after(msg: &Message, words: usize) -> &str {
+    let mut rest = msg.content.trim();
+    for _ in 0..words {
+        rest = match rest.find(char::is_whitespace) {
+            Some(i) => rest[i..].trim_start(),
+            None => "",
+        };
+    }
+    rest.trim()
+}
+
+/// Parses a period like `30m`, `12h`, `7d` or `2w` into seconds.
+pub fn parse_period(period: &str) -> Option<i64> {
+    let (split, _) = period.char_indices().last()?;
+    if split == 0 {
+        return None;
+    }
+
+    let (amount, unit) = period.split_at(split);
+    let amount: i64 = amount.parse().ok()?;
+    let unit = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    if amount > 0 {
+        amount.checked_mul(unit)
+    }
+    else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_periods() {
+        assert_eq!(parse_period("30m"), Some(30 * 60));
+        assert_eq!(parse_period("7d"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_period("0d"), None);
+        assert_eq!(parse_period("d"), None);
+        assert_eq!(parse_period("3y"), None);
+        assert_eq!(parse_period("1é"), None);
+        assert_eq!(parse_period("é"), None);
+        assert_eq!(parse_period(""), None);
+    }
 }
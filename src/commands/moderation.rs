@@ -0,0 +1,727 @@
+use commands::{Command, CommandError, CommandResult, database, guild_only, has_permission, parse_period, user_name};
+use commands::reactionroles::{can_manage, roles_position, top_position};
+use scheduler;
+use scheduler::{Job, JobHandler, NewJob};
+
+use chrono::{NaiveDateTime, Utc};
+use rusqlite;
+use rusqlite::Connection;
+use serde_json;
+
+use serenity;
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::http;
+use serenity::http::HttpError;
+use serenity::model::channel::Message;
+use serenity::model::guild::{Guild, Member};
+use serenity::model::id::{GuildId, MessageId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use serenity::utils::{Colour, parse_username};
+
+use std::sync::{Arc, Mutex};
+
+/// Name of the role that gets given to muted members, matched case insensitively.
+const MUTE_ROLE: &str = "Muted";
+
+/// Kind of the scheduled job that lifts a temporary ban or mute.
+pub const LIFT_JOB: &str = "moderation.lift";
+
+/// Discord only allows bulk deleting this many messages at once.
+const MAX_PURGE: u64 = 100;
+
+/// Discord won't bulk delete messages older than two weeks.
+const MAX_PURGE_AGE_SECS: i64 = 14 * 24 * 60 * 60;
+
+/// Discord's limit on the length of an audit log reason.
+const MAX_REASON_LEN: usize = 512;
+
+/// How many cases to list for a user.
+const MAX_LISTED_CASES: i64 = 15;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    Warn,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match *self {
+            Action::Kick => "kick",
+            Action::Ban => "ban",
+            Action::Unban => "unban",
+            Action::Mute => "mute",
+            Action::Unmute => "unmute",
+            Action::Warn => "warn",
+        }
+    }
+
+    fn past_tense(&self) -> &'static str {
+        match *self {
+            Action::Kick => "Kicked",
+            Action::Ban => "Banned",
+            Action::Unban => "Unbanned",
+            Action::Mute => "Muted",
+            Action::Unmute => "Unmuted",
+            Action::Warn => "Warned",
+        }
+    }
+
+    fn permission(&self) -> (Permissions, &'static str) {
+        match *self {
+            Action::Kick | Action::Warn => (Permissions::KICK_MEMBERS, "Kick Members"),
+            Action::Ban | Action::Unban => (Permissions::BAN_MEMBERS, "Ban Members"),
+            Action::Mute | Action::Unmute => (Permissions::MANAGE_ROLES, "Manage Roles"),
+        }
+    }
+
+    /// Whether the action can be given a duration, after which it gets lifted.
+    fn temporary(&self) -> bool {
+        *self == Action::Ban || *self == Action::Mute
+    }
+
+    /// The temporary action that this action lifts, if any.
+    fn lifts(&self) -> Option<Action> {
+        match *self {
+            Action::Ban | Action::Unban => Some(Action::Ban),
+            Action::Mute | Action::Unmute => Some(Action::Mute),
+            _ => None,
+        }
+    }
+}
+
+/// A moderation action, as it is stored in the database.
+pub struct Case {
+    pub id: i64,
+    pub action: String,
+    pub moderator_id: UserId,
+    pub target_id: Option<UserId>,
+    pub reason: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub lifted: bool,
+}
+
+/// A case to store, before it gets a number.
+pub struct NewCase<'a> {
+    pub action: &'a str,
+    pub moderator_id: UserId,
+    pub target_id: Option<UserId>,
+    pub reason: &'a str,
+    pub role_id: Option<RoleId>,
+    pub expires_at: Option<i64>,
+}
+
+const CASE_COLUMNS: &str = "case_id, action, moderator_id, target_id, reason, created_at, expires_at, lifted";
+
+fn case_from_row(row: &rusqlite::Row) -> Case {
+    Case {
+        id: row.get(0),
+        action: row.get(1),
+        moderator_id: UserId(row.get::<_, i64>(2) as u64),
+        target_id: row.get::<_, Option<i64>>(3).map(|id| UserId(id as u64)),
+        reason: row.get(4),
+        created_at: row.get(5),
+        expires_at: row.get(6),
+        lifted: row.get(7),
+    }
+}
+
+/// Stores a case under the next free number in the guild and returns the number.
+pub fn add_case(conn: &Connection, guild_id: GuildId, case: &NewCase, now: i64) -> rusqlite::Result<i64> {
+    let guild = guild_id.0 as i64;
+    let id: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(case_id), 0) + 1 FROM mod_cases WHERE guild_id = ?1",
+        &[&guild],
+        |row| row.get(0))?;
+    conn.execute(
+        "INSERT INTO mod_cases (guild_id, case_id, action, moderator_id, target_id, reason, role_id, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        &[&guild, &id, &case.action, &(case.moderator_id.0 as i64), &case.target_id.map(|id| id.0 as i64),
+          &case.reason, &case.role_id.map(|id| id.0 as i64), &now, &case.expires_at])?;
+    Ok(id)
+}
+
+pub fn get_case(conn: &Connection, guild_id: GuildId, case_id: i64) -> rusqlite::Result<Option<Case>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM mod_cases WHERE guild_id = ?1 AND case_id = ?2", CASE_COLUMNS))?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64), &case_id])?;
+    match rows.next() {
+        Some(row) => Ok(Some(case_from_row(&row?))),
+        None => Ok(None),
+    }
+}
+
+/// Gets the newest cases against a user, newest first.
+pub fn user_cases(conn: &Connection, guild_id: GuildId, user_id: UserId, limit: i64) -> rusqlite::Result<Vec<Case>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM mod_cases WHERE guild_id = ?1 AND target_id = ?2 ORDER BY case_id DESC LIMIT ?3", CASE_COLUMNS))?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64), &(user_id.0 as i64), &limit], |row| case_from_row(row))?;
+    let mut cases = Vec::new();
+    for row in rows {
+        cases.push(row?);
+    }
+    Ok(cases)
+}
+
+/// Marks the pending temporary actions of a kind against a user as lifted,
+/// so that they don't get lifted again when they run out.
+fn supersede(conn: &Connection, guild_id: GuildId, user_id: UserId, action: Action) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE mod_cases SET lifted = 1 WHERE guild_id = ?1 AND target_id = ?2 AND action = ?3 AND expires_at IS NOT NULL AND lifted = 0",
+        &[&(guild_id.0 as i64), &(user_id.0 as i64), &action.name()]).map(|n| n as usize)
+}
+
+fn mark_lifted(conn: &Connection, guild_id: GuildId, case_id: i64) -> rusqlite::Result<()> {
+    conn.execute("UPDATE mod_cases SET lifted = 1 WHERE guild_id = ?1 AND case_id = ?2",
+        &[&(guild_id.0 as i64), &case_id])?;
+    Ok(())
+}
+
+/// What a lift job needs to know to undo a temporary ban or mute.
+#[derive(Serialize, Deserialize)]
+struct LiftPayload {
+    guild_id: u64,
+    case_id: i64,
+    action: String,
+    target_id: u64,
+    role_id: Option<u64>,
+}
+
+/// Key of the job that lifts a temporary action, so there's only one per user and it can be cancelled.
+fn lift_key(guild_id: GuildId, user_id: UserId, action: Action) -> String {
+    format!("{}:{}:{}:{}", LIFT_JOB, guild_id, user_id, action.name())
+}
+
+fn lift(payload: &LiftPayload) -> serenity::Result<()> {
+    let guild_id = GuildId(payload.guild_id);
+    match (payload.action.as_str(), payload.role_id) {
+        ("ban", _) => guild_id.unban(UserId(payload.target_id)),
+        ("mute", Some(role_id)) => http::remove_member_role(guild_id.0, payload.target_id, role_id),
+        _ => Ok(()),
+    }
+}
+
+/// Lifts temporary bans and mutes when they run out.
+pub struct LiftHandler {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl LiftHandler {
+    pub fn new(db: Arc<Mutex<Connection>>) -> LiftHandler {
+        LiftHandler {
+            db: db,
+        }
+    }
+}
+
+impl JobHandler for LiftHandler {
+    fn run(&self, job: &Job) -> Result<(), CommandError> {
+        let payload: LiftPayload = serde_json::from_value(job.payload.clone())
+            .map_err(|e| CommandError::Other(format!("Invalid lift job: {}", e)))?;
+
+        match lift(&payload) {
+            Ok(()) => info!("Lifted {} of user {} in guild {} (case #{})",
+                payload.action, payload.target_id, payload.guild_id, payload.case_id),
+            // Discord rejected it, the user was already unbanned, left the server, the role is gone...
+            // Retrying won't help with any of those.
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res))) if res.status.is_client_error() => {
+                warn!("Could not lift {} case #{} in guild {}: {}", payload.action, payload.case_id, payload.guild_id, res.status)
+            },
+            Err(e) => return Err(e.into()),
+        }
+        mark_lifted(&self.db.lock().unwrap(), GuildId(payload.guild_id), payload.case_id)?;
+        Ok(())
+    }
+}
+
+fn parse_user(arg: &str) -> Option<UserId> {
+    parse_username(arg).or_else(|| arg.parse().ok()).map(UserId)
+}
+
+fn format_time(timestamp: i64) -> String {
+    NaiveDateTime::from_timestamp(timestamp, 0).format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    text.chars().take(max).collect()
+}
+
+fn require_permission(msg: &Message, action: Action) -> Result<(), String> {
+    let (permission, name) = action.permission();
+    if has_permission(msg, permission) {
+        Ok(())
+    }
+    else {
+        Err(format!("You need the {} permission to do that.", name))
+    }
+}
+
+/// Checks that the bot has the permission for an action, and that both the moderator and the bot
+/// rank above the target, returning the reason if not.
+/// Finds a member of a guild, asking Discord if they aren't cached. Returns None if they aren't in the guild.
+fn find_member(guild_id: GuildId, user_id: UserId) -> Result<Option<Member>, CommandError> {
+    let cached = guild_id.find().and_then(|guild| guild.read().members.get(&user_id).cloned());
+    if cached.is_some() {
+        return Ok(cached);
+    }
+    match guild_id.member(user_id) {
+        Ok(member) => Ok(Some(member)),
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res))) if res.status.is_client_error() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn can_moderate(guild: &Guild, moderator: UserId, target: Option<&Member>, own_id: UserId, action: Action) -> Result<(), String> {
+    let (permission, name) = action.permission();
+    if !guild.member_permissions(own_id).contains(permission) {
+        return Err(format!("I need the {} permission to do that.", name));
+    }
+    // Users that aren't in the server (like when banning by ID) don't have any roles to compare.
+    let target = match target {
+        Some(target) if action != Action::Unban => target,
+        _ => return Ok(()),
+    };
+    if target.user.read().id == guild.owner_id {
+        return Err(format!("The server owner can't be {}.", action.past_tense().to_lowercase()));
+    }
+    let target_position = roles_position(guild, &target.roles);
+    if moderator != guild.owner_id && top_position(guild, moderator) <= target_position {
+        return Err(format!("You can't {} someone whose highest role isn't below yours.", action.name()));
+    }
+    if top_position(guild, own_id) <= target_position {
+        return Err(format!("I can't {} someone whose highest role isn't below mine.", action.name()));
+    }
+    Ok(())
+}
+
+fn mute_role(guild_id: GuildId) -> Option<RoleId> {
+    let guild = guild_id.find()?;
+    let guild = guild.read();
+    guild.roles.values()
+        .find(|role| role.name.eq_ignore_ascii_case(MUTE_ROLE))
+        .map(|role| role.id)
+}
+
+/// Kicks, bans, mutes or warns a user and records it as a case.
+pub struct ModerationCommand {
+    action: Action,
+}
+
+impl ModerationCommand {
+    pub fn new(action: Action) -> ModerationCommand {
+        ModerationCommand {
+            action: action,
+        }
+    }
+}
+
+impl Command for ModerationCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg).and_then(|_| require_permission(msg, self.action)) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let guild_id = msg.guild_id().unwrap();
+
+        let target = match args.get(1).and_then(|arg| parse_user(arg)) {
+            Some(target) => target,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+        if target == msg.author.id {
+            msg.reply(&format!("You can't {} yourself.", self.action.name()))?;
+            return Ok(());
+        }
+        let member = if self.action == Action::Unban { None } else { find_member(guild_id, target)? };
+        let checked = match guild_id.find() {
+            Some(guild) => can_moderate(&guild.read(), msg.author.id, member.as_ref(), CACHE.read().user.id, self.action),
+            None => return Err(CommandError::Other("Guild not in cache".to_string())),
+        };
+        if let Err(reason) = checked {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let mut rest = &args[2..];
+        let mut duration = None;
+        if self.action.temporary() {
+            if let Some(secs) = rest.first().and_then(|arg| parse_period(arg)) {
+                duration = Some((secs, rest[0].clone()));
+                rest = &rest[1..];
+            }
+        }
+        let reason = truncate(&rest.join(" "), MAX_REASON_LEN);
+        let name = user_name(target);
+
+        let mut role_id = None;
+        let result = match self.action {
+            Action::Kick => guild_id.kick(target),
+            Action::Ban if reason.is_empty() => guild_id.ban(target, &0u8),
+            Action::Ban => guild_id.ban(target, &(0u8, reason.as_str())),
+            Action::Unban => guild_id.unban(target),
+            Action::Mute | Action::Unmute => {
+                match mute_role(guild_id) {
+                    Some(role) => role_id = Some(role),
+                    None => {
+                        msg.reply(&format!("There's no role named `{}` in this server. Create one that can't send messages first.", MUTE_ROLE))?;
+                        return Ok(());
+                    }
+                }
+                let role = role_id.unwrap();
+                let checked = guild_id.find()
+                    .and_then(|guild| {
+                        let guild = guild.read();
+                        guild.roles.get(&role).map(|r| can_manage(&guild, CACHE.read().user.id, r))
+                    });
+                if let Some(Err(reason)) = checked {
+                    msg.reply(&format!("I can't hand out the `{}` role: {}", MUTE_ROLE, reason))?;
+                    return Ok(());
+                }
+                if self.action == Action::Mute {
+                    http::add_member_role(guild_id.0, target.0, role.0)
+                }
+                else {
+                    http::remove_member_role(guild_id.0, target.0, role.0)
+                }
+            },
+            Action::Warn => {
+                let text = match guild_id.find() {
+                    Some(guild) => format!("You have been warned in {}", guild.read().name),
+                    None => "You have been warned".to_string(),
+                };
+                let text = if reason.is_empty() { format!("{}.", text) } else { format!("{}: {}", text, reason) };
+                // The warning still counts if the user doesn't accept DMs.
+                if let Err(e) = target.create_dm_channel().and_then(|dm| dm.id.say(&text)) {
+                    debug!("Could not DM a warning to {}: {}", target, e);
+                }
+                Ok(())
+            },
+        };
+        if let Err(e) = result {
+            msg.reply(&format!("Couldn't {} {}: {}", self.action.name(), name, e))?;
+            return Ok(());
+        }
+
+        let scheduler = match scheduler::get(ctx) {
+            Some(scheduler) => scheduler,
+            None => return Err(CommandError::Other("No scheduler".to_string())),
+        };
+        let now = Utc::now().timestamp();
+        let expires_at = duration.as_ref().map(|&(secs, _)| now + secs);
+        let case_id = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            if let Some(lifted) = self.action.lifts() {
+                supersede(&conn, guild_id, target, lifted)?;
+            }
+            add_case(&conn, guild_id, &NewCase {
+                action: self.action.name(),
+                moderator_id: msg.author.id,
+                target_id: Some(target),
+                reason: &reason,
+                role_id: role_id,
+                expires_at: expires_at,
+            }, now)?
+        };
+
+        // A new ban or an unban replaces whatever was going to lift the previous ban.
+        if let Some(lifted) = self.action.lifts() {
+            scheduler.cancel_key(&lift_key(guild_id, target, lifted))?;
+        }
+        if let Some(expires_at) = expires_at {
+            let payload = LiftPayload {
+                guild_id: guild_id.0,
+                case_id: case_id,
+                action: self.action.name().to_string(),
+                target_id: target.0,
+                role_id: role_id.map(|id| id.0),
+            };
+            let payload = serde_json::to_value(&payload).map_err(|e| CommandError::Other(e.to_string()))?;
+            scheduler.schedule(NewJob::at(LIFT_JOB, expires_at, payload).key(&lift_key(guild_id, target, self.action)))?;
+        }
+
+        let length = match duration {
+            Some((_, ref period)) => format!(" for {}", period),
+            None => String::new(),
+        };
+        msg.channel_id.say(&format!("{} **{}**{}. (case #{})", self.action.past_tense(), name, length, case_id))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        match self.action {
+            Action::Kick => "Kicks a member from the server.",
+            Action::Ban => "Bans a user from the server, for a while if a duration is given.",
+            Action::Unban => "Lifts the ban of a user.",
+            Action::Mute => "Gives a member the Muted role, for a while if a duration is given.",
+            Action::Unmute => "Takes the Muted role away from a member.",
+            Action::Warn => "Warns a member and sends them the reason.",
+        }
+    }
+
+    fn usage(&self) -> &str {
+        if self.action.temporary() {
+            "<user> [duration, e.g. 30m, 12h or 7d] [reason]"
+        }
+        else {
+            "<user> [reason]"
+        }
+    }
+
+    fn category(&self) -> &str {
+        "moderation"
+    }
+}
+
+pub struct PurgeCommand;
+
+impl Command for PurgeCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        if !has_permission(msg, Permissions::MANAGE_MESSAGES) {
+            msg.reply("You need the Manage Messages permission to do that.")?;
+            return Ok(());
+        }
+
+        let count = args.get(1)
+            .and_then(|arg| arg.parse::<u64>().ok())
+            .and_then(|n| if n > 0 && n <= MAX_PURGE { Some(n) } else { None });
+        let user = args.get(2).map(|arg| parse_user(arg));
+        let (count, user) = match (count, user) {
+            (Some(count), None) => (count, None),
+            (Some(count), Some(Some(user))) => (count, Some(user)),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let cutoff = Utc::now().timestamp() - MAX_PURGE_AGE_SECS;
+        let ids: Vec<MessageId> = msg.channel_id.messages(|g| g.before(msg.id).limit(MAX_PURGE))?
+            .into_iter()
+            .filter(|m| user.map_or(true, |user| m.author.id == user))
+            .filter(|m| m.timestamp.timestamp() > cutoff)
+            .take(count as usize)
+            .map(|m| m.id)
+            .collect();
+        if ids.is_empty() {
+            msg.reply("There are no messages to delete.")?;
+            return Ok(());
+        }
+
+        msg.channel_id.delete_messages(&ids)?;
+        let _ = msg.delete();
+
+        let reason = match user {
+            Some(user) => format!("Deleted {} message(s) by {} in <#{}>", ids.len(), user_name(user), msg.channel_id),
+            None => format!("Deleted {} message(s) in <#{}>", ids.len(), msg.channel_id),
+        };
+        let now = Utc::now().timestamp();
+        let case_id = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            add_case(&conn, msg.guild_id().unwrap(), &NewCase {
+                action: "purge",
+                moderator_id: msg.author.id,
+                target_id: user,
+                reason: &reason,
+                role_id: None,
+                expires_at: None,
+            }, now)?
+        };
+        msg.channel_id.say(&format!("{}. (case #{})", reason, case_id))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Deletes recent messages in this channel, only the ones by a user if one is given."
+    }
+
+    fn usage(&self) -> &str {
+        "<count, up to 100> [user]"
+    }
+
+    fn category(&self) -> &str {
+        "moderation"
+    }
+}
+
+/// Checks that the author can look up cases, which is anyone who can warn.
+fn can_view_cases(ctx: &Context, msg: &Message) -> Result<(), String> {
+    guild_only(ctx, msg).and_then(|_| require_permission(msg, Action::Warn))
+}
+
+pub struct CaseCommand;
+
+impl Command for CaseCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = can_view_cases(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let case_id = match args.get(1).and_then(|arg| arg.trim_matches('#').parse::<i64>().ok()) {
+            Some(id) => id,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let case = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            get_case(&conn, msg.guild_id().unwrap(), case_id)?
+        };
+        let case = match case {
+            Some(case) => case,
+            None => {
+                msg.reply(&format!("There's no case #{} in this server.", case_id))?;
+                return Ok(());
+            }
+        };
+
+        let target = case.target_id.map(user_name).unwrap_or_else(|| "*Nobody*".to_string());
+        let reason = if case.reason.is_empty() { "*No reason given*".to_string() } else { case.reason.clone() };
+        msg.channel_id.send_message(|m| m.embed(|e| {
+            let e = e.title(&format!("Case #{}: {}", case.id, case.action))
+                .colour(Colour::dark_red())
+                .field("Target", &target, true)
+                .field("Moderator", &user_name(case.moderator_id), true)
+                .field("Reason", &reason, false)
+                .field("Created", &format_time(case.created_at), true);
+            match case.expires_at {
+                Some(expires_at) => {
+                    let state = if case.lifted { " (lifted)" } else { "" };
+                    e.field("Expires", &format!("{}{}", format_time(expires_at), state), true)
+                },
+                None => e,
+            }
+        }))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows a moderation case."
+    }
+
+    fn usage(&self) -> &str {
+        "<case number>"
+    }
+
+    fn category(&self) -> &str {
+        "moderation"
+    }
+}
+
+pub struct CasesCommand;
+
+impl Command for CasesCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = can_view_cases(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let user = match args.get(1).and_then(|arg| parse_user(arg)) {
+            Some(user) => user,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let cases = {
+            let db = database(ctx)?;
+            let conn = db.lock().unwrap();
+            user_cases(&conn, msg.guild_id().unwrap(), user, MAX_LISTED_CASES)?
+        };
+        if cases.is_empty() {
+            msg.reply(&format!("{} has no cases in this server.", user_name(user)))?;
+            return Ok(());
+        }
+
+        let mut text = format!("Cases for **{}**:", user_name(user));
+        for case in cases.iter() {
+            let reason = if case.reason.is_empty() { "no reason given" } else { case.reason.as_str() };
+            text.push_str(&format!("\n`#{}` {} by {} on {}: {}",
+                case.id, case.action, user_name(case.moderator_id), format_time(case.created_at), truncate(reason, 100)));
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the most recent moderation cases against a user."
+    }
+
+    fn usage(&self) -> &str {
+        "<user>"
+    }
+
+    fn category(&self) -> &str {
+        "moderation"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn new_case<'a>(action: &'a str, target: u64, expires_at: Option<i64>) -> NewCase<'a> {
+        NewCase {
+            action: action,
+            moderator_id: UserId(1),
+            target_id: Some(UserId(target)),
+            reason: "",
+            role_id: None,
+            expires_at: expires_at,
+        }
+    }
+
+    #[test]
+    fn cases_are_numbered_per_guild() {
+        let conn = db::open(":memory:").unwrap();
+
+        assert_eq!(add_case(&conn, GuildId(1), &new_case("warn", 10, None), 0).unwrap(), 1);
+        assert_eq!(add_case(&conn, GuildId(1), &new_case("kick", 10, None), 0).unwrap(), 2);
+        assert_eq!(add_case(&conn, GuildId(2), &new_case("warn", 10, None), 0).unwrap(), 1);
+        assert_eq!(add_case(&conn, GuildId(1), &new_case("warn", 11, None), 0).unwrap(), 3);
+
+        assert_eq!(get_case(&conn, GuildId(1), 2).unwrap().unwrap().action, "kick");
+        assert!(get_case(&conn, GuildId(2), 2).unwrap().is_none());
+        let ids: Vec<i64> = user_cases(&conn, GuildId(1), UserId(10), 10).unwrap().iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn superseded_cases_are_lifted() {
+        let conn = db::open(":memory:").unwrap();
+
+        add_case(&conn, GuildId(1), &new_case("mute", 10, Some(100)), 0).unwrap();
+        add_case(&conn, GuildId(1), &new_case("mute", 11, Some(100)), 0).unwrap();
+        add_case(&conn, GuildId(1), &new_case("ban", 10, Some(100)), 0).unwrap();
+
+        // Unmuting by hand means the earlier mute doesn't need lifting any more.
+        assert_eq!(supersede(&conn, GuildId(1), UserId(10), Action::Mute).unwrap(), 1);
+        assert!(get_case(&conn, GuildId(1), 1).unwrap().unwrap().lifted);
+        assert!(!get_case(&conn, GuildId(1), 2).unwrap().unwrap().lifted);
+        assert!(!get_case(&conn, GuildId(1), 3).unwrap().unwrap().lifted);
+
+        mark_lifted(&conn, GuildId(1), 3).unwrap();
+        assert!(get_case(&conn, GuildId(1), 3).unwrap().unwrap().lifted);
+        assert_eq!(supersede(&conn, GuildId(1), UserId(10), Action::Ban).unwrap(), 0);
+    }
+}
@@ -0,0 +1,469 @@
+use commands::{Command, CommandError, CommandResult, guild_only, guild_admin_only};
+use commands::group::CommandGroup;
+use db;
+
+use chrono::Utc;
+use typemap;
+
+use serenity::CACHE;
+use serenity::builder::CreateEmbed;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::model::user::User;
+use serenity::utils::{Colour, parse_channel};
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Leaves room below the embed description limit for the rest of the text.
+const MAX_CONTENT_LEN: usize = 1500;
+
+/// Max length of an embed field value.
+const MAX_FIELD_LEN: usize = 1024;
+
+/// A message as it was when we last saw it.
+#[derive(Clone)]
+pub struct CachedMessage {
+    pub guild_id: GuildId,
+    pub author_id: UserId,
+    pub author_tag: String,
+    pub content: String,
+    pub attachments: Vec<String>,
+}
+
+struct CacheInner {
+    messages: HashMap<MessageId, CachedMessage>,
+    order: VecDeque<MessageId>,
+}
+
+/// Remembers the most recent guild messages, so the log can show what a
+/// message said after it was edited or deleted.
+pub struct MessageCache {
+    max: usize,
+    inner: Mutex<CacheInner>,
+}
+
+impl typemap::Key for MessageCache {
+    type Value = Arc<MessageCache>;
+}
+
+impl MessageCache {
+    pub fn new(max: usize) -> MessageCache {
+        MessageCache {
+            max: max,
+            inner: Mutex::new(CacheInner {
+                messages: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn insert(&self, id: MessageId, message: CachedMessage) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.messages.insert(id, message).is_none() {
+            inner.order.push_back(id);
+        }
+        while inner.order.len() > self.max {
+            if let Some(old) = inner.order.pop_front() {
+                inner.messages.remove(&old);
+            }
+        }
+    }
+
+    /// Removes a message from the cache, returning it if it was there.
+    pub fn remove(&self, id: MessageId) -> Option<CachedMessage> {
+        let mut inner = self.inner.lock().unwrap();
+        let message = inner.messages.remove(&id)?;
+        inner.order.retain(|&m| m != id);
+        Some(message)
+    }
+
+    /// Updates the content of a message, returning what it was before the edit.
+    pub fn update(&self, id: MessageId, content: &str) -> Option<CachedMessage> {
+        let mut inner = self.inner.lock().unwrap();
+        let message = inner.messages.get_mut(&id)?;
+        let old = message.clone();
+        message.content = content.to_string();
+        Some(old)
+    }
+}
+
+fn message_cache(ctx: &Context) -> Option<Arc<MessageCache>> {
+    let data = ctx.data.lock();
+    data.get::<MessageCache>().map(Arc::clone)
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() > max {
+        let mut text: String = text.chars().take(max - 3).collect();
+        text.push_str("...");
+        text
+    }
+    else {
+        text.to_string()
+    }
+}
+
+fn describe_content(message: &CachedMessage) -> String {
+    let mut text = if message.content.is_empty() {
+        "*No text*".to_string()
+    }
+    else {
+        message.content.clone()
+    };
+    for url in message.attachments.iter() {
+        text.push('\n');
+        text.push_str(url);
+    }
+    truncate(&text, MAX_CONTENT_LEN)
+}
+
+fn guild_of_channel(channel_id: ChannelId) -> Option<GuildId> {
+    CACHE.read().guild_channel(channel_id).map(|c| c.read().guild_id)
+}
+
+fn log_channel(ctx: &Context, guild_id: GuildId) -> Result<Option<ChannelId>, CommandError> {
+    if let Some(db) = db::get(ctx) {
+        let conn = db.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT channel_id FROM modlog_channels WHERE guild_id = ?1")?;
+        let mut rows = stmt.query(&[&(guild_id.0 as i64)])?;
+        match rows.next() {
+            Some(row) => Ok(Some(ChannelId(row?.get::<_, i64>(0) as u64))),
+            None => Ok(None),
+        }
+    }
+    else {
+        Ok(None)
+    }
+}
+
+fn set_log_channel(ctx: &Context, guild_id: GuildId, channel_id: Option<ChannelId>) -> Result<(), CommandError> {
+    let db = match db::get(ctx) {
+        Some(db) => db,
+        None => return Err(CommandError::Other("No database connection".to_string())),
+    };
+    let conn = db.lock().unwrap();
+    match channel_id {
+        Some(channel_id) => conn.execute(
+            "INSERT OR REPLACE INTO modlog_channels (guild_id, channel_id) VALUES (?1, ?2)",
+            &[&(guild_id.0 as i64), &(channel_id.0 as i64)])?,
+        None => conn.execute("DELETE FROM modlog_channels WHERE guild_id = ?1", &[&(guild_id.0 as i64)])?,
+    };
+    Ok(())
+}
+
+/// Posts an embed to the moderation log of a guild, if it has one.
+fn post<F>(ctx: &Context, guild_id: GuildId, title: &str, colour: Colour, f: F)
+    where F: FnOnce(CreateEmbed) -> CreateEmbed
+{
+    let channel_id = match log_channel(ctx, guild_id) {
+        Ok(Some(channel_id)) => channel_id,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Could not get the moderation log channel: {}", e);
+            return;
+        }
+    };
+
+    let result = channel_id.send_message(|m| m.embed(|e| {
+        f(e.title(title).colour(colour).timestamp(&Utc::now()))
+    }));
+    if let Err(e) = result {
+        warn!("Could not post to the moderation log of guild {}: {}", guild_id, e);
+    }
+}
+
+fn user_line(user: &User) -> String {
+    format!("{} {}", user.mention(), user.tag())
+}
+
+fn role_names(roles: &[RoleId]) -> String {
+    roles.iter().map(|r| r.mention()).collect::<Vec<_>>().join(", ")
+}
+
+/// Remembers a message so it can be shown if it gets edited or deleted.
+pub fn message_created(ctx: &Context, msg: &Message) {
+    if msg.author.bot {
+        return;
+    }
+
+    let (cache, guild_id) = match (message_cache(ctx), msg.guild_id()) {
+        (Some(cache), Some(guild_id)) => (cache, guild_id),
+        _ => return,
+    };
+
+    cache.insert(msg.id, CachedMessage {
+        guild_id: guild_id,
+        author_id: msg.author.id,
+        author_tag: msg.author.tag(),
+        content: msg.content.clone(),
+        attachments: msg.attachments.iter().map(|a| a.url.clone()).collect(),
+    });
+}
+
+pub fn message_updated(ctx: &Context, update: &MessageUpdateEvent) {
+    let content = match update.content {
+        Some(ref content) => content,
+        None => return,
+    };
+
+    let old = message_cache(ctx).and_then(|cache| cache.update(update.id, content));
+    if let Some(ref old) = old {
+        if old.content == *content {
+            return;
+        }
+    }
+
+    if update.author.as_ref().map_or(false, |a| a.bot) {
+        return;
+    }
+
+    let guild_id = match old.as_ref().map(|m| m.guild_id).or_else(|| guild_of_channel(update.channel_id)) {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    let author = match (old.as_ref(), update.author.as_ref()) {
+        (_, Some(author)) => user_line(author),
+        (Some(old), None) => format!("{} {}", old.author_id.mention(), old.author_tag),
+        (None, None) => "Unknown".to_string(),
+    };
+
+    post(ctx, guild_id, "Message edited", Colour::gold(), |e| {
+        e.description(&format!("{} in {}", author, update.channel_id.mention()))
+            .field("Before", &old.map(|m| truncate(&m.content, MAX_FIELD_LEN)).unwrap_or_else(|| "*Not cached*".to_string()), false)
+            .field("After", &truncate(if content.is_empty() { "*No text*" } else { content }, MAX_FIELD_LEN), false)
+            .footer(|f| f.text(&format!("Message ID: {}", update.id)))
+    });
+}
+
+pub fn message_deleted(ctx: &Context, channel_id: ChannelId, message_id: MessageId) {
+    // Bot messages are never cached, so an uncached deletion is most likely one of them
+    // (or too old to say anything useful about) and isn't worth logging.
+    let m = match message_cache(ctx).and_then(|cache| cache.remove(message_id)) {
+        Some(m) => m,
+        None => return,
+    };
+
+    let description = format!("{} {} in {}\n{}", m.author_id.mention(), m.author_tag, channel_id.mention(), describe_content(&m));
+    post(ctx, m.guild_id, "Message deleted", Colour::red(), |e| {
+        e.description(&description)
+            .footer(|f| f.text(&format!("Message ID: {}", message_id)))
+    });
+}
+
+pub fn messages_deleted(ctx: &Context, channel_id: ChannelId, message_ids: &[MessageId]) {
+    let cache = message_cache(ctx);
+    let cached: Vec<CachedMessage> = message_ids.iter()
+        .filter_map(|&id| cache.as_ref().and_then(|c| c.remove(id)))
+        .collect();
+    let guild_id = match cached.first().map(|m| m.guild_id).or_else(|| guild_of_channel(channel_id)) {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    let mut description = format!("{} messages deleted in {}", message_ids.len(), channel_id.mention());
+    for m in cached.iter() {
+        let line = format!("\n**{}**: {}", m.author_tag, truncate(&m.content, 200));
+        if description.len() + line.len() > MAX_CONTENT_LEN {
+            description.push_str("\n...");
+            break;
+        }
+        description.push_str(&line);
+    }
+    post(ctx, guild_id, "Messages deleted", Colour::red(), |e| e.description(&description));
+}
+
+pub fn member_joined(ctx: &Context, guild_id: GuildId, member: &Member) {
+    let user = member.user.read().clone();
+    let created = user.created_at().format("%Y-%m-%d").to_string();
+    post(ctx, guild_id, "Member joined", Colour::dark_green(), |e| {
+        e.description(&format!("{}\nAccount created {}", user_line(&user), created))
+            .footer(|f| f.text(&format!("User ID: {}", user.id)))
+    });
+}
+
+pub fn member_left(ctx: &Context, guild_id: GuildId, user: &User) {
+    post(ctx, guild_id, "Member left", Colour::dark_grey(), |e| {
+        e.description(&user_line(user))
+            .footer(|f| f.text(&format!("User ID: {}", user.id)))
+    });
+}
+
+pub fn member_updated(ctx: &Context, old: Option<&Member>, new: &Member) {
+    let old = match old {
+        Some(old) => old,
+        None => return,
+    };
+    let user = new.user.read().clone();
+
+    if old.nick != new.nick {
+        let nick = |n: &Option<String>| n.clone().unwrap_or_else(|| "*None*".to_string());
+        post(ctx, new.guild_id, "Nickname changed", Colour::blue(), |e| {
+            e.description(&user_line(&user))
+                .field("Before", &nick(&old.nick), true)
+                .field("After", &nick(&new.nick), true)
+                .footer(|f| f.text(&format!("User ID: {}", user.id)))
+        });
+    }
+
+    let added: Vec<RoleId> = new.roles.iter().filter(|r| !old.roles.contains(r)).cloned().collect();
+    let removed: Vec<RoleId> = old.roles.iter().filter(|r| !new.roles.contains(r)).cloned().collect();
+    if !added.is_empty() || !removed.is_empty() {
+        post(ctx, new.guild_id, "Roles changed", Colour::blue(), |e| {
+            let e = e.description(&user_line(&user));
+            let e = if added.is_empty() { e } else { e.field("Added", &role_names(&added), true) };
+            let e = if removed.is_empty() { e } else { e.field("Removed", &role_names(&removed), true) };
+            e.footer(|f| f.text(&format!("User ID: {}", user.id)))
+        });
+    }
+}
+
+pub fn member_banned(ctx: &Context, guild_id: GuildId, user: &User) {
+    post(ctx, guild_id, "Member banned", Colour::dark_red(), |e| {
+        e.description(&user_line(user))
+            .footer(|f| f.text(&format!("User ID: {}", user.id)))
+    });
+}
+
+pub fn member_unbanned(ctx: &Context, guild_id: GuildId, user: &User) {
+    post(ctx, guild_id, "Member unbanned", Colour::dark_teal(), |e| {
+        e.description(&user_line(user))
+            .footer(|f| f.text(&format!("User ID: {}", user.id)))
+    });
+}
+
+pub struct ModLogStatus;
+
+impl Command for ModLogStatus {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        match log_channel(ctx, msg.guild_id().unwrap())? {
+            Some(channel_id) => msg.reply(&format!("The moderation log is posted in {}.", channel_id.mention()))?,
+            None => msg.reply("The moderation log is turned off in this server.")?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows where the moderation log is posted."
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+pub struct ModLogSet;
+
+impl Command for ModLogSet {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_admin_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let channel_id = match args.get(1).and_then(|arg| parse_channel(arg).or_else(|| arg.parse().ok())) {
+            Some(id) => ChannelId(id),
+            None => msg.channel_id,
+        };
+        if guild_of_channel(channel_id) != msg.guild_id() {
+            msg.reply("That channel isn't in this server.")?;
+            return Ok(());
+        }
+
+        set_log_channel(ctx, msg.guild_id().unwrap(), Some(channel_id))?;
+        msg.reply(&format!("The moderation log will be posted in {}.", channel_id.mention()))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets the channel to post the moderation log in, defaults to this channel."
+    }
+
+    fn usage(&self) -> &str {
+        "[channel]"
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+pub struct ModLogOff;
+
+impl Command for ModLogOff {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_admin_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        set_log_channel(ctx, msg.guild_id().unwrap(), None)?;
+        msg.reply("Turned off the moderation log.")?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Turns off the moderation log in this server."
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+/// Creates the `modlog` command group for setting up the moderation log in a guild.
+pub fn modlog_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Logs member joins and leaves, message edits and deletes, nickname and role changes and bans.");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_command("status", ModLogStatus);
+    group.add_command("set", ModLogSet);
+    group.add_command("off", ModLogOff);
+    group.set_default("status");
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> CachedMessage {
+        CachedMessage {
+            guild_id: GuildId(1),
+            author_id: UserId(3),
+            author_tag: "user#0001".to_string(),
+            content: content.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cache_evicts_oldest() {
+        let cache = MessageCache::new(2);
+        cache.insert(MessageId(1), message("a"));
+        cache.insert(MessageId(2), message("b"));
+        assert_eq!(cache.update(MessageId(2), "c").map(|m| m.content), Some("b".to_string()));
+        cache.insert(MessageId(3), message("d"));
+
+        assert!(cache.remove(MessageId(1)).is_none());
+        assert_eq!(cache.remove(MessageId(2)).map(|m| m.content), Some("c".to_string()));
+        assert!(cache.remove(MessageId(2)).is_none());
+        assert_eq!(cache.remove(MessageId(3)).map(|m| m.content), Some("d".to_string()));
+    }
+
+    #[test]
+    fn content_with_attachments_fits() {
+        let mut m = message(&"a".repeat(2000));
+        m.attachments = vec!["https://cdn.discordapp.com/attachments/1/2/image.png".to_string(); 10];
+        assert_eq!(describe_content(&m).chars().count(), MAX_CONTENT_LEN);
+
+        m.content = String::new();
+        m.attachments.truncate(1);
+        assert_eq!(describe_content(&m), "*No text*\nhttps://cdn.discordapp.com/attachments/1/2/image.png");
+    }
+}
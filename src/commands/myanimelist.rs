@@ -1,16 +1,27 @@
-use commands::{Command, CommandError, CommandResult};
+use commands::{Command, CommandError, CommandResult, Middleware, MessageState, Flow};
+use commands::{guild_only, guild_admin_only};
+use commands::group::CommandGroup;
+use db;
+use metrics::METRICS;
 use reqwest;
 use url;
 use quick_xml;
+use serenity;
 
 use quick_xml::events::Event;
+use regex::Regex;
 
 use serenity::client::Context;
 use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
 use serenity::utils::Colour;
 
 const ANIME_URL: &str = "https://myanimelist.net/api/anime/search.xml";
 const MANGA_URL: &str = "https://myanimelist.net/api/manga/search.xml";
+const ANILIST_URL: &str = "https://graphql.anilist.co";
+
+/// Max amount of links to unfurl in a single message
+const MAX_UNFURLS: usize = 3;
 
 #[derive(Debug, PartialEq)]
 enum ResultFields {
@@ -30,10 +41,9 @@ enum ResultFields {
     EndDate,
     Synopsis,
     Image,
-    Entry,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct Entry {
     pub id: String,
     pub title: String,
@@ -51,6 +61,88 @@ struct Entry {
     pub image: String,
 }
 
+impl Entry {
+    fn clean_up(&mut self) {
+        lazy_static! {
+            static ref REPLACEMENTS: Vec<(&'static str, &'static str)> = vec![
+                ("<br />", ""),
+                ("&#039;", "'"),
+                ("[i]", "*"),
+                ("[/i]", "*"),
+                ("&quot;", "\""),
+                ("&mdash;", "—"),
+                ("&ndash;", "–"),
+            ];
+        }
+        
+        for rep in REPLACEMENTS.iter() {
+            self.synopsis = self.synopsis.replace(rep.0, rep.1);
+        }
+
+        if self.synopsis.len() >= 2048 {
+            let mut end = 2044;
+            while !self.synopsis.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.synopsis.truncate(end);
+            self.synopsis.push_str("...");
+        }
+        if self.english_title.is_empty() {
+            self.english_title = "—".to_string();
+        }
+        if self.title_synonyms.is_empty() {
+            self.title_synonyms = "—".to_string();
+        }
+        if self.score.is_empty() {
+            self.score = "—".to_string();
+        }
+        if self.status.is_empty() {
+            self.status = "—".to_string();
+        }
+        if self.episodes.is_empty() {
+            self.episodes = "—".to_string();
+        }
+        if self.chapters.is_empty() {
+            self.chapters = "—".to_string();
+        }
+        if self.volumes.is_empty() {
+            self.volumes = "—".to_string();
+        }
+        if self.entry_type.is_empty() {
+            self.entry_type = "—".to_string();
+        }
+        if self.start_date.is_empty() {
+            self.start_date = "—".to_string();
+        }
+        if self.end_date.is_empty() {
+            self.end_date = "—".to_string();
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AniListResponse {
+    data: AniListData,
+}
+
+#[derive(Deserialize)]
+struct AniListData {
+    #[serde(rename = "Media")]
+    media: Option<AniListMedia>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AniListMedia {
+    id_mal: Option<u64>,
+    title: AniListTitle,
+}
+
+#[derive(Deserialize)]
+struct AniListTitle {
+    romaji: String,
+}
+
 struct MyAnimeListApi {
     username: String,
     password: String,
@@ -59,12 +151,14 @@ struct MyAnimeListApi {
 impl MyAnimeListApi {
     fn query(&self, url: &str, q: &str) -> reqwest::Result<reqwest::Response> {
         let cli = reqwest::Client::new();
-        cli.get(url::Url::parse_with_params(url, &[("q", q)]).unwrap())
+        let res = cli.get(url::Url::parse_with_params(url, &[("q", q)]).unwrap())
             .basic_auth(self.username.clone(), Some(self.password.clone()))
-            .send()
+            .send();
+        METRICS.record_api_request("myanimelist", &res);
+        res
     }
 
-    fn parse_response(&self, mut response: reqwest::Response) -> Result<Entry, CommandError> {
+    fn parse_entries(&self, mut response: reqwest::Response) -> Result<Vec<Entry>, CommandError> {
         let text = response.text().unwrap();
 
         let mut xml = quick_xml::Reader::from_str(&text);
@@ -74,22 +168,8 @@ impl MyAnimeListApi {
         let mut buf = Vec::new();
         let mut field = ResultFields::None;
         
-        let mut entry = Entry {
-            id: String::new(),
-            title: String::new(),
-            english_title: String::new(),
-            title_synonyms: String::new(),
-            score: String::new(),
-            episodes: String::new(),
-            chapters: String::new(),
-            volumes: String::new(),
-            entry_type: String::new(),
-            status: String::new(),
-            start_date: String::new(),
-            end_date: String::new(),
-            synopsis: String::new(),
-            image: String::new(),
-        };
+        let mut entries = Vec::new();
+        let mut entry = Entry::default();
 
         loop {
             match xml.read_event(&mut buf) {
@@ -160,14 +240,13 @@ impl MyAnimeListApi {
                     }
                 }
                 Ok(Event::End(e)) => {
-                                            
                     if e.name() == b"entry" {
-                        field = ResultFields::Entry;
-                        break; // Break after the first entry
-                    }
-                    else {
-                        field = ResultFields::None;
+                        let mut done = Entry::default();
+                        ::std::mem::swap(&mut entry, &mut done);
+                        done.clean_up();
+                        entries.push(done);
                     }
+                    field = ResultFields::None;
                 },
                 Ok(Event::Eof) => {
                     break;
@@ -177,61 +256,11 @@ impl MyAnimeListApi {
                 },
                 _ => {}
             }
-        }
-        
-        lazy_static! {
-            static ref REPLACEMENTS: Vec<(&'static str, &'static str)> = vec![
-                ("<br />", ""),
-                ("&#039;", "'"),
-                ("[i]", "*"),
-                ("[/i]", "*"),
-                ("&quot;", "\""),
-                ("&mdash;", "—"),
-                ("&ndash;", "–"),
-            ];
-        }
-        
-        for rep in REPLACEMENTS.iter() {
-            entry.synopsis = entry.synopsis.replace(rep.0, rep.1);
-        }
-
-        if entry.synopsis.len() >= 2048 {
-            entry.synopsis.truncate(2044);
-            entry.synopsis.push_str("...");
-        }
-        if entry.english_title.is_empty() {
-            entry.english_title = "—".to_string();
-        }
-        if entry.title_synonyms.is_empty() {
-            entry.title_synonyms = "—".to_string();
-        }
-        if entry.score.is_empty() {
-            entry.score = "—".to_string();
-        }
-        if entry.status.is_empty() {
-            entry.status = "—".to_string();
-        }
-        if entry.episodes.is_empty() {
-            entry.episodes = "—".to_string();
-        }
-        if entry.chapters.is_empty() {
-            entry.chapters = "—".to_string();
-        }
-        if entry.volumes.is_empty() {
-            entry.volumes = "—".to_string();
-        }
-        if entry.entry_type.is_empty() {
-            entry.entry_type = "—".to_string();
-        }
-        if entry.start_date.is_empty() {
-            entry.start_date = "—".to_string();
-        }
-        if entry.end_date.is_empty() {
-            entry.end_date = "—".to_string();
+            buf.clear();
         }
 
-        if field == ResultFields::Entry {
-            Ok(entry)
+        if entries.len() > 0 {
+            Ok(entries)
         }
         else {
             Err(CommandError::Argument("Found no entries in xml response".to_string()))
@@ -242,8 +271,8 @@ impl MyAnimeListApi {
         let res = self.query(ANIME_URL, query)?;
 
         if res.status().is_success() {
-            if let Ok(entry) = self.parse_response(res) {
-                Ok(entry)
+            if let Ok(mut entries) = self.parse_entries(res) {
+                Ok(entries.remove(0))
             }
             else {
                 Err(CommandError::Argument(format!("Could not find anime: {}", query)))
@@ -258,8 +287,8 @@ impl MyAnimeListApi {
         let res = self.query(MANGA_URL, query)?;
 
         if res.status().is_success() {
-            if let Ok(entry) = self.parse_response(res) {
-                Ok(entry)
+            if let Ok(mut entries) = self.parse_entries(res) {
+                Ok(entries.remove(0))
             }
             else {
                 Err(CommandError::Argument(format!("Could not find manga: {}", query)))
@@ -269,6 +298,141 @@ impl MyAnimeListApi {
             Err(CommandError::Other(format!("Failed https request: {}", res.status())))
         }
     }
+
+    /// Looks up an anime by its MAL id. The old api can only search by title,
+    /// so we search for the title and pick out the entry with the right id.
+    pub fn anime_by_id(&self, id: u64, title: Option<&str>) -> Result<Entry, CommandError> {
+        self.find_by_id(ANIME_URL, "anime", id, title)
+    }
+
+    /// Looks up a manga by its MAL id, see `anime_by_id`.
+    pub fn manga_by_id(&self, id: u64, title: Option<&str>) -> Result<Entry, CommandError> {
+        self.find_by_id(MANGA_URL, "manga", id, title)
+    }
+
+    fn find_by_id(&self, url: &str, kind: &str, id: u64, title: Option<&str>) -> Result<Entry, CommandError> {
+        let title = match title {
+            Some(t) => t.to_string(),
+            None => self.title_from_id(kind, id)?,
+        };
+
+        let res = self.query(url, &title)?;
+
+        if res.status().is_success() {
+            let id = id.to_string();
+            match self.parse_entries(res) {
+                Ok(entries) => entries.into_iter()
+                    .find(|e| e.id == id)
+                    .ok_or_else(|| CommandError::Argument(format!("Could not find {} with id {}", kind, id))),
+                Err(_) => Err(CommandError::Argument(format!("Could not find {} with id {}", kind, id))),
+            }
+        }
+        else {
+            Err(CommandError::Other(format!("Failed https request: {}", res.status())))
+        }
+    }
+
+    /// MAL redirects urls without a title to the one with the title
+    /// in it, so we can just grab it from the url we end up on.
+    fn title_from_id(&self, kind: &str, id: u64) -> Result<String, CommandError> {
+        let res = reqwest::get(&format!("https://myanimelist.net/{}/{}/", kind, id));
+        METRICS.record_api_request("myanimelist", &res);
+        let res = res?;
+
+        if !res.status().is_success() {
+            return Err(CommandError::Argument(format!("Could not find {} with id {}", kind, id)));
+        }
+
+        match res.url().path_segments().and_then(|mut p| p.nth(2)) {
+            Some(slug) if !slug.is_empty() => Ok(title_from_slug(slug)),
+            _ => Err(CommandError::Argument(format!("Could not find a title for {} {}", kind, id))),
+        }
+    }
+
+    /// Gets the MAL id and romaji title for an AniList entry.
+    pub fn from_anilist(&self, id: u64) -> Result<(u64, String), CommandError> {
+        let query = json!({
+            "query": "query ($id: Int) { Media(id: $id) { idMal title { romaji } } }",
+            "variables": { "id": id },
+        });
+
+        let res = reqwest::Client::new().post(ANILIST_URL)
+            .json(&query)
+            .send();
+        METRICS.record_api_request("anilist", &res);
+        let mut res = res?;
+
+        if !res.status().is_success() {
+            return Err(CommandError::Other(format!("Failed https request: {}", res.status())));
+        }
+
+        let res: AniListResponse = res.json()?;
+
+        match res.data.media {
+            Some(AniListMedia { id_mal: Some(mal_id), title }) => Ok((mal_id, title.romaji)),
+            Some(_) => Err(CommandError::Argument(format!("AniList entry {} isn't on MyAnimeList", id))),
+            None => Err(CommandError::Argument(format!("Could not find AniList entry {}", id))),
+        }
+    }
+}
+
+fn title_from_slug(slug: &str) -> String {
+    url::percent_encoding::percent_decode(slug.as_bytes())
+        .decode_utf8_lossy()
+        .replace('_', " ")
+}
+
+fn send_anime_embed(channel_id: ChannelId, entry: Entry) -> serenity::Result<Message> {
+    channel_id.send_message(|m| m
+        .embed(|e| e
+            .author(|a| a
+                .name("MyAnimeList")
+                .url("https://myanimelist.net/")
+                .icon_url("https://myanimelist.cdn-dena.com/img/sp/icon/apple-touch-icon-256.png"))
+            .title(&entry.title)
+            .description(&entry.synopsis)
+            .thumbnail(&entry.image)
+            .fields(vec![
+                ("English:", entry.english_title, true),
+                ("Synonyms:", entry.title_synonyms, true),
+                ("Score:", entry.score, true),
+                ("Type:", entry.entry_type, true),
+                ("Status:", entry.status, true),
+                ("Episodes:", entry.episodes, true),
+                ("Start date:", entry.start_date, true),
+                ("End date:", entry.end_date, true)
+            ])
+            .colour(Colour::from_rgb(46, 81, 162))
+            .url(&format!("https://myanimelist.net/anime/{}/", entry.id))
+        )
+    )
+}
+
+fn send_manga_embed(channel_id: ChannelId, entry: Entry) -> serenity::Result<Message> {
+    channel_id.send_message(|m| m
+        .embed(|e| e
+            .author(|a| a
+                .name("MyAnimeList")
+                .url("https://myanimelist.net/")
+                .icon_url("https://myanimelist.cdn-dena.com/img/sp/icon/apple-touch-icon-256.png"))
+            .title(&entry.title)
+            .description(&entry.synopsis)
+            .thumbnail(&entry.image)
+            .fields(vec![
+                ("English:", entry.english_title, true),
+                ("Synonyms:", entry.title_synonyms, true),
+                ("Score:", entry.score, true),
+                ("Type:", entry.entry_type, true),
+                ("Status:", entry.status, true),
+                ("Chapters:", entry.chapters, true),
+                ("Volumes:", entry.volumes, true),
+                ("Start date:", entry.start_date, true),
+                ("End date:", entry.end_date, true)
+            ])
+            .colour(Colour::from_rgb(46, 81, 162))
+            .url(&format!("https://myanimelist.net/manga/{}/", entry.id))
+        )
+    )
 }
 
 pub struct AnimeCommand {
@@ -294,29 +458,7 @@ impl Command for AnimeCommand {
 
         match self.mal.search_anime(&query) {
             Ok(entry) => {
-                let _ = _msg.channel_id.send_message(|m| m
-                    .embed(|e| e
-                        .author(|a| a
-                            .name("MyAnimeList")
-                            .url("https://myanimelist.net/")
-                            .icon_url("https://myanimelist.cdn-dena.com/img/sp/icon/apple-touch-icon-256.png"))
-                        .title(&entry.title)
-                        .description(&entry.synopsis)
-                        .thumbnail(&entry.image)
-                        .fields(vec![
-                            ("English:", entry.english_title, true),
-                            ("Synonyms:", entry.title_synonyms, true),
-                            ("Score:", entry.score, true),
-                            ("Type:", entry.entry_type, true),
-                            ("Status:", entry.status, true),
-                            ("Episodes:", entry.episodes, true),
-                            ("Start date:", entry.start_date, true),
-                            ("End date:", entry.end_date, true)
-                        ])
-                        .colour(Colour::from_rgb(46, 81, 162))
-                        .url(&format!("https://myanimelist.net/anime/{}/", entry.id))
-                    )
-                )?;
+                send_anime_embed(_msg.channel_id, entry)?;
                 Ok(())
             },
             Err(CommandError::Argument(s)) => {
@@ -327,6 +469,18 @@ impl Command for AnimeCommand {
         }
 
     }
+
+    fn description(&self) -> &str {
+        "Searches MyAnimeList for anime."
+    }
+
+    fn usage(&self) -> &str {
+        "<title>"
+    }
+
+    fn category(&self) -> &str {
+        "myanimelist"
+    }
 }
 
 pub struct MangaCommand {
@@ -352,30 +506,7 @@ impl Command for MangaCommand {
 
         match self.mal.search_manga(&query) {
             Ok(entry) => {
-                let _ = _msg.channel_id.send_message(|m| m
-                    .embed(|e| e
-                        .author(|a| a
-                            .name("MyAnimeList")
-                            .url("https://myanimelist.net/")
-                            .icon_url("https://myanimelist.cdn-dena.com/img/sp/icon/apple-touch-icon-256.png"))
-                        .title(&entry.title)
-                        .description(&entry.synopsis)
-                        .thumbnail(&entry.image)
-                        .fields(vec![
-                            ("English:", entry.english_title, true),
-                            ("Synonyms:", entry.title_synonyms, true),
-                            ("Score:", entry.score, true),
-                            ("Type:", entry.entry_type, true),
-                            ("Status:", entry.status, true),
-                            ("Chapters:", entry.chapters, true),
-                            ("Volumes:", entry.volumes, true),
-                            ("Start date:", entry.start_date, true),
-                            ("End date:", entry.end_date, true)
-                        ])
-                        .colour(Colour::from_rgb(46, 81, 162))
-                        .url(&format!("https://myanimelist.net/manga/{}/", entry.id))
-                    )
-                )?;
+                send_manga_embed(_msg.channel_id, entry)?;
                 Ok(())
             },
             Err(CommandError::Argument(s)) => {
@@ -386,4 +517,233 @@ impl Command for MangaCommand {
         }
 
     }
+
+    fn description(&self) -> &str {
+        "Searches MyAnimeList for manga."
+    }
+
+    fn usage(&self) -> &str {
+        "<title>"
+    }
+
+    fn category(&self) -> &str {
+        "myanimelist"
+    }
+}
+
+
+fn unfurl_enabled(ctx: &Context, guild_id: GuildId) -> Result<bool, CommandError> {
+    if let Some(db) = db::get(ctx) {
+        let conn = db.lock().unwrap();
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM unfurl_guilds WHERE guild_id = ?1",
+            &[&(guild_id.0 as i64)],
+            |row| row.get(0))?;
+        Ok(count > 0)
+    }
+    else {
+        Ok(false)
+    }
+}
+
+fn set_unfurl_enabled(ctx: &Context, guild_id: GuildId, enabled: bool) -> Result<(), CommandError> {
+    if let Some(db) = db::get(ctx) {
+        let conn = db.lock().unwrap();
+        if enabled {
+            conn.execute("INSERT OR IGNORE INTO unfurl_guilds (guild_id) VALUES (?1)", &[&(guild_id.0 as i64)])?;
+        }
+        else {
+            conn.execute("DELETE FROM unfurl_guilds WHERE guild_id = ?1", &[&(guild_id.0 as i64)])?;
+        }
+        Ok(())
+    }
+    else {
+        Err(CommandError::Other("No database connection".to_string()))
+    }
+}
+
+/// Posts anime/manga embeds for MyAnimeList and AniList links in guilds that have opted in.
+pub struct LinkUnfurler {
+    mal: MyAnimeListApi,
+    link_re: Regex,
+}
+
+impl LinkUnfurler {
+    pub fn new(user: &str, pass: &str) -> LinkUnfurler {
+        LinkUnfurler {
+            mal: MyAnimeListApi {
+                username: user.to_string(),
+                password: pass.to_string(),
+            },
+            link_re: Regex::new(r#"(?:https?://)?(?:www\.)?(myanimelist\.net|anilist\.co)/(anime|manga)/(\d+)(?:/([^\s/?#>]+))?"#).unwrap(),
+        }
+    }
+
+    fn unfurl(&self, channel_id: ChannelId, site: &str, kind: &str, id: u64, slug: Option<&str>) -> CommandResult {
+        let (id, title) = if site == "anilist.co" {
+            let (mal_id, title) = self.mal.from_anilist(id)?;
+            (mal_id, Some(title))
+        }
+        else {
+            (id, slug.map(title_from_slug))
+        };
+
+        if kind == "anime" {
+            send_anime_embed(channel_id, self.mal.anime_by_id(id, title.as_ref().map(String::as_str))?)?;
+        }
+        else {
+            send_manga_embed(channel_id, self.mal.manga_by_id(id, title.as_ref().map(String::as_str))?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Middleware for LinkUnfurler {
+    fn before(&self, ctx: &mut Context, msg: &Message, state: &mut MessageState) -> Result<Flow, CommandError> {
+        if state.args.is_some() || state.from_interaction {
+            return Ok(Flow::Continue);
+        }
+
+        let guild_id = match msg.guild_id() {
+            Some(id) => id,
+            None => return Ok(Flow::Continue),
+        };
+
+        if !self.link_re.is_match(&msg.content) || !unfurl_enabled(ctx, guild_id)? {
+            return Ok(Flow::Continue);
+        }
+
+        let mut seen = Vec::new();
+        for cap in self.link_re.captures_iter(&msg.content) {
+            let site = cap.get(1).unwrap().as_str();
+            let kind = cap.get(2).unwrap().as_str();
+            let id = match cap.get(3).unwrap().as_str().parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+
+            if seen.contains(&(site, kind, id)) {
+                continue;
+            }
+            if seen.len() >= MAX_UNFURLS {
+                break;
+            }
+            seen.push((site, kind, id));
+
+            match self.unfurl(msg.channel_id, site, kind, id, cap.get(4).map(|m| m.as_str())) {
+                Err(CommandError::Argument(s)) => {
+                    info!("Could not unfurl link: {}", s);
+                },
+                Err(e) => return Err(e),
+                Ok(()) => {},
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+pub struct UnfurlStatus;
+
+impl Command for UnfurlStatus {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        if unfurl_enabled(ctx, msg.guild_id().unwrap())? {
+            msg.reply("Link unfurling is enabled in this server.")?;
+        }
+        else {
+            msg.reply("Link unfurling is disabled in this server.")?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows whether link unfurling is enabled in this server."
+    }
+
+    fn category(&self) -> &str {
+        "myanimelist"
+    }
+}
+
+pub struct UnfurlToggle {
+    enable: bool,
+}
+
+impl Command for UnfurlToggle {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_admin_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        set_unfurl_enabled(ctx, msg.guild_id().unwrap(), self.enable)?;
+        if self.enable {
+            msg.reply("Link unfurling turned on.")?;
+        }
+        else {
+            msg.reply("Link unfurling turned off.")?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        if self.enable {
+            "Turns on link unfurling in this server."
+        }
+        else {
+            "Turns off link unfurling in this server."
+        }
+    }
+
+    fn category(&self) -> &str {
+        "myanimelist"
+    }
+}
+
+/// Creates the `unfurl` command group for turning link unfurling on and off in a guild.
+pub fn unfurl_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Posts info about MyAnimeList and AniList links posted in the server.");
+    group.set_category("myanimelist");
+    group.add_check(guild_only);
+    group.add_command("status", UnfurlStatus);
+    group.add_command("on", UnfurlToggle { enable: true });
+    group.add_command("off", UnfurlToggle { enable: false });
+    group.set_default("status");
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn title_from_slug_decodes() {
+        assert_eq!(title_from_slug("Shingeki_no_Kyojin"), "Shingeki no Kyojin");
+        assert_eq!(title_from_slug("Kimi_no_Na_wa%2E"), "Kimi no Na wa.");
+    }
+
+    #[test]
+    fn unfurler_matches_links() {
+        let unfurler = LinkUnfurler::new("", "");
+        let cap = unfurler.link_re.captures("look https://myanimelist.net/anime/20/Naruto ok").unwrap();
+        assert_eq!(&cap[1], "myanimelist.net");
+        assert_eq!(&cap[2], "anime");
+        assert_eq!(&cap[3], "20");
+        assert_eq!(&cap[4], "Naruto");
+
+        let cap = unfurler.link_re.captures("<https://anilist.co/manga/30013>").unwrap();
+        assert_eq!(&cap[1], "anilist.co");
+        assert_eq!(&cap[2], "manga");
+        assert_eq!(&cap[3], "30013");
+        assert!(cap.get(4).is_none());
+    }
+
+    #[test]
+    fn long_synopsis_is_cut_on_a_char_boundary() {
+        let mut entry = Entry {
+            synopsis: format!("a{}", "é".repeat(1100)),
+            ..Entry::default()
+        };
+        entry.clean_up();
+        assert!(entry.synopsis.len() < 2048);
+        assert!(entry.synopsis.ends_with("é..."));
+    }
 }
@@ -0,0 +1,519 @@
+use commands::{Command, CommandResult, owner_only};
+use config::{Config, ConfigContainer, ConfigSource};
+use framework::{PlankFramework, FrameworkContainer};
+use interactions::SlashCommands;
+
+use rusqlite::{Connection, SQLITE_OPEN_READ_ONLY};
+use rusqlite::types::Value;
+use typemap;
+
+use serenity;
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::client::bridge::gateway::{ShardManager, ShardMessenger};
+use serenity::model::channel::Message;
+use serenity::model::gateway::Game;
+use serenity::model::id::GuildId;
+use serenity::model::user::OnlineStatus;
+use serenity::prelude::Mutex;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Max amount of rows to show from a query.
+const MAX_SQL_ROWS: usize = 20;
+
+/// Leaves some room below Discord's 2000 character limit for formatting.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+/// Sets up the commands and middleware for a config.
+pub type Setup = fn(&mut PlankFramework, &Config);
+
+/// Key for the shard manager in the client data.
+pub struct ShardManagerContainer;
+
+impl typemap::Key for ShardManagerContainer {
+    type Value = Arc<Mutex<ShardManager>>;
+}
+
+/// Key for when the bot was started in the client data.
+pub struct StartTime;
+
+impl typemap::Key for StartTime {
+    type Value = Instant;
+}
+
+/// Replies with the reason and returns from the command if the author isn't an owner.
+macro_rules! require_owner {
+    ($ctx:expr, $msg:expr) => {
+        if let Err(reason) = owner_only($ctx, $msg) {
+            $msg.reply(&reason)?;
+            return Ok(());
+        }
+    };
+}
+
+fn shard_manager(ctx: &Context) -> Option<Arc<Mutex<ShardManager>>> {
+    let data = ctx.data.lock();
+    data.get::<ShardManagerContainer>().map(Arc::clone)
+}
+
+/// Formats a duration as days, hours, minutes and seconds, leaving out the leading zeroes.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, mins, secs)
+    }
+    else if hours > 0 {
+        format!("{}h {}m {}s", hours, mins, secs)
+    }
+    else if mins > 0 {
+        format!("{}m {}s", mins, secs)
+    }
+    else {
+        format!("{}s", secs)
+    }
+}
+
+/// Reads the resident memory of the process. Only works on Linux.
+fn memory_usage() -> Option<String> {
+    let mut status = String::new();
+    File::open("/proc/self/status").and_then(|mut f| f.read_to_string(&mut status)).ok()?;
+    let kb: u64 = status.lines()
+        .find(|l| l.starts_with("VmRSS:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    Some(format!("{:.1} MB", kb as f64 / 1024.0))
+}
+
+/// Gets the text after the command name, without the argument parsing that would strip quotes.
+fn raw_args(msg: &Message) -> &str {
+    msg.content.trim().splitn(2, char::is_whitespace).nth(1).unwrap_or("").trim()
+}
+
+pub struct ReloadCommand {
+    setup: Setup,
+}
+
+impl ReloadCommand {
+    pub fn new(setup: Setup) -> ReloadCommand {
+        ReloadCommand {
+            setup: setup,
+        }
+    }
+}
+
+impl Command for ReloadCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let (source, fw, old_cfg, slash) = {
+            let data = ctx.data.lock();
+            (
+                data.get::<ConfigSource>().cloned(),
+                data.get::<FrameworkContainer>().cloned(),
+                data.get::<ConfigContainer>().cloned(),
+                data.get::<SlashCommands>().cloned(),
+            )
+        };
+
+        let (source, fw, old_cfg) = match (source, fw, old_cfg) {
+            (Some(source), Some(fw), Some(old_cfg)) => (source, fw, old_cfg),
+            _ => {
+                msg.reply("Reloading isn't set up.")?;
+                return Ok(());
+            }
+        };
+
+        let mut cfg = match source.load() {
+            Ok(cfg) => cfg,
+            Err(errors) => {
+                msg.channel_id.say(&format!("The config has errors, keeping the current one:\n```\n{}\n```", errors.join("\n")))?;
+                return Ok(());
+            }
+        };
+
+        let mut new_fw = PlankFramework::new();
+        (self.setup)(&mut new_fw, &cfg);
+        fw.replace(new_fw);
+        info!("Reloaded config from '{}'", source.path.display());
+
+        if let Some(slash) = slash {
+            let app = serenity::http::get_current_application_info()?;
+            if let Err(e) = slash.register(app.id.0, &fw.commands().read().unwrap()) {
+                error!("{}", e);
+            }
+        }
+
+        let mut text = format!("Reloaded the config, {} command(s) registered.", fw.commands().read().unwrap().len());
+        let restart = old_cfg.restart_required(&cfg);
+        if !restart.is_empty() {
+            text.push_str(&format!("\nChanges to {} won't apply until a restart.", restart.join(", ")));
+        }
+        cfg.keep_restart_values(&old_cfg);
+
+        ctx.data.lock().insert::<ConfigContainer>(Arc::new(cfg));
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Reloads the config file and sets up the commands again."
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+pub struct StatusCommand;
+
+impl Command for StatusCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let (start, fw) = {
+            let data = ctx.data.lock();
+            (data.get::<StartTime>().cloned(), data.get::<FrameworkContainer>().cloned())
+        };
+
+        let mut text = String::new();
+        if let Some(start) = start {
+            text.push_str(&format!("Uptime: {}\n", format_duration(start.elapsed())));
+        }
+
+        if let Some(manager) = shard_manager(ctx) {
+            let manager = manager.lock();
+            let runners = manager.runners.lock();
+            let mut shards: Vec<_> = runners.iter().collect();
+            shards.sort_by_key(|&(id, _)| id.0);
+            for (id, info) in shards {
+                match info.latency {
+                    Some(latency) => text.push_str(&format!("Shard {}: {}ms ({})\n",
+                        id.0, latency.as_secs() * 1000 + latency.subsec_nanos() as u64 / 1_000_000, info.stage)),
+                    None => text.push_str(&format!("Shard {}: no heartbeat yet ({})\n", id.0, info.stage)),
+                }
+            }
+        }
+
+        text.push_str(&format!("Guilds: {}\n", CACHE.read().guilds.len()));
+
+        if let Some(fw) = fw {
+            text.push_str(&format!("Commands: {} registered, {} run since start\n",
+                fw.commands().read().unwrap().len(), fw.commands_run()));
+        }
+
+        text.push_str(&format!("Memory: {}", memory_usage().unwrap_or_else(|| "unknown".to_string())));
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows the uptime, shard latencies, guild count, command counts and memory usage."
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+pub struct GuildsCommand;
+
+impl Command for GuildsCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let mut guilds: Vec<(String, GuildId, u64)> = CACHE.read().guilds.values()
+            .map(|g| {
+                let g = g.read();
+                (g.name.clone(), g.id, g.member_count)
+            })
+            .collect();
+        guilds.sort_by(|a, b| a.0.to_lowercase().cmp(&b.0.to_lowercase()));
+
+        let mut text = format!("In {} guild(s):", guilds.len());
+        for (i, &(ref name, id, members)) in guilds.iter().enumerate() {
+            let line = format!("\n    {} ({}) - {} members", name, id, members);
+            if text.len() + line.len() > MAX_MESSAGE_LEN {
+                text.push_str(&format!("\n    ...and {} more", guilds.len() - i));
+                break;
+            }
+            text.push_str(&line);
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the guilds the bot is in."
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+pub struct LeaveCommand;
+
+impl Command for LeaveCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let guild_id = match args.get(1).and_then(|id| id.parse().ok()) {
+            Some(id) => GuildId(id),
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let name = guild_id.find().map(|g| g.read().name.clone()).unwrap_or_else(|| guild_id.to_string());
+        guild_id.leave()?;
+        info!("Left guild '{}' by owner request", name);
+        if msg.guild_id() != Some(guild_id) {
+            msg.reply(&format!("Left {}.", name))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Makes the bot leave a guild."
+    }
+
+    fn usage(&self) -> &str {
+        "<guild id>"
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+pub struct ShutdownCommand;
+
+impl Command for ShutdownCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let manager = match shard_manager(ctx) {
+            Some(manager) => manager,
+            None => {
+                msg.reply("Shutting down isn't set up.")?;
+                return Ok(());
+            }
+        };
+
+        msg.channel_id.say("Shutting down, bye!")?;
+        info!("Shutdown requested by {}", msg.author.tag());
+        // The client returns once the shards are shut down, after which
+        // main waits for the commands that are still running to finish.
+        manager.lock().shutdown_all();
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shuts down the bot after letting running commands finish."
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+fn parse_status(name: &str) -> Option<OnlineStatus> {
+    match name.to_lowercase().as_str() {
+        "online" => Some(OnlineStatus::Online),
+        "idle" => Some(OnlineStatus::Idle),
+        "dnd" => Some(OnlineStatus::DoNotDisturb),
+        "invisible" => Some(OnlineStatus::Invisible),
+        _ => None,
+    }
+}
+
+/// Runs a function with a messenger for every running shard, so presence changes apply to all of them.
+fn for_each_shard<F: Fn(&ShardMessenger)>(ctx: &Context, f: F) {
+    match shard_manager(ctx) {
+        Some(manager) => {
+            let manager = manager.lock();
+            for info in manager.runners.lock().values() {
+                f(&ShardMessenger::new(info.runner_tx.clone()));
+            }
+        },
+        None => f(&ctx.shard),
+    }
+}
+
+pub struct SetGameCommand;
+
+impl Command for SetGameCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let name = raw_args(msg).to_string();
+        if name.is_empty() {
+            for_each_shard(ctx, |shard| shard.set_game(None));
+            msg.reply("Cleared the game.")?;
+        }
+        else {
+            for_each_shard(ctx, |shard| shard.set_game(Some(Game::playing(&name))));
+            msg.reply(&format!("Now playing {}.", name))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets the game the bot is playing, or clears it if no game is given."
+    }
+
+    fn usage(&self) -> &str {
+        "[game]"
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+pub struct SetStatusCommand;
+
+impl Command for SetStatusCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let status = match args.get(1).and_then(|s| parse_status(s)) {
+            Some(status) => status,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        for_each_shard(ctx, |shard| shard.set_status(status));
+        msg.reply(&format!("Status set to {}.", status.name()))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets the online status of the bot."
+    }
+
+    fn usage(&self) -> &str {
+        "<online|idle|dnd|invisible>"
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(s) => s,
+        Value::Blob(b) => format!("<{} byte blob>", b.len()),
+    }
+}
+
+/// Runs a query on a read-only connection and formats the result as text.
+fn run_query(conn: &Connection, query: &str) -> Result<String, ::rusqlite::Error> {
+    let mut stmt = conn.prepare(query)?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let count = columns.len();
+
+    let mut text = columns.join(" | ");
+    let mut rows = stmt.query(&[])?;
+    let mut shown = 0;
+    while let Some(row) = rows.next() {
+        let row = row?;
+        if shown >= MAX_SQL_ROWS {
+            text.push_str("\n...");
+            break;
+        }
+
+        let mut values = Vec::with_capacity(count);
+        for i in 0..count {
+            values.push(format_value(row.get_checked(i as i32)?));
+        }
+        text.push('\n');
+        text.push_str(&values.join(" | "));
+        shown += 1;
+    }
+
+    if text.len() > MAX_MESSAGE_LEN {
+        let mut end = MAX_MESSAGE_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n...");
+    }
+    Ok(text)
+}
+
+pub struct SqlCommand;
+
+impl Command for SqlCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        require_owner!(ctx, msg);
+
+        let query = raw_args(msg);
+        if query.is_empty() {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let path = {
+            let data = ctx.data.lock();
+            match data.get::<ConfigContainer>() {
+                Some(cfg) => cfg.database.clone(),
+                None => return Ok(()),
+            }
+        };
+
+        let result = Connection::open_with_flags(&path, SQLITE_OPEN_READ_ONLY)
+            .and_then(|conn| run_query(&conn, query));
+        match result {
+            Ok(text) => msg.channel_id.say(&format!("```\n{}\n```", text))?,
+            Err(e) => msg.channel_id.say(&format!("```\n{}\n```", e))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Runs a read-only query against the database."
+    }
+
+    fn usage(&self) -> &str {
+        "<query>"
+    }
+
+    fn category(&self) -> &str {
+        "owner"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 61)), "1h 1m 1s");
+        assert_eq!(format_duration(Duration::from_secs(2 * 86400 + 30)), "2d 0h 0m 30s");
+    }
+
+    #[test]
+    fn query_results() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (1, 'x'), (2, NULL);").unwrap();
+        assert_eq!(run_query(&conn, "SELECT a, b FROM t ORDER BY a").unwrap(), "a | b\n1 | x\n2 | NULL");
+    }
+}
@@ -0,0 +1,632 @@
+use commands::{Command, CommandError, CommandResult, database, guild_only, has_permission, parse_period};
+use db;
+use scheduler;
+use scheduler::{Job, JobHandler, NewJob};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite;
+use rusqlite::Connection;
+use serde_json;
+
+use serenity;
+use serenity::CACHE;
+use serenity::builder::CreateEmbed;
+use serenity::client::Context;
+use serenity::http::HttpError;
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::model::id::{ChannelId, MessageId, UserId};
+use serenity::model::permissions::Permissions;
+use serenity::utils::Colour;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+pub const POLL_JOB: &str = "poll.close";
+
+/// The reactions used to vote, one per option.
+const NUMBERS: &[&str] = &[
+    "1\u{fe0f}\u{20e3}", "2\u{fe0f}\u{20e3}", "3\u{fe0f}\u{20e3}", "4\u{fe0f}\u{20e3}", "5\u{fe0f}\u{20e3}",
+    "6\u{fe0f}\u{20e3}", "7\u{fe0f}\u{20e3}", "8\u{fe0f}\u{20e3}", "9\u{fe0f}\u{20e3}", "\u{1f51f}",
+];
+
+/// The longest a poll can stay open.
+const MAX_DURATION: i64 = 30 * 24 * 60 * 60;
+
+/// The longest a question can be, since it's shown as the embed title.
+const MAX_QUESTION_LEN: usize = 256;
+
+/// How many characters the bars in the results are wide.
+const BAR_WIDTH: usize = 20;
+
+pub struct Poll {
+    pub id: i64,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub author_id: UserId,
+    pub question: String,
+    pub options: Vec<String>,
+    pub multi: bool,
+    pub closes_at: Option<i64>,
+    pub closed: bool,
+}
+
+/// The arguments of the poll command.
+#[derive(Debug, PartialEq)]
+pub struct PollArgs {
+    pub question: String,
+    pub options: Vec<String>,
+    pub duration: Option<i64>,
+    pub multi: bool,
+}
+
+/// Reads `"question" "option 1" "option 2" ... [--duration 1h] [--multi]`, without the command name.
+pub fn parse_args(args: &[String]) -> Result<PollArgs, String> {
+    let mut texts = Vec::new();
+    let mut duration = None;
+    let mut multi = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--multi" => multi = true,
+            "--duration" => {
+                let period = args.next().ok_or_else(|| "--duration needs a period like 30m, 12h or 2d.".to_string())?;
+                match parse_period(period) {
+                    Some(secs) if secs <= MAX_DURATION => duration = Some(secs),
+                    Some(_) => return Err("Polls can be open for at most 30 days.".to_string()),
+                    None => return Err(format!("'{}' isn't a period like 30m, 12h or 2d.", period)),
+                }
+            },
+            text => texts.push(text.trim().to_string()),
+        }
+    }
+
+    if texts.iter().any(|t| t.is_empty()) {
+        return Err("The question and options can't be empty.".to_string());
+    }
+    if texts.len() < 3 {
+        return Err("A poll needs a question and at least two options.".to_string());
+    }
+    if texts.len() > NUMBERS.len() + 1 {
+        return Err(format!("A poll can have at most {} options.", NUMBERS.len()));
+    }
+    let question = texts.remove(0);
+    if question.chars().count() > MAX_QUESTION_LEN {
+        return Err(format!("The question can be at most {} characters long.", MAX_QUESTION_LEN));
+    }
+    Ok(PollArgs {
+        question: question,
+        options: texts,
+        duration: duration,
+        multi: multi,
+    })
+}
+
+/// Finds which option a reaction is a vote for.
+fn option_for(emoji: &ReactionType) -> Option<usize> {
+    match *emoji {
+        ReactionType::Unicode(ref name) => {
+            let name = name.replace('\u{fe0f}', "");
+            NUMBERS.iter().position(|n| n.replace('\u{fe0f}', "") == name)
+        },
+        _ => None,
+    }
+}
+
+/// Draws a bar that's filled in proportion to `count` out of `total`.
+pub fn bar(count: i64, total: i64) -> String {
+    let filled = if total > 0 {
+        ((count as f64 / total as f64) * BAR_WIDTH as f64).round() as usize
+    }
+    else {
+        0
+    };
+    let filled = filled.min(BAR_WIDTH);
+    format!("{}{}", "█".repeat(filled), "░".repeat(BAR_WIDTH - filled))
+}
+
+fn poll_from_row(row: &rusqlite::Row) -> Poll {
+    let options: String = row.get(5);
+    Poll {
+        id: row.get(0),
+        channel_id: ChannelId(row.get::<_, i64>(1) as u64),
+        message_id: MessageId(row.get::<_, i64>(2) as u64),
+        author_id: UserId(row.get::<_, i64>(3) as u64),
+        question: row.get(4),
+        options: serde_json::from_str(&options).unwrap_or_default(),
+        multi: row.get(6),
+        closes_at: row.get(7),
+        closed: row.get(8),
+    }
+}
+
+const POLL_COLUMNS: &str = "id, channel_id, message_id, author_id, question, options, multi, closes_at, closed";
+
+pub fn add_poll(conn: &Connection, poll: &Poll, now: i64) -> rusqlite::Result<i64> {
+    let options = serde_json::to_string(&poll.options).unwrap();
+    conn.execute(
+        "INSERT INTO polls (channel_id, message_id, author_id, question, options, multi, created_at, closes_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        &[&(poll.channel_id.0 as i64), &(poll.message_id.0 as i64), &(poll.author_id.0 as i64), &poll.question,
+          &options, &poll.multi, &now, &poll.closes_at])?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_poll(conn: &Connection, id: i64) -> rusqlite::Result<Option<Poll>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM polls WHERE id = ?1", POLL_COLUMNS))?;
+    let mut rows = stmt.query(&[&id])?;
+    match rows.next() {
+        Some(row) => Ok(Some(poll_from_row(&row?))),
+        None => Ok(None),
+    }
+}
+
+pub fn poll_for_message(conn: &Connection, message_id: MessageId) -> rusqlite::Result<Option<Poll>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM polls WHERE message_id = ?1", POLL_COLUMNS))?;
+    let mut rows = stmt.query(&[&(message_id.0 as i64)])?;
+    match rows.next() {
+        Some(row) => Ok(Some(poll_from_row(&row?))),
+        None => Ok(None),
+    }
+}
+
+/// Records a vote, returning the options the user no longer votes for because the poll is single choice.
+pub fn add_vote(conn: &Connection, poll: &Poll, user_id: UserId, option: usize) -> rusqlite::Result<Vec<usize>> {
+    let mut replaced = Vec::new();
+    if !poll.multi {
+        let mut stmt = conn.prepare("SELECT option FROM poll_votes WHERE poll_id = ?1 AND user_id = ?2 AND option != ?3")?;
+        let rows = stmt.query_map(&[&poll.id, &(user_id.0 as i64), &(option as i64)], |row| row.get::<_, i64>(0) as usize)?;
+        for row in rows {
+            replaced.push(row?);
+        }
+        conn.execute("DELETE FROM poll_votes WHERE poll_id = ?1 AND user_id = ?2", &[&poll.id, &(user_id.0 as i64)])?;
+    }
+    conn.execute("INSERT OR IGNORE INTO poll_votes (poll_id, user_id, option) VALUES (?1, ?2, ?3)",
+        &[&poll.id, &(user_id.0 as i64), &(option as i64)])?;
+    Ok(replaced)
+}
+
+pub fn remove_vote(conn: &Connection, poll_id: i64, user_id: UserId, option: usize) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM poll_votes WHERE poll_id = ?1 AND user_id = ?2 AND option = ?3",
+        &[&poll_id, &(user_id.0 as i64), &(option as i64)])?;
+    Ok(())
+}
+
+/// Gets every vote in a poll, as the user and the option they voted for.
+fn recorded_votes(conn: &Connection, poll_id: i64) -> rusqlite::Result<Vec<(UserId, usize)>> {
+    let mut stmt = conn.prepare("SELECT user_id, option FROM poll_votes WHERE poll_id = ?1")?;
+    let rows = stmt.query_map(&[&poll_id], |row| (UserId(row.get::<_, i64>(0) as u64), row.get::<_, i64>(1) as usize))?;
+    rows.collect()
+}
+
+fn replace_votes(conn: &Connection, poll_id: i64, votes: &[(UserId, usize)]) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM poll_votes WHERE poll_id = ?1", &[&poll_id])?;
+    for &(user_id, option) in votes {
+        conn.execute("INSERT OR IGNORE INTO poll_votes (poll_id, user_id, option) VALUES (?1, ?2, ?3)",
+            &[&poll_id, &(user_id.0 as i64), &(option as i64)])?;
+    }
+    Ok(())
+}
+
+/// Works out the votes from who reacted with each option. In a single choice poll, users that reacted
+/// with more than one option keep the vote that was recorded, or else the first option they reacted with.
+fn reconcile(poll: &Poll, reactions: &[Vec<UserId>], recorded: &[(UserId, usize)]) -> Vec<(UserId, usize)> {
+    let votes = reactions.iter().enumerate()
+        .flat_map(|(option, users)| users.iter().map(move |&user_id| (user_id, option)));
+    if poll.multi {
+        return votes.collect();
+    }
+
+    let mut chosen = BTreeMap::new();
+    for (user_id, option) in votes {
+        if !chosen.contains_key(&user_id) || recorded.contains(&(user_id, option)) {
+            chosen.insert(user_id, option);
+        }
+    }
+    chosen.into_iter().collect()
+}
+
+/// Gets who reacted with each option of a poll, leaving out bots.
+fn fetch_reactions(poll: &Poll) -> serenity::Result<Vec<Vec<UserId>>> {
+    let mut reactions = Vec::new();
+    for number in NUMBERS.iter().take(poll.options.len()) {
+        let mut users = Vec::new();
+        let mut after = None;
+        loop {
+            let page = poll.channel_id.reaction_users(poll.message_id, *number, Some(100), after)?;
+            users.extend(page.iter().filter(|user| !user.bot).map(|user| user.id));
+            if page.len() < 100 {
+                break;
+            }
+            after = page.last().map(|user| user.id);
+        }
+        reactions.push(users);
+    }
+    Ok(reactions)
+}
+
+/// Counts the votes for each option, and how many people voted.
+pub fn tally(conn: &Connection, poll: &Poll) -> rusqlite::Result<(Vec<i64>, i64)> {
+    let mut counts = vec![0; poll.options.len()];
+    let mut stmt = conn.prepare("SELECT option, COUNT(*) FROM poll_votes WHERE poll_id = ?1 GROUP BY option")?;
+    let rows = stmt.query_map(&[&poll.id], |row| (row.get::<_, i64>(0), row.get::<_, i64>(1)))?;
+    for row in rows {
+        let (option, count) = row?;
+        if let Some(c) = counts.get_mut(option as usize) {
+            *c = count;
+        }
+    }
+    let voters = conn.query_row("SELECT COUNT(DISTINCT user_id) FROM poll_votes WHERE poll_id = ?1", &[&poll.id], |row| row.get(0))?;
+    Ok((counts, voters))
+}
+
+/// Marks a poll as closed, returning false if it already was.
+fn mark_closed(conn: &Connection, id: i64) -> rusqlite::Result<bool> {
+    Ok(conn.execute("UPDATE polls SET closed = 1 WHERE id = ?1 AND closed = 0", &[&id])? > 0)
+}
+
+fn job_key(id: i64) -> String {
+    format!("poll:{}", id)
+}
+
+fn poll_embed(e: CreateEmbed, poll: &Poll) -> CreateEmbed {
+    let lines: Vec<String> = poll.options.iter().enumerate()
+        .map(|(i, option)| format!("{} {}", NUMBERS[i], option))
+        .collect();
+    let kind = if poll.multi { "Pick any number of options" } else { "Pick one option" };
+    let e = e.title(&poll.question)
+        .description(&lines.join("\n"))
+        .colour(Colour::blurple());
+    match poll.closes_at {
+        Some(closes_at) => e.footer(|f| f.text(&format!("Poll {} · {} · Closes", poll.id, kind)))
+            .timestamp(&DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(closes_at, 0), Utc)),
+        None => e.footer(|f| f.text(&format!("Poll {} · {}", poll.id, kind))),
+    }
+}
+
+fn results_embed(e: CreateEmbed, poll: &Poll, counts: &[i64], voters: i64) -> CreateEmbed {
+    let total: i64 = counts.iter().sum();
+    let lines: Vec<String> = poll.options.iter().zip(counts).enumerate()
+        .map(|(i, (option, &count))| {
+            let percent = if total > 0 { count * 100 / total } else { 0 };
+            format!("{} {}\n`{}` {} vote(s), {}%", NUMBERS[i], option, bar(count, total), count, percent)
+        })
+        .collect();
+    e.title(&poll.question)
+        .description(&lines.join("\n"))
+        .colour(Colour::dark_grey())
+        .footer(|f| f.text(&format!("Poll {} · Closed with {} voter(s)", poll.id, voters)))
+}
+
+/// Closes a poll and edits the results into its message, returning false if it was already closed.
+///
+/// The results are shown even if the poll was already closed, so a retry after a failed edit still fills them in.
+pub fn close_poll(db: &Mutex<Connection>, id: i64) -> Result<bool, CommandError> {
+    let (poll, closed) = {
+        let conn = db.lock().unwrap();
+        let poll = match get_poll(&conn, id)? {
+            Some(poll) => poll,
+            None => return Ok(false),
+        };
+        let closed = mark_closed(&conn, id)?;
+        (poll, closed)
+    };
+
+    // Votes that came in or were taken back while the bot was offline only show up in the reactions.
+    // Once the poll is closed the votes are final, so reactions added since then don't count.
+    let reactions = if closed { Some(fetch_reactions(&poll)) } else { None };
+    let (counts, voters) = {
+        let conn = db.lock().unwrap();
+        match reactions {
+            None => {},
+            Some(Ok(reactions)) => {
+                let votes = reconcile(&poll, &reactions, &recorded_votes(&conn, id)?);
+                replace_votes(&conn, id, &votes)?;
+            },
+            Some(Err(e)) => warn!("Could not read the reactions of poll {}, counting the recorded votes: {}", id, e),
+        }
+        tally(&conn, &poll)?
+    };
+
+    poll.channel_id.edit_message(poll.message_id, |m| m.embed(|e| results_embed(e, &poll, &counts, voters)))?;
+    Ok(closed)
+}
+
+/// Closes polls when their time is up.
+pub struct PollHandler {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl PollHandler {
+    pub fn new(db: Arc<Mutex<Connection>>) -> PollHandler {
+        PollHandler {
+            db: db,
+        }
+    }
+}
+
+impl JobHandler for PollHandler {
+    fn run(&self, job: &Job) -> Result<(), CommandError> {
+        let id = job.payload["id"].as_i64()
+            .ok_or_else(|| CommandError::Other("Poll job without an id".to_string()))?;
+        match close_poll(&self.db, id) {
+            // The message or channel is gone, so there's nothing to show the results in.
+            Err(CommandError::Serenity(serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res))))
+                if res.status.is_client_error() => {
+                warn!("Could not show the results of poll {}: {}", id, res.status);
+                Ok(())
+            },
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+fn own_id() -> UserId {
+    CACHE.read().user.id
+}
+
+/// Records a vote when someone reacts to an open poll, and takes back their other vote if it's single choice.
+pub fn reaction_added(ctx: &Context, reaction: &Reaction) {
+    if reaction.user_id == own_id() {
+        return;
+    }
+    let option = match option_for(&reaction.emoji) {
+        Some(option) => option,
+        None => return,
+    };
+    let db = match db::get(ctx) {
+        Some(db) => db,
+        None => return,
+    };
+
+    let replaced = {
+        let conn = db.lock().unwrap();
+        let poll = match poll_for_message(&conn, reaction.message_id) {
+            Ok(Some(ref poll)) if poll.closed || option >= poll.options.len() => return,
+            Ok(Some(poll)) => poll,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Could not look up poll: {}", e);
+                return;
+            }
+        };
+        match add_vote(&conn, &poll, reaction.user_id, option) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                error!("Could not record vote in poll {}: {}", poll.id, e);
+                return;
+            }
+        }
+    };
+
+    for old in replaced {
+        if let Err(e) = reaction.channel_id.delete_reaction(reaction.message_id, Some(reaction.user_id), NUMBERS[old]) {
+            warn!("Could not remove an old vote from a poll: {}", e);
+        }
+    }
+}
+
+/// Takes back a vote when someone removes their reaction from an open poll.
+pub fn reaction_removed(ctx: &Context, reaction: &Reaction) {
+    let option = match option_for(&reaction.emoji) {
+        Some(option) => option,
+        None => return,
+    };
+    let db = match db::get(ctx) {
+        Some(db) => db,
+        None => return,
+    };
+
+    let conn = db.lock().unwrap();
+    let result = poll_for_message(&conn, reaction.message_id).and_then(|poll| match poll {
+        Some(ref poll) if !poll.closed => remove_vote(&conn, poll.id, reaction.user_id, option),
+        _ => Ok(()),
+    });
+    if let Err(e) = result {
+        error!("Could not remove vote: {}", e);
+    }
+}
+
+pub struct PollCommand;
+
+impl Command for PollCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+        let poll_args = match parse_args(&args[1..]) {
+            Ok(poll_args) => poll_args,
+            Err(reason) => {
+                msg.reply(&format!("{}\nUsage: `{} {}`", reason, args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let scheduler = match scheduler::get(ctx) {
+            Some(scheduler) => scheduler,
+            None => return Err(CommandError::Other("No scheduler".to_string())),
+        };
+
+        let now = Utc::now().timestamp();
+        let mut poll = Poll {
+            id: 0,
+            channel_id: msg.channel_id,
+            message_id: MessageId(0),
+            author_id: msg.author.id,
+            question: poll_args.question,
+            options: poll_args.options,
+            multi: poll_args.multi,
+            closes_at: poll_args.duration.map(|d| now + d),
+            closed: false,
+        };
+
+        // The embed shows the poll id, so the poll gets stored before it's posted.
+        // It's scheduled to close right away too, so that nothing going wrong later can leave it open forever.
+        poll.id = add_poll(&db.lock().unwrap(), &poll, now)?;
+        if let Some(closes_at) = poll.closes_at {
+            scheduler.schedule(NewJob::at(POLL_JOB, closes_at, json!({ "id": poll.id })).key(&job_key(poll.id)))?;
+        }
+        let posted = msg.channel_id.send_message(|m| m.embed(|e| poll_embed(e, &poll)));
+        let posted = match posted {
+            Ok(posted) => posted,
+            Err(e) => {
+                db.lock().unwrap().execute("DELETE FROM polls WHERE id = ?1", &[&poll.id])?;
+                scheduler.cancel_key(&job_key(poll.id))?;
+                return Err(e.into());
+            }
+        };
+        db.lock().unwrap().execute("UPDATE polls SET message_id = ?2 WHERE id = ?1", &[&poll.id, &(posted.id.0 as i64)])?;
+
+        for number in NUMBERS.iter().take(poll.options.len()) {
+            posted.react(*number)?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Starts a poll that people vote in with reactions. With a duration, the results are shown when it closes."
+    }
+
+    fn usage(&self) -> &str {
+        "\"question\" \"option 1\" \"option 2\" ... [--duration 1h] [--multi]"
+    }
+
+    fn category(&self) -> &str {
+        "polls"
+    }
+}
+
+pub struct EndPollCommand;
+
+impl Command for EndPollCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let id = match args.get(1).and_then(|id| id.parse::<i64>().ok()) {
+            Some(id) => id,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let poll = get_poll(&db.lock().unwrap(), id)?;
+        let poll = match poll {
+            Some(ref poll) if poll.closed => {
+                msg.reply(&format!("Poll {} is already closed.", id))?;
+                return Ok(());
+            },
+            Some(poll) => poll,
+            None => {
+                msg.reply(&format!("There's no poll {}.", id))?;
+                return Ok(());
+            }
+        };
+
+        let in_guild = poll.channel_id.get().ok().and_then(|c| c.guild()).map(|c| c.read().guild_id) == msg.guild_id();
+        if !in_guild || (poll.author_id != msg.author.id && !has_permission(msg, Permissions::MANAGE_MESSAGES)) {
+            msg.reply("Only the one who started a poll, or someone who can manage messages, can end it.")?;
+            return Ok(());
+        }
+
+        if close_poll(&db, id)? {
+            if let Some(scheduler) = scheduler::get(ctx) {
+                scheduler.cancel_key(&job_key(id))?;
+            }
+            msg.reply(&format!("Closed poll {}.", id))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Closes a poll early and shows the results."
+    }
+
+    fn usage(&self) -> &str {
+        "<poll id>"
+    }
+
+    fn category(&self) -> &str {
+        "polls"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn args(text: &str) -> Vec<String> {
+        ::framework::PlankFramework::split_args(text)
+    }
+
+    #[test]
+    fn parse_poll_args() {
+        let parsed = parse_args(&args("\"Pizza or tacos?\" Pizza \"Tacos, obviously\" --duration 1h --multi")).unwrap();
+        assert_eq!(parsed, PollArgs {
+            question: "Pizza or tacos?".to_string(),
+            options: vec!["Pizza".to_string(), "Tacos, obviously".to_string()],
+            duration: Some(60 * 60),
+            multi: true,
+        });
+        assert_eq!(parse_args(&args("question yes no")).unwrap().duration, None);
+        assert!(parse_args(&args("\"only one\" option")).is_err());
+        assert!(parse_args(&args("q a b --duration soon")).is_err());
+        assert!(parse_args(&args("q a b --duration 5w")).is_err());
+        assert!(parse_args(&args("q 1 2 3 4 5 6 7 8 9 10 11")).is_err());
+        assert!(parse_args(&args(&format!("{} a b", "é".repeat(MAX_QUESTION_LEN)))).is_ok());
+        assert!(parse_args(&args(&format!("{} a b", "é".repeat(MAX_QUESTION_LEN + 1)))).is_err());
+    }
+
+    #[test]
+    fn reactions_and_bars() {
+        assert_eq!(option_for(&ReactionType::from("1\u{fe0f}\u{20e3}")), Some(0));
+        assert_eq!(option_for(&ReactionType::from("3\u{20e3}")), Some(2));
+        assert_eq!(option_for(&ReactionType::from("\u{1f51f}")), Some(9));
+        assert_eq!(option_for(&ReactionType::from("👍")), None);
+
+        assert_eq!(bar(0, 0), "░".repeat(BAR_WIDTH));
+        assert_eq!(bar(1, 2), format!("{}{}", "█".repeat(BAR_WIDTH / 2), "░".repeat(BAR_WIDTH / 2)));
+        assert_eq!(bar(3, 3), "█".repeat(BAR_WIDTH));
+    }
+
+    #[test]
+    fn votes_are_tallied() {
+        let conn = db::open(":memory:").unwrap();
+        let mut poll = Poll {
+            id: 0,
+            channel_id: ChannelId(1),
+            message_id: MessageId(2),
+            author_id: UserId(3),
+            question: "?".to_string(),
+            options: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            multi: false,
+            closes_at: Some(1000),
+            closed: false,
+        };
+        poll.id = add_poll(&conn, &poll, 0).unwrap();
+        assert_eq!(poll_for_message(&conn, MessageId(2)).unwrap().unwrap().options, poll.options);
+
+        assert!(add_vote(&conn, &poll, UserId(10), 0).unwrap().is_empty());
+        assert_eq!(add_vote(&conn, &poll, UserId(10), 1).unwrap(), vec![0]);
+        add_vote(&conn, &poll, UserId(11), 1).unwrap();
+        add_vote(&conn, &poll, UserId(12), 2).unwrap();
+        remove_vote(&conn, poll.id, UserId(12), 2).unwrap();
+        assert_eq!(tally(&conn, &poll).unwrap(), (vec![0, 2, 0], 2));
+
+        poll.multi = true;
+        add_vote(&conn, &poll, UserId(10), 0).unwrap();
+        assert_eq!(tally(&conn, &poll).unwrap(), (vec![1, 2, 0], 2));
+
+        poll.multi = false;
+        let reactions = vec![vec![UserId(10), UserId(13)], vec![UserId(10), UserId(11)], vec![]];
+        let votes = reconcile(&poll, &reactions, &recorded_votes(&conn, poll.id).unwrap());
+        assert_eq!(votes, vec![(UserId(10), 1), (UserId(11), 1), (UserId(13), 0)]);
+        replace_votes(&conn, poll.id, &votes).unwrap();
+        assert_eq!(tally(&conn, &poll).unwrap(), (vec![1, 2, 0], 3));
+
+        assert!(mark_closed(&conn, poll.id).unwrap());
+        assert!(!mark_closed(&conn, poll.id).unwrap());
+        assert!(get_poll(&conn, poll.id).unwrap().unwrap().closed);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,488 @@
+use commands::{Command, CommandError, CommandResult, database, guild_only, guild_admin_only, parse_reaction};
+use commands::group::CommandGroup;
+use db;
+
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::http;
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::model::guild::{Guild, Role};
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::model::permissions::Permissions;
+use serenity::utils::parse_role;
+
+
+/// How a reaction role behaves when people react.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// Reacting gives the role and taking the reaction away takes the role away.
+    Toggle,
+    /// Like toggle, but only one of the unique roles on a message can be held at a time.
+    Unique,
+    /// Reacting gives the role, which stays when the reaction is taken away.
+    Verify,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Toggle => "toggle",
+            Mode::Unique => "unique",
+            Mode::Verify => "verify",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Mode> {
+        match name {
+            "toggle" => Some(Mode::Toggle),
+            "unique" => Some(Mode::Unique),
+            "verify" => Some(Mode::Verify),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReactionRole {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    /// The emoji the way it was given, like `👍` or `<:name:id>`.
+    pub emoji: String,
+    pub role_id: RoleId,
+    pub mode: Mode,
+}
+
+/// Identifies an emoji regardless of how it was written, so reactions can be matched to the emoji that was set up.
+fn emoji_key(emoji: &ReactionType) -> String {
+    match *emoji {
+        ReactionType::Custom { id, .. } => id.to_string(),
+        ReactionType::Unicode(ref name) => name.replace('\u{fe0f}', ""),
+    }
+}
+
+/// Reads a message given as a link, as `channel id-message id`, or as an id in `channel_id`.
+pub fn parse_message(text: &str, channel_id: ChannelId) -> Option<(ChannelId, MessageId)> {
+    if text.starts_with("https://") {
+        let mut parts = text.trim_end_matches('/').rsplit('/');
+        let message_id = parts.next()?.parse().ok()?;
+        let channel_id = parts.next()?.parse().ok()?;
+        return Some((ChannelId(channel_id), MessageId(message_id)));
+    }
+
+    let mut parts = text.splitn(2, '-');
+    match (parts.next(), parts.next()) {
+        (Some(channel), Some(message)) => Some((ChannelId(channel.parse().ok()?), MessageId(message.parse().ok()?))),
+        (Some(message), None) => Some((channel_id, MessageId(message.parse().ok()?))),
+        _ => None,
+    }
+}
+
+fn reaction_role_from_row(row: &rusqlite::Row) -> ReactionRole {
+    let mode: String = row.get(5);
+    ReactionRole {
+        guild_id: GuildId(row.get::<_, i64>(0) as u64),
+        channel_id: ChannelId(row.get::<_, i64>(1) as u64),
+        message_id: MessageId(row.get::<_, i64>(2) as u64),
+        emoji: row.get(3),
+        role_id: RoleId(row.get::<_, i64>(4) as u64),
+        mode: Mode::parse(&mode).unwrap_or(Mode::Toggle),
+    }
+}
+
+/// Sets up a reaction role, replacing the role for the emoji if the message already had one.
+pub fn add_reaction_role(conn: &Connection, rr: &ReactionRole) -> rusqlite::Result<()> {
+    remove_reaction_role(conn, rr.message_id, &parse_reaction(&rr.emoji))?;
+    conn.execute(
+        "INSERT INTO reaction_roles (guild_id, channel_id, message_id, emoji, role_id, mode) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[&(rr.guild_id.0 as i64), &(rr.channel_id.0 as i64), &(rr.message_id.0 as i64), &rr.emoji,
+          &(rr.role_id.0 as i64), &rr.mode.name()])?;
+    Ok(())
+}
+
+/// Removes the reaction role for an emoji on a message, returning false if there wasn't one.
+pub fn remove_reaction_role(conn: &Connection, message_id: MessageId, emoji: &ReactionType) -> rusqlite::Result<bool> {
+    let key = emoji_key(emoji);
+    let mut removed = false;
+    for rr in message_roles(conn, message_id)? {
+        if emoji_key(&parse_reaction(&rr.emoji)) == key {
+            conn.execute("DELETE FROM reaction_roles WHERE message_id = ?1 AND emoji = ?2", &[&(message_id.0 as i64), &rr.emoji])?;
+            removed = true;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn message_roles(conn: &Connection, message_id: MessageId) -> rusqlite::Result<Vec<ReactionRole>> {
+    let mut stmt = conn.prepare(
+        "SELECT guild_id, channel_id, message_id, emoji, role_id, mode FROM reaction_roles WHERE message_id = ?1")?;
+    let rows = stmt.query_map(&[&(message_id.0 as i64)], reaction_role_from_row)?;
+    rows.collect()
+}
+
+pub fn guild_reaction_roles(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Vec<ReactionRole>> {
+    let mut stmt = conn.prepare(
+        "SELECT guild_id, channel_id, message_id, emoji, role_id, mode FROM reaction_roles \
+         WHERE guild_id = ?1 ORDER BY message_id")?;
+    let rows = stmt.query_map(&[&(guild_id.0 as i64)], reaction_role_from_row)?;
+    rows.collect()
+}
+
+/// The position of the highest role of a member, which decides which roles they can manage.
+pub fn top_position(guild: &Guild, user_id: UserId) -> i64 {
+    match guild.members.get(&user_id) {
+        Some(member) => roles_position(guild, &member.roles),
+        None => 0,
+    }
+}
+
+/// The position of the highest of the given roles.
+pub fn roles_position(guild: &Guild, roles: &[RoleId]) -> i64 {
+    roles.iter()
+        .filter_map(|id| guild.roles.get(id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Checks that a user is allowed to hand out a role, returning the reason if they aren't.
+pub fn can_manage(guild: &Guild, user_id: UserId, role: &Role) -> Result<(), String> {
+    if role.managed || role.id.0 == guild.id.0 {
+        return Err(format!("{} is managed by Discord or an integration, so it can't be handed out.", role.name));
+    }
+    if guild.owner_id == user_id {
+        return Ok(());
+    }
+    if !guild.member_permissions(user_id).contains(Permissions::MANAGE_ROLES) {
+        return Err("That needs the Manage Roles permission.".to_string());
+    }
+    if top_position(guild, user_id) <= role.position {
+        return Err(format!("{} is not below the highest role.", role.name));
+    }
+    Ok(())
+}
+
+/// Looks up the role for a reaction role, and checks that the bot can hand it out.
+fn assignable_role(guild_id: GuildId, role_id: RoleId) -> Option<RoleId> {
+    let guild = guild_id.find()?;
+    let guild = guild.read();
+    let role = guild.roles.get(&role_id)?;
+    match can_manage(&guild, CACHE.read().user.id, role) {
+        Ok(()) => Some(role_id),
+        Err(reason) => {
+            warn!("Can't hand out reaction role {} in guild {}: {}", role_id, guild_id, reason);
+            None
+        }
+    }
+}
+
+fn is_bot(user_id: UserId) -> bool {
+    CACHE.read().user(user_id).map_or(false, |user| user.read().bot)
+}
+
+/// Finds the reaction role that a reaction is for.
+fn find_reaction_role(ctx: &Context, reaction: &Reaction) -> Option<(ReactionRole, Vec<ReactionRole>)> {
+    if reaction.user_id == CACHE.read().user.id || is_bot(reaction.user_id) {
+        return None;
+    }
+    let db = db::get(ctx)?;
+    let roles = match message_roles(&db.lock().unwrap(), reaction.message_id) {
+        Ok(roles) => roles,
+        Err(e) => {
+            error!("Could not look up reaction roles: {}", e);
+            return None;
+        }
+    };
+
+    let key = emoji_key(&reaction.emoji);
+    let rr = roles.iter().find(|rr| emoji_key(&parse_reaction(&rr.emoji)) == key)?.clone();
+    Some((rr, roles))
+}
+
+/// Gives the role for a reaction, and takes away the other unique roles on the message for unique ones.
+pub fn reaction_added(ctx: &Context, reaction: &Reaction) {
+    let (rr, roles) = match find_reaction_role(ctx, reaction) {
+        Some(found) => found,
+        None => return,
+    };
+    let role_id = match assignable_role(rr.guild_id, rr.role_id) {
+        Some(role_id) => role_id,
+        None => return,
+    };
+
+    if let Err(e) = http::add_member_role(rr.guild_id.0, reaction.user_id.0, role_id.0) {
+        warn!("Could not give reaction role {} to {}: {}", role_id, reaction.user_id, e);
+        return;
+    }
+    if rr.mode != Mode::Unique {
+        return;
+    }
+
+    let held: Vec<RoleId> = match rr.guild_id.find() {
+        Some(guild) => guild.read().members.get(&reaction.user_id).map(|m| m.roles.clone()).unwrap_or_default(),
+        None => return,
+    };
+    // Only the unique roles the member has are worth a request each. Without the role,
+    // they most likely don't have the reaction either.
+    let others = roles.iter()
+        .filter(|other| other.mode == Mode::Unique && other.role_id != rr.role_id && held.contains(&other.role_id));
+    for other in others {
+        if assignable_role(rr.guild_id, other.role_id).is_some() {
+            if let Err(e) = http::remove_member_role(rr.guild_id.0, reaction.user_id.0, other.role_id.0) {
+                warn!("Could not take away reaction role {} from {}: {}", other.role_id, reaction.user_id, e);
+            }
+        }
+        let emoji = parse_reaction(&other.emoji);
+        if let Err(e) = reaction.channel_id.delete_reaction(reaction.message_id, Some(reaction.user_id), emoji) {
+            debug!("Could not remove a reaction for a unique role: {}", e);
+        }
+    }
+}
+
+/// Takes away the role for a reaction, unless it's a verify role.
+pub fn reaction_removed(ctx: &Context, reaction: &Reaction) {
+    let rr = match find_reaction_role(ctx, reaction) {
+        Some((ref rr, _)) if rr.mode == Mode::Verify => return,
+        Some((rr, _)) => rr,
+        None => return,
+    };
+    if let Some(role_id) = assignable_role(rr.guild_id, rr.role_id) {
+        if let Err(e) = http::remove_member_role(rr.guild_id.0, reaction.user_id.0, role_id.0) {
+            warn!("Could not take away reaction role {} from {}: {}", role_id, reaction.user_id, e);
+        }
+    }
+}
+
+/// Finds a role by mention, id or name.
+fn find_role(guild: &Guild, text: &str) -> Option<RoleId> {
+    if let Some(id) = parse_role(text).or_else(|| text.parse().ok()) {
+        if guild.roles.contains_key(&RoleId(id)) {
+            return Some(RoleId(id));
+        }
+    }
+    guild.roles.values().find(|role| role.name.eq_ignore_ascii_case(text)).map(|role| role.id)
+}
+
+/// Finds a role by mention, id or name, and checks that both a user and the bot can hand it out,
+/// returning the reason if they can't.
+pub fn find_assignable_role(guild: &Guild, user_id: UserId, text: &str) -> Result<RoleId, String> {
+    let role = match find_role(guild, text).and_then(|id| guild.roles.get(&id)) {
+        Some(role) => role,
+        None => return Err(format!("There's no role called '{}' in this server.", text)),
+    };
+    can_manage(guild, user_id, role)
+        .map_err(|reason| format!("You can't hand out that role: {}", reason))?;
+    can_manage(guild, CACHE.read().user.id, role)
+        .map_err(|reason| format!("I can't hand out that role: {}", reason))?;
+    Ok(role.id)
+}
+
+pub struct AddReactionRole;
+
+impl Command for AddReactionRole {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 4 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let mut role_words = &args[3..];
+        let mut mode = Mode::Toggle;
+        if let Some(m) = role_words.last().and_then(|m| Mode::parse(&m.to_lowercase())) {
+            if role_words.len() > 1 {
+                mode = m;
+                role_words = &role_words[..role_words.len() - 1];
+            }
+        }
+
+        let (channel_id, message_id) = match parse_message(&args[1], msg.channel_id) {
+            Some(ids) => ids,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let role_id = {
+            let guild = match guild_id.find() {
+                Some(guild) => guild,
+                None => return Err(CommandError::Other("Guild not in cache".to_string())),
+            };
+            let guild = guild.read();
+            let checked = if guild.channels.contains_key(&channel_id) {
+                find_assignable_role(&guild, msg.author.id, &role_words.join(" "))
+            }
+            else {
+                Err("That message isn't in this server.".to_string())
+            };
+            match checked {
+                Ok(role_id) => role_id,
+                Err(reason) => {
+                    msg.reply(&reason)?;
+                    return Ok(());
+                }
+            }
+        };
+
+        let message = match channel_id.message(message_id) {
+            Ok(message) => message,
+            Err(_) => {
+                msg.reply("I can't find that message.")?;
+                return Ok(());
+            }
+        };
+        if message.react(parse_reaction(&args[2])).is_err() {
+            msg.reply("I can't react with that, it has to be a single emoji I can use.")?;
+            return Ok(());
+        }
+
+        let rr = ReactionRole {
+            guild_id: guild_id,
+            channel_id: channel_id,
+            message_id: message_id,
+            emoji: args[2].clone(),
+            role_id: role_id,
+            mode: mode,
+        };
+        add_reaction_role(&database(ctx)?.lock().unwrap(), &rr)?;
+        msg.reply(&format!("Reacting with {} now gives {} ({}).", rr.emoji, role_id.mention(), mode.name()))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Makes reacting to a message give a role. Toggle roles go away with the reaction, only one unique role \
+         on a message can be held at a time, and verify roles stay."
+    }
+
+    fn usage(&self) -> &str {
+        "<message link or id> <emoji> <role> [toggle|unique|verify]"
+    }
+}
+
+pub struct RemoveReactionRole;
+
+impl Command for RemoveReactionRole {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let ids = args.get(1).and_then(|m| parse_message(m, msg.channel_id));
+        let (channel_id, message_id, emoji) = match (ids, args.get(2)) {
+            (Some((channel_id, message_id)), Some(emoji)) => (channel_id, message_id, parse_reaction(emoji)),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let conn = db.lock().unwrap();
+        let in_guild = message_roles(&conn, message_id)?.iter().all(|rr| Some(rr.guild_id) == msg.guild_id());
+        if in_guild && remove_reaction_role(&conn, message_id, &emoji)? {
+            if let Err(e) = channel_id.delete_reaction(message_id, None, emoji) {
+                debug!("Could not remove the bot's reaction: {}", e);
+            }
+            msg.reply("Removed the reaction role.")?;
+        }
+        else {
+            msg.reply("There's no reaction role for that emoji on that message.")?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Stops reacting with an emoji from giving a role."
+    }
+
+    fn usage(&self) -> &str {
+        "<message link or id> <emoji>"
+    }
+}
+
+pub struct ListReactionRoles;
+
+impl Command for ListReactionRoles {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let roles = guild_reaction_roles(&database(ctx)?.lock().unwrap(), guild_id)?;
+        if roles.is_empty() {
+            msg.channel_id.say("This server doesn't have any reaction roles.")?;
+            return Ok(());
+        }
+
+        let lines: Vec<String> = roles.iter()
+            .map(|rr| format!("{} `{}-{}` {} → {} ({})", rr.channel_id.mention(), rr.channel_id, rr.message_id,
+                rr.emoji, rr.role_id.mention(), rr.mode.name()))
+            .collect();
+        msg.channel_id.send_message(|m| m.embed(|e| e.title("Reaction roles").description(&lines.join("\n"))))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the reaction roles in this server."
+    }
+}
+
+pub fn reactionrole_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Sets up roles that people can give themselves by reacting to a message.");
+    group.add_command("add", AddReactionRole);
+    group.add_command("remove", RemoveReactionRole);
+    group.add_command("list", ListReactionRoles);
+    group.set_default("list");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_check(guild_admin_only);
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    #[test]
+    fn parse_messages() {
+        let here = ChannelId(1);
+        assert_eq!(parse_message("https://discord.com/channels/10/20/30", here), Some((ChannelId(20), MessageId(30))));
+        assert_eq!(parse_message("https://discordapp.com/channels/10/20/30/", here), Some((ChannelId(20), MessageId(30))));
+        assert_eq!(parse_message("20-30", here), Some((ChannelId(20), MessageId(30))));
+        assert_eq!(parse_message("30", here), Some((here, MessageId(30))));
+        assert_eq!(parse_message("message", here), None);
+    }
+
+    #[test]
+    fn emoji_keys() {
+        assert_eq!(emoji_key(&parse_reaction("<:blob:123>")), "123");
+        assert_eq!(emoji_key(&parse_reaction("<a:blob:123>")), "123");
+        assert_eq!(emoji_key(&parse_reaction("\u{2764}\u{fe0f}")), emoji_key(&parse_reaction("\u{2764}")));
+    }
+
+    #[test]
+    fn store_reaction_roles() {
+        let conn = db::open(":memory:").unwrap();
+        let mut rr = ReactionRole {
+            guild_id: GuildId(1),
+            channel_id: ChannelId(2),
+            message_id: MessageId(3),
+            emoji: "<:blob:123>".to_string(),
+            role_id: RoleId(4),
+            mode: Mode::Unique,
+        };
+        add_reaction_role(&conn, &rr).unwrap();
+        rr.emoji = "<:blob_renamed:123>".to_string();
+        rr.role_id = RoleId(5);
+        add_reaction_role(&conn, &rr).unwrap();
+        add_reaction_role(&conn, &ReactionRole { emoji: "👍".to_string(), mode: Mode::Verify, ..rr.clone() }).unwrap();
+
+        let roles = message_roles(&conn, MessageId(3)).unwrap();
+        assert_eq!(roles.len(), 2);
+        assert!(roles.contains(&rr));
+        assert_eq!(guild_reaction_roles(&conn, GuildId(1)).unwrap().len(), 2);
+
+        assert!(remove_reaction_role(&conn, MessageId(3), &parse_reaction("<:other:123>")).unwrap());
+        assert!(!remove_reaction_role(&conn, MessageId(3), &parse_reaction("<:blob:123>")).unwrap());
+        assert_eq!(message_roles(&conn, MessageId(3)).unwrap()[0].mode, Mode::Verify);
+    }
+}
\ No newline at end of file
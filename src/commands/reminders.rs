@@ -0,0 +1,654 @@
+use commands::{Command, CommandError, CommandResult, database, guild_only, has_permission, sanitize_mentions};
+use commands::group::CommandGroup;
+use commands::owner::format_duration;
+use scheduler;
+use scheduler::{Job, JobHandler, NewJob};
+use tz::{Tz, user_timezone};
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity;
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::http::HttpError;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::model::permissions::Permissions;
+use serenity::utils::{parse_channel, parse_username};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Kind of the scheduled job that delivers a reminder.
+pub const REMINDER_JOB: &str = "reminder";
+
+/// The hour reminders go off at when only a day is given.
+const DEFAULT_HOUR: u32 = 9;
+
+/// How many reminders a user can have waiting at once.
+const MAX_REMINDERS: i64 = 25;
+
+/// Leaves room in the message for the mentions around the text.
+const MAX_TEXT_LEN: usize = 1500;
+
+/// How far ahead reminders can be set.
+const MAX_AHEAD_SECS: i64 = 5 * 365 * 24 * 60 * 60;
+
+const WHEN_EXAMPLES: &str = "Try something like `in 2h30m`, `tomorrow at 9`, `friday 18:00` or `2018-06-01T10:00`.";
+
+fn unit_seconds(unit: &str) -> Option<i64> {
+    match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(60),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(60 * 60),
+        "d" | "day" | "days" => Some(24 * 60 * 60),
+        "w" | "wk" | "wks" | "week" | "weeks" => Some(7 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+/// Parses a word like `2h30m` into seconds.
+fn parse_compact_duration(word: &str) -> Option<i64> {
+    if word.is_empty() {
+        return None;
+    }
+
+    let mut total: i64 = 0;
+    let mut rest = word;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        let amount: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let letters = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit = unit_seconds(&rest[..letters])?;
+        rest = &rest[letters..];
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+    }
+    Some(total)
+}
+
+/// Parses a duration like `2h30m`, `2 hours 30 minutes` or `an hour and 15 mins` from
+/// the start of `words`. Returns the duration in seconds and how many words it used.
+fn parse_duration(words: &[&str]) -> Option<(i64, usize)> {
+    let mut total: i64 = 0;
+    let mut used = 0;
+    while used < words.len() {
+        let start = if used > 0 && words[used] == "and" { used + 1 } else { used };
+
+        let amount = match words.get(start) {
+            Some(&"a") | Some(&"an") => Some(1),
+            Some(word) => word.parse::<i64>().ok(),
+            None => None,
+        };
+        let unit = words.get(start + 1).and_then(|word| unit_seconds(word));
+
+        if let Some(secs) = words.get(start).and_then(|word| parse_compact_duration(word)) {
+            total = total.checked_add(secs)?;
+            used = start + 1;
+        }
+        else if let (Some(amount), Some(unit)) = (amount, unit) {
+            total = total.checked_add(amount.checked_mul(unit)?)?;
+            used = start + 2;
+        }
+        else {
+            break;
+        }
+    }
+
+    if used > 0 {
+        Some((total, used))
+    }
+    else {
+        None
+    }
+}
+
+/// Parses a time of day like `18:00`, `6:30pm`, `9 am` or `noon`. Unless `loose` is set,
+/// a bare number isn't taken as a time, since it could just as well be part of the reminder.
+pub fn parse_time(words: &[&str], loose: bool) -> Option<(NaiveTime, usize)> {
+    let word = *words.first()?;
+    match word {
+        "noon" => return Some((NaiveTime::from_hms(12, 0, 0), 1)),
+        "midnight" => return Some((NaiveTime::from_hms(0, 0, 0), 1)),
+        _ => {},
+    }
+
+    let (text, mut pm, mut used) = if word.ends_with("am") || word.ends_with("pm") {
+        (&word[..word.len() - 2], Some(word.ends_with("pm")), 1)
+    }
+    else {
+        (word, None, 1)
+    };
+    if pm.is_none() {
+        match words.get(1) {
+            Some(&"am") => { pm = Some(false); used = 2; },
+            Some(&"pm") => { pm = Some(true); used = 2; },
+            _ => {},
+        }
+    }
+
+    let (hour, minute): (u32, u32) = match text.find(':') {
+        Some(i) if text.len() - i == 3 => (text[..i].parse().ok()?, text[i + 1..].parse().ok()?),
+        Some(_) => return None,
+        None if loose || pm.is_some() => (text.parse().ok()?, 0),
+        None => return None,
+    };
+    let hour = match pm {
+        Some(_) if hour < 1 || hour > 12 => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0).map(|time| (time, used))
+}
+
+/// Parses an optional `at` followed by a time of day.
+fn parse_at_time(words: &[&str]) -> Option<(NaiveTime, usize)> {
+    if words.first() == Some(&"at") {
+        parse_time(&words[1..], true).map(|(time, used)| (time, used + 1))
+    }
+    else {
+        parse_time(words, false)
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<u32> {
+    match word {
+        "monday" | "mon" => Some(0),
+        "tuesday" | "tue" | "tues" => Some(1),
+        "wednesday" | "wed" => Some(2),
+        "thursday" | "thu" | "thurs" => Some(3),
+        "friday" | "fri" => Some(4),
+        "saturday" | "sat" => Some(5),
+        "sunday" | "sun" => Some(6),
+        _ => None,
+    }
+}
+
+/// Parses when a reminder should go off from the start of `words`, which should be in
+/// lowercase. Times are read in the timezone `tz`. Returns the time as a unix timestamp
+/// and how many words were used.
+fn parse_when(words: &[&str], now: i64, tz: Tz) -> Option<(i64, usize)> {
+    let first = *words.first()?;
+    let today = tz.to_local(now).date();
+    let default_time = NaiveTime::from_hms(DEFAULT_HOUR, 0, 0);
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(&first.to_uppercase()) {
+        return Some((time.timestamp(), 1));
+    }
+    for format in &["%Y-%m-%dt%H:%M:%S", "%Y-%m-%dt%H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(first, format) {
+            return Some((tz.to_utc(time), 1));
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(first, "%Y-%m-%d") {
+        let (time, used) = parse_at_time(&words[1..]).unwrap_or((default_time, 0));
+        return Some((tz.to_utc(date.and_time(time)), used + 1));
+    }
+
+    if first == "in" {
+        let (secs, used) = parse_duration(&words[1..])?;
+        return now.checked_add(secs).map(|when| (when, used + 1));
+    }
+    if let Some((secs, used)) = parse_duration(words) {
+        return now.checked_add(secs).map(|when| (when, used));
+    }
+
+    let skip = if first == "next" { 1 } else { 0 };
+    let day = match words.get(skip).cloned() {
+        Some("today") if skip == 0 => Some((0, false)),
+        Some("tomorrow") if skip == 0 => Some((1, false)),
+        Some(word) => parse_weekday(word).map(|weekday| {
+            let days = (weekday + 7 - today.weekday().num_days_from_monday()) % 7;
+            (days as i64, true)
+        }),
+        None => None,
+    };
+    if let Some((days, weekday)) = day {
+        let date = today + ChronoDuration::days(days);
+        let (time, used) = parse_at_time(&words[skip + 1..]).unwrap_or((default_time, 0));
+        let mut when = tz.to_utc(date.and_time(time));
+        // Asking for a weekday on that same day means next week, unless the time hasn't come yet.
+        if weekday && when <= now {
+            when = tz.to_utc((date + ChronoDuration::days(7)).and_time(time));
+        }
+        return Some((when, skip + used + 1));
+    }
+
+    // A time on its own means the next time the clock shows it.
+    if let Some((time, used)) = parse_at_time(words) {
+        let mut when = tz.to_utc(today.and_time(time));
+        if when <= now {
+            when = tz.to_utc(today.succ().and_time(time));
+        }
+        return Some((when, used));
+    }
+    None
+}
+
+/// A reminder waiting to go off. It's posted in `channel_id` if there is one,
+/// mentioning `target_id`, otherwise it's sent to `target_id` in a DM.
+pub struct Reminder {
+    pub id: i64,
+    pub user_id: UserId,
+    pub channel_id: Option<ChannelId>,
+    pub target_id: Option<UserId>,
+    pub text: String,
+    pub due_at: i64,
+}
+
+fn reminder_from_row(row: &rusqlite::Row) -> Reminder {
+    Reminder {
+        id: row.get(0),
+        user_id: UserId(row.get::<_, i64>(1) as u64),
+        channel_id: row.get::<_, Option<i64>>(2).map(|id| ChannelId(id as u64)),
+        target_id: row.get::<_, Option<i64>>(3).map(|id| UserId(id as u64)),
+        text: row.get(4),
+        due_at: row.get(5),
+    }
+}
+
+pub fn add_reminder(conn: &Connection, reminder: &Reminder, now: i64) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO reminders (user_id, channel_id, target_id, text, created_at, due_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[&(reminder.user_id.0 as i64), &reminder.channel_id.map(|id| id.0 as i64), &reminder.target_id.map(|id| id.0 as i64),
+          &reminder.text, &now, &reminder.due_at])?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_reminder(conn: &Connection, id: i64) -> rusqlite::Result<Option<Reminder>> {
+    let mut stmt = conn.prepare("SELECT id, user_id, channel_id, target_id, text, due_at FROM reminders WHERE id = ?1")?;
+    let mut rows = stmt.query(&[&id])?;
+    match rows.next() {
+        Some(row) => Ok(Some(reminder_from_row(&row?))),
+        None => Ok(None),
+    }
+}
+
+/// Gets the reminders a user has set, soonest first.
+pub fn user_reminders(conn: &Connection, user_id: UserId) -> rusqlite::Result<Vec<Reminder>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, user_id, channel_id, target_id, text, due_at FROM reminders WHERE user_id = ?1 ORDER BY due_at, id")?;
+    let rows = stmt.query_map(&[&(user_id.0 as i64)], |row| reminder_from_row(row))?;
+    let mut reminders = Vec::new();
+    for row in rows {
+        reminders.push(row?);
+    }
+    Ok(reminders)
+}
+
+/// Deletes a reminder, only if it was set by `user_id` if one is given.
+/// Returns whether there was one to delete.
+pub fn delete_reminder(conn: &Connection, id: i64, user_id: Option<UserId>) -> rusqlite::Result<bool> {
+    let n = match user_id {
+        Some(user_id) => conn.execute("DELETE FROM reminders WHERE id = ?1 AND user_id = ?2", &[&id, &(user_id.0 as i64)])?,
+        None => conn.execute("DELETE FROM reminders WHERE id = ?1", &[&id])?,
+    };
+    Ok(n > 0)
+}
+
+fn job_key(id: i64) -> String {
+    format!("{}:{}", REMINDER_JOB, id)
+}
+
+fn deliver(reminder: &Reminder) -> serenity::Result<()> {
+    let text = sanitize_mentions(&reminder.text);
+    let from = |target: UserId| if target == reminder.user_id { "you".to_string() } else { reminder.user_id.mention() };
+    match (reminder.channel_id, reminder.target_id) {
+        (Some(channel_id), Some(target)) => {
+            channel_id.say(&format!("{}, {} asked me to remind you: {}", target.mention(), from(target), text))?;
+        },
+        (Some(channel_id), None) => {
+            channel_id.say(&format!("Reminder from {}: {}", reminder.user_id.mention(), text))?;
+        },
+        (None, Some(target)) => {
+            let from = if target == reminder.user_id { "You".to_string() } else { reminder.user_id.mention() };
+            target.create_dm_channel()?.id.say(&format!("{} asked me to remind you: {}", from, text))?;
+        },
+        (None, None) => {},
+    }
+    Ok(())
+}
+
+/// Delivers reminders when they go off.
+pub struct ReminderHandler {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl ReminderHandler {
+    pub fn new(db: Arc<Mutex<Connection>>) -> ReminderHandler {
+        ReminderHandler {
+            db: db,
+        }
+    }
+}
+
+impl JobHandler for ReminderHandler {
+    fn run(&self, job: &Job) -> Result<(), CommandError> {
+        let id = job.payload["id"].as_i64()
+            .ok_or_else(|| CommandError::Other("Reminder job without an id".to_string()))?;
+        let reminder = match get_reminder(&self.db.lock().unwrap(), id)? {
+            Some(reminder) => reminder,
+            None => return Ok(()),
+        };
+
+        match deliver(&reminder) {
+            Ok(()) => {},
+            // The channel is gone, the user doesn't take DMs... Retrying won't help.
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res))) if res.status.is_client_error() => {
+                warn!("Could not deliver reminder {}: {}", id, res.status)
+            },
+            Err(e) => {
+                if job.attempts < job.max_retries {
+                    return Err(e.into());
+                }
+                warn!("Giving up on delivering reminder {}: {}", id, e);
+            },
+        }
+        delete_reminder(&self.db.lock().unwrap(), id, None)?;
+        Ok(())
+    }
+}
+
+/// Sets a reminder from `words`, which start with when it should go off followed by the text.
+fn set_reminder(ctx: &Context, msg: &Message, words: &[String], channel_id: Option<ChannelId>, target_id: Option<UserId>) -> Result<Option<String>, CommandError> {
+    let db = database(ctx)?;
+    let scheduler = match scheduler::get(ctx) {
+        Some(scheduler) => scheduler,
+        None => return Err(CommandError::Other("No scheduler".to_string())),
+    };
+    let tz = user_timezone(&db.lock().unwrap(), msg.author.id)?;
+
+    let lower: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+    let lower: Vec<&str> = lower.iter().map(String::as_str).collect();
+    let now = Utc::now().timestamp();
+    let (due_at, used) = match parse_when(&lower, now, tz) {
+        Some(when) => when,
+        None => return Ok(Some(format!("I don't understand when that is. {}", WHEN_EXAMPLES))),
+    };
+
+    let mut rest = &words[used..];
+    if rest.len() > 1 && (lower[used] == "to" || lower[used] == "that") {
+        rest = &rest[1..];
+    }
+    let text = rest.join(" ");
+    if text.is_empty() {
+        return Ok(Some("What should I remind about?".to_string()));
+    }
+    if text.chars().count() > MAX_TEXT_LEN {
+        return Ok(Some(format!("That's too long, reminders can be at most {} characters.", MAX_TEXT_LEN)));
+    }
+    if due_at <= now {
+        return Ok(Some("That time has already passed.".to_string()));
+    }
+    if due_at - now > MAX_AHEAD_SECS {
+        return Ok(Some("That's too far ahead, reminders can be at most 5 years away.".to_string()));
+    }
+
+    let id = {
+        let conn = db.lock().unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM reminders WHERE user_id = ?1",
+            &[&(msg.author.id.0 as i64)], |row| row.get(0))?;
+        if count >= MAX_REMINDERS {
+            return Ok(Some(format!("You already have {} reminders, delete some with `reminders delete <id>` first.", count)));
+        }
+        add_reminder(&conn, &Reminder {
+            id: 0,
+            user_id: msg.author.id,
+            channel_id: channel_id,
+            target_id: target_id,
+            text: text,
+            due_at: due_at,
+        }, now)?
+    };
+    scheduler.schedule(NewJob::at(REMINDER_JOB, due_at, json!({ "id": id })).key(&job_key(id)))?;
+
+    msg.reply(&format!("Reminder #{} set for {} (in {}).",
+        id, tz.format(due_at), format_duration(Duration::from_secs((due_at - now) as u64))))?;
+    Ok(None)
+}
+
+pub struct RemindMeCommand;
+
+impl Command for RemindMeCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if args.len() < 3 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        // Reminders set in a server go off in the same channel, ones set in a DM go off in a DM.
+        let channel_id = msg.guild_id().map(|_| msg.channel_id);
+        if let Some(reason) = set_reminder(ctx, msg, &args[1..], channel_id, Some(msg.author.id))? {
+            msg.reply(&reason)?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Reminds you about something later, in this channel or in a DM if you ask in one."
+    }
+
+    fn usage(&self) -> &str {
+        "<when, e.g. in 2h30m, tomorrow at 9 or friday 18:00> <what>"
+    }
+}
+
+pub struct RemindCommand;
+
+impl Command for RemindCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let target = args.get(1).map(String::as_str).unwrap_or("");
+        let (channel_id, target_id) = if let Some(user) = parse_username(target) {
+            (msg.channel_id, Some(UserId(user)))
+        }
+        else if let Some(channel) = parse_channel(target) {
+            let channel_id = ChannelId(channel);
+            let guild_id = CACHE.read().guild_channel(channel_id).map(|c| c.read().guild_id);
+            if guild_id != msg.guild_id() {
+                msg.reply("That channel isn't in this server.")?;
+                return Ok(());
+            }
+            if !has_permission(msg, Permissions::MANAGE_MESSAGES) {
+                msg.reply("You need the Manage Messages permission to set reminders for a channel.")?;
+                return Ok(());
+            }
+            (channel_id, None)
+        }
+        else {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        };
+
+        if args.len() < 4 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+        if let Some(reason) = set_reminder(ctx, msg, &args[2..], Some(channel_id), target_id)? {
+            msg.reply(&reason)?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Reminds someone about something later in this channel, or posts a reminder in another channel."
+    }
+
+    fn usage(&self) -> &str {
+        "<user or channel> <when> <what>"
+    }
+}
+
+fn describe_destination(reminder: &Reminder) -> String {
+    match (reminder.channel_id, reminder.target_id) {
+        (Some(channel_id), Some(target)) if target == reminder.user_id => format!("in {}", channel_id.mention()),
+        (Some(channel_id), Some(target)) => format!("for {} in {}", target.mention(), channel_id.mention()),
+        (Some(channel_id), None) => format!("in {}", channel_id.mention()),
+        (None, _) => "in a DM".to_string(),
+    }
+}
+
+pub struct ListReminders;
+
+impl Command for ListReminders {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let db = database(ctx)?;
+        let (reminders, tz) = {
+            let conn = db.lock().unwrap();
+            (user_reminders(&conn, msg.author.id)?, user_timezone(&conn, msg.author.id)?)
+        };
+        if reminders.is_empty() {
+            msg.reply("You don't have any reminders.")?;
+            return Ok(());
+        }
+
+        let now = Utc::now().timestamp();
+        let mut text = "Your reminders:".to_string();
+        for reminder in reminders.iter() {
+            let preview: String = reminder.text.chars().take(80).collect();
+            let left = format_duration(Duration::from_secs((reminder.due_at - now).max(0) as u64));
+            let line = format!("\n`#{}` {} (in {}) {}: {}",
+                reminder.id, tz.format(reminder.due_at), left, describe_destination(reminder), sanitize_mentions(&preview));
+            if text.len() + line.len() > MAX_TEXT_LEN {
+                text.push_str("\n...");
+                break;
+            }
+            text.push_str(&line);
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the reminders you have set."
+    }
+}
+
+pub struct DeleteReminder;
+
+impl Command for DeleteReminder {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let id = match args.get(1).and_then(|arg| arg.trim_matches('#').parse::<i64>().ok()) {
+            Some(id) => id,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let deleted = delete_reminder(&database(ctx)?.lock().unwrap(), id, Some(msg.author.id))?;
+        if !deleted {
+            msg.reply(&format!("You don't have a reminder #{}.", id))?;
+            return Ok(());
+        }
+        if let Some(scheduler) = scheduler::get(ctx) {
+            scheduler.cancel_key(&job_key(id))?;
+        }
+        msg.reply(&format!("Deleted reminder #{}.", id))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Deletes one of your reminders."
+    }
+
+    fn usage(&self) -> &str {
+        "<id>"
+    }
+}
+
+pub fn reminders_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Lists and deletes your reminders.");
+    group.add_command("list", ListReminders);
+    group.add_command("delete", DeleteReminder);
+    group.set_default("list");
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0).timestamp()
+    }
+
+    fn when(text: &str, tz: Tz) -> Option<(i64, usize)> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        // 2018-05-02 was a wednesday.
+        parse_when(&words, at(2018, 5, 2, 12, 0), tz)
+    }
+
+    #[test]
+    fn parse_durations() {
+        let now = at(2018, 5, 2, 12, 0);
+        assert_eq!(when("in 2h30m to stretch", Tz::utc()), Some((now + 9000, 2)));
+        assert_eq!(when("2 hours and 30 minutes", Tz::utc()), Some((now + 9000, 5)));
+        assert_eq!(when("in an hour", Tz::utc()), Some((now + 3600, 3)));
+        assert_eq!(when("in 2x", Tz::utc()), None);
+        assert_eq!(when("in 9223372036854775807s", Tz::utc()), None);
+        assert_eq!(when("9223372036854775807 seconds", Tz::utc()), None);
+    }
+
+    #[test]
+    fn parse_days_and_times() {
+        let utc = Tz::utc();
+        assert_eq!(when("tomorrow at 9 stand up", utc), Some((at(2018, 5, 3, 9, 0), 3)));
+        assert_eq!(when("tomorrow 5 things", utc), Some((at(2018, 5, 3, 9, 0), 1)));
+        assert_eq!(when("friday 18:00", utc), Some((at(2018, 5, 4, 18, 0), 2)));
+        assert_eq!(when("wednesday 9am", utc), Some((at(2018, 5, 9, 9, 0), 2)));
+        assert_eq!(when("next wed at 3 pm", utc), Some((at(2018, 5, 2, 15, 0), 5)));
+        assert_eq!(when("at 6:30pm", utc), Some((at(2018, 5, 2, 18, 30), 2)));
+        assert_eq!(when("11am", utc), Some((at(2018, 5, 3, 11, 0), 1)));
+        assert_eq!(when("at 25", utc), None);
+        assert_eq!(when("soon", utc), None);
+
+        // Local times are read in the user's timezone.
+        assert_eq!(when("today at 14:30", Tz::Fixed(2 * 3600)), Some((at(2018, 5, 2, 12, 30), 3)));
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        let utc = Tz::utc();
+        assert_eq!(when("2018-06-01T10:00:00Z", utc), Some((at(2018, 6, 1, 10, 0), 1)));
+        assert_eq!(when("2018-06-01T10:00:00+02:00", utc), Some((at(2018, 6, 1, 8, 0), 1)));
+        assert_eq!(when("2018-06-01t10:00", Tz::Fixed(-3600)), Some((at(2018, 6, 1, 11, 0), 1)));
+        assert_eq!(when("2018-06-01 14:30 pay rent", utc), Some((at(2018, 6, 1, 14, 30), 2)));
+        assert_eq!(when("2018-06-01", utc), Some((at(2018, 6, 1, 9, 0), 1)));
+    }
+
+    #[test]
+    fn store_reminders() {
+        let conn = db::open(":memory:").unwrap();
+        let reminder = |user, due_at| Reminder {
+            id: 0,
+            user_id: UserId(user),
+            channel_id: None,
+            target_id: Some(UserId(user)),
+            text: "x".to_string(),
+            due_at: due_at,
+        };
+
+        let late = add_reminder(&conn, &reminder(1, 200), 0).unwrap();
+        let early = add_reminder(&conn, &reminder(1, 100), 0).unwrap();
+        add_reminder(&conn, &reminder(2, 100), 0).unwrap();
+
+        let ids: Vec<i64> = user_reminders(&conn, UserId(1)).unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![early, late]);
+        assert!(!delete_reminder(&conn, late, Some(UserId(2))).unwrap());
+        assert!(delete_reminder(&conn, late, Some(UserId(1))).unwrap());
+        assert!(get_reminder(&conn, late).unwrap().is_none());
+    }
+}
@@ -0,0 +1,414 @@
+use commands::{Command, CommandError, CommandMap, CommandResult, guild_only, guild_admin_only};
+use commands::group::CommandGroup;
+use db;
+use framework::CommandRegistry;
+
+use rusqlite::Connection;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::parse_channel;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Categories that can't be turned off, so admins can't lock themselves out.
+const EXEMPT_CATEGORIES: &[&str] = &["admin", "owner"];
+
+/// Prefix for category names in the settings tables, so they don't clash with command names.
+const CATEGORY_PREFIX: &str = "category:";
+
+/// Name in the settings tables that matches every command.
+const ALL: &str = "*";
+
+/// The command settings of a guild. Names are either a command, a category
+/// prefixed with `category:`, or `*` for every command.
+#[derive(Default)]
+pub struct GuildRules {
+    enabled: HashMap<String, bool>,
+    channels: HashMap<String, Vec<(ChannelId, bool)>>,
+}
+
+impl GuildRules {
+    pub fn load(conn: &Connection, guild_id: GuildId) -> Result<GuildRules, CommandError> {
+        let mut rules = GuildRules::default();
+
+        let mut stmt = conn.prepare("SELECT name, enabled FROM command_settings WHERE guild_id = ?1")?;
+        let rows = stmt.query_map(&[&(guild_id.0 as i64)], |row| (row.get(0), row.get(1)))?;
+        for row in rows {
+            let (name, enabled): (String, bool) = row?;
+            rules.enabled.insert(name, enabled);
+        }
+
+        let mut stmt = conn.prepare("SELECT name, channel_id, allow FROM command_channels WHERE guild_id = ?1")?;
+        let rows = stmt.query_map(&[&(guild_id.0 as i64)], |row| (row.get(0), row.get(1), row.get(2)))?;
+        for row in rows {
+            let (name, channel_id, allow): (String, i64, bool) = row?;
+            rules.channels.entry(name).or_insert_with(Vec::new).push((ChannelId(channel_id as u64), allow));
+        }
+
+        Ok(rules)
+    }
+
+    /// Checks if a command can be used in a channel. The most specific setting wins,
+    /// so a command can be turned on even if its category is turned off.
+    pub fn allows(&self, name: &str, category: &str, channel_id: ChannelId) -> bool {
+        if EXEMPT_CATEGORIES.contains(&category) {
+            return true;
+        }
+
+        let category = format!("{}{}", CATEGORY_PREFIX, category);
+        let keys = [name, &category, ALL];
+
+        if let Some(&enabled) = keys.iter().filter_map(|k| self.enabled.get(*k)).next() {
+            if !enabled {
+                return false;
+            }
+        }
+
+        match keys.iter().filter_map(|k| self.channels.get(*k)).next() {
+            Some(channels) => match channels.iter().find(|&&(id, _)| id == channel_id) {
+                Some(&(_, allow)) => allow,
+                None => !channels.iter().any(|&(_, allow)| allow),
+            },
+            None => true,
+        }
+    }
+}
+
+/// Checks if a command is allowed in the channel a message was sent in.
+/// Messages outside of guilds are always allowed.
+pub fn is_allowed(ctx: &Context, msg: &Message, name: &str, command: &Command) -> bool {
+    let guild_id = match msg.guild_id() {
+        Some(id) => id,
+        None => return true,
+    };
+
+    let db = match db::get(ctx) {
+        Some(db) => db,
+        None => return true,
+    };
+
+    let rules = GuildRules::load(&db.lock().unwrap(), guild_id);
+    match rules {
+        Ok(rules) => rules.allows(name, command.category(), msg.channel_id),
+        Err(e) => {
+            error!("Could not load command settings: {}", e);
+            true
+        }
+    }
+}
+
+/// Resolves a name given by a user to the name used in the settings tables.
+fn resolve_name(commands: &RwLock<CommandMap>, name: &str) -> Result<String, String> {
+    let name = name.to_lowercase();
+    if name == "all" || name == ALL {
+        return Ok(ALL.to_string());
+    }
+
+    let commands = commands.read().unwrap();
+
+    let category = if let Some(command) = commands.get(&name) {
+        command.category().to_string()
+    }
+    else {
+        let category = if name.starts_with(CATEGORY_PREFIX) {
+            name[CATEGORY_PREFIX.len()..].to_string()
+        }
+        else {
+            name.clone()
+        };
+        if !commands.values().any(|c| c.category() == category) {
+            return Err(format!("No command or category named '{}'.", name));
+        }
+        category
+    };
+
+    if EXEMPT_CATEGORIES.contains(&category.as_str()) {
+        return Err(format!("'{}' can't be turned off or restricted.", name));
+    }
+
+    if commands.contains_key(&name) {
+        Ok(name)
+    }
+    else {
+        Ok(format!("{}{}", CATEGORY_PREFIX, category))
+    }
+}
+
+fn display_name(name: &str) -> String {
+    if name == ALL {
+        "all commands".to_string()
+    }
+    else if name.starts_with(CATEGORY_PREFIX) {
+        format!("category `{}`", &name[CATEGORY_PREFIX.len()..])
+    }
+    else {
+        format!("`{}`", name)
+    }
+}
+
+/// Resolves the names in the arguments, or gives the reason why one of them can't be used.
+fn resolve_names(ctx: &Context, names: &[String]) -> Result<Vec<String>, String> {
+    let registry = {
+        let data = ctx.data.lock();
+        match data.get::<CommandRegistry>() {
+            Some(registry) => Arc::clone(registry),
+            None => return Err("Commands can't be configured right now.".to_string()),
+        }
+    };
+    names.iter().map(|name| resolve_name(&registry, name)).collect()
+}
+
+fn with_db<T, F: FnOnce(&Connection) -> Result<T, CommandError>>(ctx: &Context, f: F) -> Result<T, CommandError> {
+    match db::get(ctx) {
+        Some(db) => f(&db.lock().unwrap()),
+        None => Err(CommandError::Other("No database connection".to_string())),
+    }
+}
+
+pub struct ToggleCommand {
+    enable: bool,
+}
+
+impl Command for ToggleCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 2 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let names = match resolve_names(ctx, &args[1..]) {
+            Ok(names) => names,
+            Err(reason) => {
+                msg.reply(&reason)?;
+                return Ok(());
+            }
+        };
+
+        with_db(ctx, |conn| {
+            for name in names.iter() {
+                conn.execute(
+                    "INSERT OR REPLACE INTO command_settings (guild_id, name, enabled) VALUES (?1, ?2, ?3)",
+                    &[&(guild_id.0 as i64), name, &self.enable])?;
+            }
+            Ok(())
+        })?;
+
+        let names: Vec<String> = names.iter().map(|n| display_name(n)).collect();
+        msg.reply(&format!("{} {}.", if self.enable { "Enabled" } else { "Disabled" }, names.join(", ")))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        if self.enable {
+            "Turns on commands or categories in this server."
+        }
+        else {
+            "Turns off commands or categories in this server."
+        }
+    }
+
+    fn usage(&self) -> &str {
+        "<command|category|all>..."
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+pub struct ChannelRuleCommand {
+    allow: bool,
+}
+
+impl Command for ChannelRuleCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 3 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let name = match resolve_names(ctx, &args[1..2]) {
+            Ok(mut names) => names.remove(0),
+            Err(reason) => {
+                msg.reply(&reason)?;
+                return Ok(());
+            }
+        };
+
+        let mut channels = Vec::new();
+        for arg in args[2..].iter() {
+            match parse_channel(arg).or_else(|| arg.parse().ok()) {
+                Some(id) => channels.push(ChannelId(id)),
+                None => {
+                    msg.reply(&format!("'{}' is not a channel.", arg))?;
+                    return Ok(());
+                }
+            }
+        }
+
+        with_db(ctx, |conn| {
+            for channel_id in channels.iter() {
+                conn.execute(
+                    "INSERT OR REPLACE INTO command_channels (guild_id, name, channel_id, allow) VALUES (?1, ?2, ?3, ?4)",
+                    &[&(guild_id.0 as i64), &name, &(channel_id.0 as i64), &self.allow])?;
+            }
+            Ok(())
+        })?;
+
+        let mentions: Vec<String> = channels.iter().map(|c| c.mention()).collect();
+        if self.allow {
+            msg.reply(&format!("{} can be used in {}.", display_name(&name), mentions.join(", ")))?;
+        }
+        else {
+            msg.reply(&format!("{} can't be used in {}.", display_name(&name), mentions.join(", ")))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        if self.allow {
+            "Only lets commands or categories be used in the given channels."
+        }
+        else {
+            "Blocks commands or categories from being used in the given channels."
+        }
+    }
+
+    fn usage(&self) -> &str {
+        "<command|category|all> <channel>..."
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+pub struct ResetCommand;
+
+impl Command for ResetCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 2 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let name = match resolve_names(ctx, &args[1..2]) {
+            Ok(mut names) => names.remove(0),
+            Err(reason) => {
+                msg.reply(&reason)?;
+                return Ok(());
+            }
+        };
+
+        with_db(ctx, |conn| {
+            conn.execute("DELETE FROM command_settings WHERE guild_id = ?1 AND name = ?2", &[&(guild_id.0 as i64), &name])?;
+            conn.execute("DELETE FROM command_channels WHERE guild_id = ?1 AND name = ?2", &[&(guild_id.0 as i64), &name])?;
+            Ok(())
+        })?;
+
+        msg.reply(&format!("Reset the settings for {}.", display_name(&name)))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Removes the settings and channel rules for a command or category."
+    }
+
+    fn usage(&self) -> &str {
+        "<command|category|all>"
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+pub struct ListCommand;
+
+impl Command for ListCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let rules = with_db(ctx, |conn| GuildRules::load(conn, guild_id))?;
+
+        let mut names: Vec<&String> = rules.enabled.keys().chain(rules.channels.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        if names.is_empty() {
+            msg.reply("All commands are enabled everywhere in this server.")?;
+            return Ok(());
+        }
+
+        let mut text = "Command settings:".to_string();
+        for name in names {
+            text.push_str(&format!("\n    {}", display_name(name)));
+            if let Some(&enabled) = rules.enabled.get(name) {
+                text.push_str(if enabled { " - enabled" } else { " - disabled" });
+            }
+            if let Some(channels) = rules.channels.get(name) {
+                let allowed: Vec<String> = channels.iter().filter(|c| c.1).map(|c| c.0.mention()).collect();
+                let denied: Vec<String> = channels.iter().filter(|c| !c.1).map(|c| c.0.mention()).collect();
+                if !allowed.is_empty() {
+                    text.push_str(&format!(" - only in {}", allowed.join(", ")));
+                }
+                if !denied.is_empty() {
+                    text.push_str(&format!(" - not in {}", denied.join(", ")));
+                }
+            }
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the command settings in this server."
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+/// Creates the `commands` command group for guild admins to manage where commands can be used.
+pub fn settings_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Turns commands on or off and restricts them to channels in this server.");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_check(guild_admin_only);
+    group.add_command("list", ListCommand);
+    group.add_command("enable", ToggleCommand { enable: true });
+    group.add_command("disable", ToggleCommand { enable: false });
+    group.add_command("allow", ChannelRuleCommand { allow: true });
+    group.add_command("deny", ChannelRuleCommand { allow: false });
+    group.add_command("reset", ResetCommand);
+    group.set_default("list");
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let mut rules = GuildRules::default();
+        rules.enabled.insert("category:games".to_string(), false);
+        rules.enabled.insert("roll".to_string(), true);
+        rules.channels.insert("roll".to_string(), vec![(ChannelId(1), true)]);
+        rules.channels.insert(ALL.to_string(), vec![(ChannelId(2), false)]);
+
+        assert!(!rules.allows("roulette", "games", ChannelId(1)));
+        assert!(rules.allows("roll", "games", ChannelId(1)));
+        assert!(!rules.allows("roll", "games", ChannelId(3)));
+        assert!(rules.allows("anime", "myanimelist", ChannelId(1)));
+        assert!(!rules.allows("anime", "myanimelist", ChannelId(2)));
+        assert!(rules.allows("commands", "admin", ChannelId(2)));
+    }
+}
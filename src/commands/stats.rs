@@ -0,0 +1,229 @@
+use commands::{Command, CommandError, CommandResult, Middleware, MessageState, Flow};
+use commands::{guild_only, guild_admin_only, parse_period, user_name};
+use db;
+use scheduler::{Job, NewJob, Scheduler};
+
+use chrono::Utc;
+use rusqlite;
+use rusqlite::Connection;
+use serde_json::Value;
+use typemap;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many commands and users to list in the stats.
+const TOP_COUNT: i64 = 5;
+
+/// Annotation for when the command in a message started running.
+struct StartedAt;
+
+impl typemap::Key for StartedAt {
+    type Value = Instant;
+}
+
+fn duration_ms(duration: Duration) -> i64 {
+    (duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000) as i64
+}
+
+/// Records every command invocation from a message to the database.
+pub struct CommandLog;
+
+impl Middleware for CommandLog {
+    fn before(&self, _ctx: &mut Context, _msg: &Message, state: &mut MessageState) -> Result<Flow, CommandError> {
+        if state.args.is_some() && !state.from_interaction {
+            state.annotations.insert::<StartedAt>(Instant::now());
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn after(&self, ctx: &mut Context, msg: &Message, state: &MessageState) -> Result<Flow, CommandError> {
+        let (args, result) = match (state.args.as_ref(), state.result.as_ref()) {
+            (Some(args), Some(result)) if !state.from_interaction => (args, result),
+            _ => return Ok(Flow::Continue),
+        };
+
+        let duration = state.annotations.get::<StartedAt>()
+            .map(|start| duration_ms(start.elapsed()))
+            .unwrap_or(0);
+        let result = match *result {
+            Ok(()) => "ok",
+            Err(ref e) => e.kind(),
+        };
+
+        if let Some(db) = db::get(ctx) {
+            db.lock().unwrap().execute(
+                "INSERT INTO command_log (command, guild_id, channel_id, user_id, duration_ms, result, at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                &[&args[0], &msg.guild_id().map(|id| id.0 as i64), &(msg.channel_id.0 as i64),
+                  &(msg.author.id.0 as i64), &duration, &result, &Utc::now().timestamp()])?;
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Deletes invocations older than `retention_days`.
+pub fn prune(conn: &Connection, retention_days: u64) -> rusqlite::Result<usize> {
+    let cutoff = Utc::now().timestamp() - (retention_days * 24 * 60 * 60) as i64;
+    conn.execute("DELETE FROM command_log WHERE at < ?1", &[&cutoff]).map(|n| n as usize)
+}
+
+/// Kind of the recurring job that prunes old invocations.
+pub const PRUNE_JOB: &str = "stats.prune";
+
+/// Schedules a job that prunes old invocations every hour.
+pub fn schedule_pruning(scheduler: &Scheduler, db: Arc<Mutex<Connection>>, retention_days: u64) -> rusqlite::Result<()> {
+    scheduler.register(PRUNE_JOB, move |_job: &Job| {
+        match prune(&db.lock().unwrap(), retention_days)? {
+            0 => {},
+            n => info!("Pruned {} old command invocation(s)", n),
+        }
+        Ok(())
+    });
+    let job = NewJob::recurring(PRUNE_JOB, "@hourly", Value::Null).expect("@hourly is a valid schedule");
+    scheduler.schedule(job.key(PRUNE_JOB)).map(|_| ())
+}
+
+struct Usage {
+    user_id: i64,
+    uses: i64,
+    errors: i64,
+}
+
+struct GuildStats {
+    total: i64,
+    errors: i64,
+    commands: Vec<(String, i64, i64)>,
+    users: Vec<Usage>,
+}
+
+fn guild_stats(conn: &Connection, guild_id: GuildId, since: i64) -> rusqlite::Result<GuildStats> {
+    let guild_id = guild_id.0 as i64;
+    let (total, errors): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(result != 'ok'), 0) FROM command_log WHERE guild_id = ?1 AND at >= ?2",
+        &[&guild_id, &since],
+        |row| (row.get(0), row.get(1)))?;
+
+    let mut stmt = conn.prepare(
+        "SELECT command, COUNT(*) AS uses, SUM(result != 'ok') FROM command_log
+         WHERE guild_id = ?1 AND at >= ?2 GROUP BY command ORDER BY uses DESC, command LIMIT ?3")?;
+    let rows = stmt.query_map(&[&guild_id, &since, &TOP_COUNT], |row| (row.get(0), row.get(1), row.get(2)))?;
+    let mut commands = Vec::new();
+    for row in rows {
+        commands.push(row?);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT user_id, COUNT(*) AS uses, SUM(result != 'ok') FROM command_log
+         WHERE guild_id = ?1 AND at >= ?2 GROUP BY user_id ORDER BY uses DESC, user_id LIMIT ?3")?;
+    let rows = stmt.query_map(&[&guild_id, &since, &TOP_COUNT], |row| Usage {
+        user_id: row.get(0),
+        uses: row.get(1),
+        errors: row.get(2),
+    })?;
+    let mut users = Vec::new();
+    for row in rows {
+        users.push(row?);
+    }
+
+    Ok(GuildStats {
+        total: total,
+        errors: errors,
+        commands: commands,
+        users: users,
+    })
+}
+
+fn error_rate(uses: i64, errors: i64) -> String {
+    if uses == 0 {
+        "0%".to_string()
+    }
+    else {
+        format!("{:.1}%", errors as f64 * 100.0 / uses as f64)
+    }
+}
+
+pub struct StatsCommand;
+
+impl Command for StatsCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if let Err(reason) = guild_only(ctx, msg).and_then(|_| guild_admin_only(ctx, msg)) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let period = args.get(1).map(String::as_str).unwrap_or("7d");
+        let (since, label) = if period == "all" {
+            (0, "in total".to_string())
+        }
+        else {
+            match parse_period(period) {
+                Some(secs) => (Utc::now().timestamp() - secs, format!("in the last {}", period)),
+                None => {
+                    msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                    return Ok(());
+                }
+            }
+        };
+
+        let stats = match db::get(ctx) {
+            Some(db) => guild_stats(&db.lock().unwrap(), msg.guild_id().unwrap(), since)?,
+            None => return Err(CommandError::Other("No database connection".to_string())),
+        };
+
+        if stats.total == 0 {
+            msg.reply("No commands have been used here in that period.")?;
+            return Ok(());
+        }
+
+        let mut text = format!("{} command(s) used {}, {} failed.\nTop commands:",
+            stats.total, label, error_rate(stats.total, stats.errors));
+        for &(ref name, uses, errors) in stats.commands.iter() {
+            text.push_str(&format!("\n    `{}` - {} use(s), {} failed", name, uses, error_rate(uses, errors)));
+        }
+        text.push_str("\nTop users:");
+        for usage in stats.users.iter() {
+            text.push_str(&format!("\n    {} - {} use(s), {} failed", user_name(UserId(usage.user_id as u64)), usage.uses, error_rate(usage.uses, usage.errors)));
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows the most used commands, the most active users and error rates in this server."
+    }
+
+    fn usage(&self) -> &str {
+        "[period, e.g. 24h, 7d, 4w or all]"
+    }
+
+    fn category(&self) -> &str {
+        "admin"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_and_pruning() {
+        let conn = db::open(":memory:").unwrap();
+        let now = Utc::now().timestamp();
+        conn.execute_batch(&format!(
+            "INSERT INTO command_log (command, guild_id, channel_id, user_id, duration_ms, result, at) VALUES
+             ('roll', 1, 1, 10, 5, 'ok', {now}), ('roll', 1, 1, 10, 5, 'argument', {now}),
+             ('help', 1, 1, 11, 5, 'ok', {now}), ('roll', 2, 2, 10, 5, 'ok', {now}),
+             ('roll', 1, 1, 10, 5, 'ok', {old})", now = now, old = now - 100 * 24 * 60 * 60)).unwrap();
+
+        let stats = guild_stats(&conn, GuildId(1), now - 60).unwrap();
+        assert_eq!((stats.total, stats.errors), (3, 1));
+        assert_eq!(stats.commands[0], ("roll".to_string(), 2, 1));
+        assert_eq!(stats.users[0].user_id, 10);
+
+        assert_eq!(prune(&conn, 90).unwrap(), 1);
+    }
+}
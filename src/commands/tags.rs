@@ -0,0 +1,518 @@
+use commands::{Command, CommandError, CommandResult, CommandSource, database, guild_only, is_guild_admin, sanitize_mentions, text_after, user_name};
+use commands::group::CommandGroup;
+use db;
+use framework::CommandRegistry;
+
+use chrono::{NaiveDateTime, Utc};
+use rand::{self, Rng};
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::parse_username;
+
+use std::sync::Arc;
+
+/// The longest a tag name can be.
+const MAX_NAME_LEN: usize = 32;
+
+/// Leaves room below the message length limit for the template variables.
+const MAX_CONTENT_LEN: usize = 1500;
+
+/// Leaves room below the message length limit.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+/// How many tags a guild can have.
+const MAX_TAGS: i64 = 500;
+
+pub struct Tag {
+    pub name: String,
+    pub content: String,
+    pub owner_id: UserId,
+    pub uses: i64,
+    pub created_at: i64,
+}
+
+pub fn get_tag(conn: &Connection, guild_id: GuildId, name: &str) -> rusqlite::Result<Option<Tag>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, content, owner_id, uses, created_at FROM tags WHERE guild_id = ?1 AND name = ?2")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64), &name])?;
+    match rows.next() {
+        Some(row) => {
+            let row = row?;
+            Ok(Some(Tag {
+                name: row.get(0),
+                content: row.get(1),
+                owner_id: UserId(row.get::<_, i64>(2) as u64),
+                uses: row.get(3),
+                created_at: row.get(4),
+            }))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Adds a tag, returning false if the guild already has a tag with that name.
+pub fn create_tag(conn: &Connection, guild_id: GuildId, name: &str, owner_id: UserId, content: &str, now: i64) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "INSERT OR IGNORE INTO tags (guild_id, name, content, owner_id, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        &[&(guild_id.0 as i64), &name, &content, &(owner_id.0 as i64), &now])?;
+    Ok(n > 0)
+}
+
+pub fn edit_tag(conn: &Connection, guild_id: GuildId, name: &str, content: &str) -> rusqlite::Result<bool> {
+    let n = conn.execute("UPDATE tags SET content = ?3 WHERE guild_id = ?1 AND name = ?2",
+        &[&(guild_id.0 as i64), &name, &content])?;
+    Ok(n > 0)
+}
+
+pub fn delete_tag(conn: &Connection, guild_id: GuildId, name: &str) -> rusqlite::Result<bool> {
+    let n = conn.execute("DELETE FROM tags WHERE guild_id = ?1 AND name = ?2", &[&(guild_id.0 as i64), &name])?;
+    Ok(n > 0)
+}
+
+fn count_tags(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM tags WHERE guild_id = ?1", &[&(guild_id.0 as i64)], |row| row.get(0))
+}
+
+/// The names of the tags in a guild, optionally only the ones owned by a user, sorted by name.
+pub fn tag_names(conn: &Connection, guild_id: GuildId, owner_id: Option<UserId>) -> rusqlite::Result<Vec<String>> {
+    let owner_id = owner_id.map(|id| id.0 as i64);
+    let mut stmt = conn.prepare(
+        "SELECT name FROM tags WHERE guild_id = ?1 AND (?2 IS NULL OR owner_id = ?2) ORDER BY name")?;
+    let names = stmt.query_map(&[&(guild_id.0 as i64), &owner_id], |row| row.get(0))?;
+    names.collect()
+}
+
+fn record_use(conn: &Connection, guild_id: GuildId, name: &str) -> rusqlite::Result<()> {
+    conn.execute("UPDATE tags SET uses = uses + 1 WHERE guild_id = ?1 AND name = ?2", &[&(guild_id.0 as i64), &name])?;
+    Ok(())
+}
+
+/// The values that template variables in a tag get replaced with.
+pub struct Variables {
+    pub user: String,
+    pub channel: String,
+    pub args: String,
+}
+
+/// Fills in `{user}`, `{channel}`, `{args}` and `{random:a|b|c}` in a tag. Anything else in braces is left alone.
+pub fn render<R: Rng>(content: &str, vars: &Variables, rng: &mut R) -> String {
+    let mut text = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let name = &rest[1..end];
+        match name {
+            "user" => text.push_str(&vars.user),
+            "channel" => text.push_str(&vars.channel),
+            "args" => text.push_str(&vars.args),
+            _ if name.starts_with("random:") => {
+                let choices: Vec<&str> = name["random:".len()..].split('|').collect();
+                text.push_str(rng.choose(&choices).unwrap_or(&""));
+            },
+            _ => text.push_str(&rest[..end + 1]),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Checks that a name can be used for a new tag, returning the reason if it can't.
+fn check_name(ctx: &Context, name: &str) -> Result<(), String> {
+    if name.len() > MAX_NAME_LEN || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Tag names can be up to {} letters, numbers, dashes and underscores.", MAX_NAME_LEN));
+    }
+
+    let data = ctx.data.lock();
+    if let Some(commands) = data.get::<CommandRegistry>() {
+        if commands.read().unwrap().contains_key(name) {
+            return Err(format!("There's already a command called '{}'.", name));
+        }
+    }
+    Ok(())
+}
+
+fn check_content(content: &str) -> Result<(), String> {
+    if content.is_empty() {
+        Err("The tag needs some content.".to_string())
+    }
+    else if content.chars().count() > MAX_CONTENT_LEN {
+        Err(format!("Tags can be at most {} characters long.", MAX_CONTENT_LEN))
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// Renders a tag for a message and sends it to the channel the message was sent in.
+fn send_tag(ctx: &Context, msg: &Message, tag: &Tag, args: &[String]) -> CommandResult {
+    let vars = Variables {
+        user: msg.author.mention(),
+        channel: msg.channel_id.mention(),
+        args: args.join(" "),
+    };
+    let mut text = sanitize_mentions(&render(&tag.content, &vars, &mut rand::thread_rng()));
+    if text.len() > MAX_MESSAGE_LEN {
+        let mut end = MAX_MESSAGE_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    msg.channel_id.say(&text)?;
+    if let Some(guild_id) = msg.guild_id() {
+        record_use(&database(ctx)?.lock().unwrap(), guild_id, &tag.name)?;
+    }
+    Ok(())
+}
+
+/// Runs a tag as if it was a command, so `^<tag name>` works directly.
+pub struct TagCommand {
+    tag: Tag,
+}
+
+impl Command for TagCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        send_tag(ctx, msg, &self.tag, &args[1..])
+    }
+
+    fn description(&self) -> &str {
+        "A tag created in this server."
+    }
+
+    fn category(&self) -> &str {
+        "tags"
+    }
+}
+
+/// Looks up unknown commands in the tags of the guild they were sent in.
+pub struct TagSource;
+
+impl CommandSource for TagSource {
+    fn find(&self, ctx: &Context, msg: &Message, name: &str) -> Option<Arc<Command>> {
+        let guild_id = msg.guild_id()?;
+        let db = db::get(ctx)?;
+        let tag = get_tag(&db.lock().unwrap(), guild_id, &name.to_lowercase());
+        match tag {
+            Ok(tag) => tag.map(|tag| Arc::new(TagCommand { tag: tag }) as Arc<Command>),
+            Err(e) => {
+                error!("Could not look up tag '{}': {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+pub struct ShowTag;
+
+impl Command for ShowTag {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let name = match args.get(1) {
+            Some(name) => name.to_lowercase(),
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let tag = get_tag(&database(ctx)?.lock().unwrap(), guild_id, &name)?;
+        match tag {
+            Some(tag) => send_tag(ctx, msg, &tag, &args[2..]),
+            None => {
+                msg.reply(&format!("There's no tag called '{}'.", name))?;
+                Ok(())
+            }
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Shows a tag. Tags can also be used as commands."
+    }
+
+    fn usage(&self) -> &str {
+        "<name> [args]"
+    }
+}
+
+pub struct CreateTag;
+
+impl Command for CreateTag {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 3 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let name = args[1].to_lowercase();
+        let content = text_after(msg, 3);
+        if let Err(reason) = check_name(ctx, &name).and_then(|_| check_content(content)) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let db = database(ctx)?;
+        let conn = db.lock().unwrap();
+        if count_tags(&conn, guild_id)? >= MAX_TAGS {
+            msg.reply(&format!("This server already has {} tags, which is as many as it can have.", MAX_TAGS))?;
+        }
+        else if create_tag(&conn, guild_id, &name, msg.author.id, content, Utc::now().timestamp())? {
+            msg.reply(&format!("Created the tag '{}'.", name))?;
+        }
+        else {
+            msg.reply(&format!("There's already a tag called '{}'.", name))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Creates a tag. The content can use {user}, {channel}, {args} and {random:a|b|c}."
+    }
+
+    fn usage(&self) -> &str {
+        "<name> <content>"
+    }
+}
+
+/// Gets a tag that the author of the message is allowed to change, replying with the reason if there isn't one.
+fn owned_tag(conn: &Connection, msg: &Message, name: &str) -> Result<Option<Tag>, CommandError> {
+    let tag = match get_tag(conn, msg.guild_id().unwrap(), name)? {
+        Some(tag) => tag,
+        None => {
+            msg.reply(&format!("There's no tag called '{}'.", name))?;
+            return Ok(None);
+        }
+    };
+
+    if tag.owner_id != msg.author.id && !is_guild_admin(msg) {
+        msg.reply(&format!("'{}' belongs to {}, so only they or an admin can change it.", name, user_name(tag.owner_id)))?;
+        return Ok(None);
+    }
+    Ok(Some(tag))
+}
+
+pub struct EditTag;
+
+impl Command for EditTag {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        if args.len() < 3 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let name = args[1].to_lowercase();
+        let content = text_after(msg, 3);
+        if let Err(reason) = check_content(content) {
+            msg.reply(&reason)?;
+            return Ok(());
+        }
+
+        let db = database(ctx)?;
+        let conn = db.lock().unwrap();
+        if owned_tag(&conn, msg, &name)?.is_some() {
+            edit_tag(&conn, guild_id, &name, content)?;
+            msg.reply(&format!("Updated the tag '{}'.", name))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Changes the content of a tag you own. Admins can change any tag."
+    }
+
+    fn usage(&self) -> &str {
+        "<name> <content>"
+    }
+}
+
+pub struct DeleteTag;
+
+impl Command for DeleteTag {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let name = match args.get(1) {
+            Some(name) => name.to_lowercase(),
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let conn = db.lock().unwrap();
+        if owned_tag(&conn, msg, &name)?.is_some() {
+            delete_tag(&conn, guild_id, &name)?;
+            msg.reply(&format!("Deleted the tag '{}'.", name))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Deletes a tag you own. Admins can delete any tag."
+    }
+
+    fn usage(&self) -> &str {
+        "<name>"
+    }
+}
+
+pub struct ListTags;
+
+impl Command for ListTags {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let owner_id = match args.get(1) {
+            Some(user) => match parse_username(user) {
+                Some(id) => Some(UserId(id)),
+                None => {
+                    msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+
+        let names = tag_names(&database(ctx)?.lock().unwrap(), guild_id, owner_id)?;
+        if names.is_empty() {
+            match owner_id {
+                Some(id) => msg.reply(&format!("{} doesn't have any tags.", user_name(id)))?,
+                None => msg.reply("This server doesn't have any tags yet. Create one with `tag create <name> <content>`.")?,
+            };
+            return Ok(());
+        }
+
+        let mut text = format!("Tags ({}): ", names.len());
+        for (i, name) in names.iter().enumerate() {
+            if text.len() + name.len() > MAX_MESSAGE_LEN {
+                text.push_str(&format!("and {} more", names.len() - i));
+                break;
+            }
+            if i > 0 {
+                text.push_str(", ");
+            }
+            text.push_str(name);
+        }
+        msg.channel_id.say(&text)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the tags in this server, or the ones owned by a user."
+    }
+
+    fn usage(&self) -> &str {
+        "[user]"
+    }
+}
+
+pub struct TagInfo;
+
+impl Command for TagInfo {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let name = match args.get(1) {
+            Some(name) => name.to_lowercase(),
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let tag = get_tag(&database(ctx)?.lock().unwrap(), guild_id, &name)?;
+        match tag {
+            Some(tag) => {
+                let created = NaiveDateTime::from_timestamp(tag.created_at, 0);
+                msg.channel_id.say(&format!("'{}' belongs to {}, was created on {} and has been used {} time(s).",
+                    tag.name, user_name(tag.owner_id), created.format("%Y-%m-%d"), tag.uses))?;
+            },
+            None => {
+                msg.reply(&format!("There's no tag called '{}'.", name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows who owns a tag and how often it's been used."
+    }
+
+    fn usage(&self) -> &str {
+        "<name>"
+    }
+}
+
+pub fn tag_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Creates and shows tags, short texts that can be used as commands in a server.");
+    group.add_command("show", ShowTag);
+    group.add_command("create", CreateTag);
+    group.add_command("edit", EditTag);
+    group.add_command("delete", DeleteTag);
+    group.add_command("list", ListTags);
+    group.add_command("info", TagInfo);
+    group.set_default("show");
+    group.set_category("tags");
+    group.add_check(guild_only);
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    #[test]
+    fn render_templates() {
+        let vars = Variables {
+            user: "<@1>".to_string(),
+            channel: "<#2>".to_string(),
+            args: "some args".to_string(),
+        };
+        let mut rng = rand::thread_rng();
+        assert_eq!(render("hi {user} in {channel}: {args}", &vars, &mut rng), "hi <@1> in <#2>: some args");
+        assert_eq!(render("{unknown} {user", &vars, &mut rng), "{unknown} {user");
+        assert_eq!(render("{random:only}", &vars, &mut rng), "only");
+        let picked = render("{random:a|b|c}", &vars, &mut rng);
+        assert!(["a", "b", "c"].contains(&picked.as_str()));
+    }
+
+    #[test]
+    fn store_tags() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        assert!(create_tag(&conn, guild, "hello", UserId(2), "Hello {user}!", 100).unwrap());
+        assert!(!create_tag(&conn, guild, "hello", UserId(3), "Hi", 100).unwrap());
+        assert!(create_tag(&conn, GuildId(9), "hello", UserId(3), "Hi", 100).unwrap());
+        assert!(create_tag(&conn, guild, "bye", UserId(3), "Bye", 100).unwrap());
+
+        assert!(edit_tag(&conn, guild, "hello", "Hey {user}!").unwrap());
+        record_use(&conn, guild, "hello").unwrap();
+        let tag = get_tag(&conn, guild, "hello").unwrap().unwrap();
+        assert_eq!(tag.content, "Hey {user}!");
+        assert_eq!(tag.owner_id, UserId(2));
+        assert_eq!(tag.uses, 1);
+
+        assert_eq!(tag_names(&conn, guild, None).unwrap(), vec!["bye", "hello"]);
+        assert_eq!(tag_names(&conn, guild, Some(UserId(3))).unwrap(), vec!["bye"]);
+        assert!(delete_tag(&conn, guild, "bye").unwrap());
+        assert!(!delete_tag(&conn, guild, "bye").unwrap());
+        assert_eq!(count_tags(&conn, guild).unwrap(), 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,301 @@
+use commands::{Command, CommandError, CommandResult, database, user_name};
+use commands::group::CommandGroup;
+use commands::reminders::parse_time;
+use tz::{Tz, ZONES, all_user_timezones, find_user_timezone, set_user_timezone, user_timezone};
+
+use chrono::Utc;
+use rusqlite::Connection;
+
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+use serenity::utils::parse_username;
+
+use std::collections::BTreeMap;
+
+/// How many names to show next to a time in the world clock.
+const MAX_NAMES: usize = 10;
+
+/// Leaves room below the message length limit.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+/// Names a timezone along with the abbreviation it's using, like `CEST (Europe/Paris)`.
+fn label(tz: Tz, timestamp: i64) -> String {
+    match tz {
+        Tz::Fixed(_) => tz.name(),
+        Tz::Named(_) => format!("{} ({})", tz.abbreviation_at(timestamp), tz.name()),
+    }
+}
+
+fn unknown_zone(name: &str) -> String {
+    format!("I don't know the timezone `{}`. Try a name like `Europe/Paris` or `Tokyo`, an abbreviation like `PST`, \
+             or an offset like `UTC+2`. `tz list` shows the names I know.", name.replace('`', "'"))
+}
+
+/// Reads a timezone from a zone name or a user mention, which means the timezone of that user.
+fn resolve(conn: &Connection, text: &str) -> Result<Tz, CommandError> {
+    if let Some(user_id) = parse_username(text) {
+        return match find_user_timezone(conn, UserId(user_id))? {
+            Some(tz) => Ok(tz),
+            None => Err(CommandError::Argument(format!("{} hasn't set their timezone.", user_name(UserId(user_id))))),
+        };
+    }
+    Tz::parse(text).ok_or_else(|| CommandError::Argument(unknown_zone(text)))
+}
+
+/// Replies with the reason for argument errors, and passes the others on.
+fn reply_errors(msg: &Message, result: CommandResult) -> CommandResult {
+    match result {
+        Err(CommandError::Argument(reason)) => {
+            msg.reply(&reason)?;
+            Ok(())
+        },
+        other => other,
+    }
+}
+
+pub struct ShowTimezone;
+
+impl Command for ShowTimezone {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let now = Utc::now().timestamp();
+        let tz = find_user_timezone(&database(ctx)?.lock().unwrap(), msg.author.id)?;
+        match tz {
+            Some(tz) => msg.reply(&format!("Your timezone is {}, where it's {} now.", label(tz, now), tz.to_local(now).format("%H:%M")))?,
+            None => msg.reply("You haven't set your timezone, so times are shown in UTC. Set it with `tz set <zone>`.")?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows your timezone."
+    }
+}
+
+pub struct SetTimezone;
+
+impl Command for SetTimezone {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        if args.len() < 2 {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let name = args[1..].join(" ");
+        let tz = match Tz::parse(&name) {
+            Some(tz) => tz,
+            None => {
+                msg.reply(&unknown_zone(&name))?;
+                return Ok(());
+            }
+        };
+
+        let now = Utc::now().timestamp();
+        set_user_timezone(&database(ctx)?.lock().unwrap(), msg.author.id, tz)?;
+        msg.reply(&format!("Your timezone is now {}, where it's {} now.", label(tz, now), tz.to_local(now).format("%H:%M")))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets your timezone, used to read and show times like the ones in reminders."
+    }
+
+    fn usage(&self) -> &str {
+        "<zone, e.g. Europe/Paris, Tokyo, PST or UTC+2>"
+    }
+}
+
+pub struct ListTimezones;
+
+impl Command for ListTimezones {
+    fn execute(&self, _ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let region = match args.get(1) {
+            Some(region) => region,
+            None => {
+                let mut regions: BTreeMap<&str, usize> = BTreeMap::new();
+                for zone in ZONES {
+                    *regions.entry(zone.name.split('/').next().unwrap_or("")).or_insert(0) += 1;
+                }
+                let regions: Vec<String> = regions.iter().map(|(region, count)| format!("{} ({})", region, count)).collect();
+                msg.channel_id.say(&format!("Regions: {}\nUse `tz list <region>` to see the zones in one.", regions.join(", ")))?;
+                return Ok(());
+            }
+        };
+
+        let names: Vec<&str> = ZONES.iter()
+            .filter(|zone| zone.name.split('/').next().map_or(false, |r| r.eq_ignore_ascii_case(region)))
+            .map(|zone| zone.name)
+            .collect();
+        if names.is_empty() {
+            msg.reply(&format!("There's no region called '{}'.", region))?;
+        }
+        else {
+            msg.channel_id.say(&names.join(", "))?;
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Lists the timezones that can be used, by region."
+    }
+
+    fn usage(&self) -> &str {
+        "[region]"
+    }
+}
+
+pub fn tz_group() -> CommandGroup {
+    let mut group = CommandGroup::new("Shows and sets your timezone.");
+    group.add_command("show", ShowTimezone);
+    group.add_command("set", SetTimezone);
+    group.add_command("list", ListTimezones);
+    group.set_default("show");
+    group
+}
+
+/// Shows the local time of everyone in the server that has set a timezone, grouped by timezone.
+fn world_clock(conn: &Connection, msg: &Message, now: i64) -> Result<Option<String>, CommandError> {
+    let guild = match msg.guild() {
+        Some(guild) => guild,
+        None => return Ok(None),
+    };
+
+    let mut zones: BTreeMap<(i32, String), (Tz, Vec<UserId>)> = BTreeMap::new();
+    {
+        let guild = guild.read();
+        for (user_id, tz) in all_user_timezones(conn)? {
+            if guild.members.contains_key(&user_id) {
+                zones.entry((tz.offset_at(now), tz.name()))
+                    .or_insert_with(|| (tz, Vec::new()))
+                    .1.push(user_id);
+            }
+        }
+    }
+    if zones.is_empty() {
+        return Ok(None);
+    }
+
+    let mut text = String::new();
+    for &(tz, ref users) in zones.values() {
+        let mut names: Vec<String> = users.iter().take(MAX_NAMES).map(|&id| user_name(id)).collect();
+        if users.len() > MAX_NAMES {
+            names.push(format!("{} more", users.len() - MAX_NAMES));
+        }
+        let line = format!("`{}` {}: {}\n", tz.to_local(now).format("%a %H:%M"), label(tz, now), names.join(", "));
+        if text.len() + line.len() > MAX_MESSAGE_LEN {
+            text.push_str("...");
+            break;
+        }
+        text.push_str(&line);
+    }
+    Ok(Some(text))
+}
+
+pub struct TimeCommand;
+
+impl Command for TimeCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let db = database(ctx)?;
+        let now = Utc::now().timestamp();
+
+        if args.len() < 2 {
+            // Either everyone's clocks, or just the author's timezone if there's nobody else.
+            let clock = {
+                let conn = db.lock().unwrap();
+                match world_clock(&conn, msg, now)? {
+                    Some(text) => Ok(text),
+                    None => Err(user_timezone(&conn, msg.author.id)?),
+                }
+            };
+            match clock {
+                Ok(text) => msg.channel_id.say(&text)?,
+                Err(tz) => msg.reply(&format!("It's {} in {}.", tz.to_local(now).format("%H:%M on %A"), label(tz, now)))?,
+            };
+            return Ok(());
+        }
+
+        let target = args[1..].join(" ");
+        let tz = resolve(&db.lock().unwrap(), &target);
+        reply_errors(msg, tz.and_then(|tz| {
+            let time = tz.to_local(now).format("%H:%M on %A");
+            match parse_username(&target) {
+                Some(user_id) => msg.reply(&format!("It's {} for {}, in {}.", time, user_name(UserId(user_id)), label(tz, now)))?,
+                None => msg.reply(&format!("It's {} in {}.", time, label(tz, now)))?,
+            };
+            Ok(())
+        }))
+    }
+
+    fn description(&self) -> &str {
+        "Shows the time in a timezone or for a user, or for everyone in the server that has set their timezone."
+    }
+
+    fn usage(&self) -> &str {
+        "[user or zone]"
+    }
+}
+
+pub struct ConvertCommand;
+
+impl Command for ConvertCommand {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let words = &args[1..];
+        let lower: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+        let lower: Vec<&str> = lower.iter().map(String::as_str).collect();
+
+        let (time, used) = match parse_time(&lower, true) {
+            Some(time) => time,
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+        let rest = &words[used..];
+        let (from, to) = match rest.iter().position(|w| w.eq_ignore_ascii_case("to") || w.eq_ignore_ascii_case("in")) {
+            Some(i) if i + 1 < rest.len() => (rest[..i].join(" "), rest[i + 1..].join(" ")),
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let db = database(ctx)?;
+        let zones = {
+            let conn = db.lock().unwrap();
+            if from.is_empty() {
+                user_timezone(&conn, msg.author.id).map_err(CommandError::from)
+            }
+            else {
+                resolve(&conn, &from)
+            }.and_then(|from| resolve(&conn, &to).map(|to| (from, to)))
+        };
+
+        reply_errors(msg, zones.and_then(|(from, to)| {
+            let now = Utc::now().timestamp();
+            let date = from.to_local(now).date();
+            let timestamp = from.to_utc(date.and_time(time));
+            let local = to.to_local(timestamp);
+            let day = if local.date() > date {
+                " the next day"
+            }
+            else if local.date() < date {
+                " the day before"
+            }
+            else {
+                ""
+            };
+
+            msg.reply(&format!("{} {} is {} {}{}.",
+                time.format("%H:%M"), label(from, timestamp), local.format("%H:%M"), label(to, timestamp), day))?;
+            Ok(())
+        }))
+    }
+
+    fn description(&self) -> &str {
+        "Converts a time today from one timezone to another, or from yours if none is given."
+    }
+
+    fn usage(&self) -> &str {
+        "<time> [zone or user] to <zone or user>, e.g. 18:00 CET to PST"
+    }
+}
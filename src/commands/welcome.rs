@@ -0,0 +1,528 @@
+use commands::{Command, CommandError, CommandResult, database, guild_only, guild_admin_only, sanitize_mentions, text_after};
+use commands::group::CommandGroup;
+use commands::reactionroles::{can_manage, find_assignable_role};
+use db;
+
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity::CACHE;
+use serenity::client::Context;
+use serenity::http;
+use serenity::model::channel::Message;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::model::misc::Mentionable;
+use serenity::model::user::User;
+use serenity::utils::{Colour, parse_channel};
+
+
+/// Leaves room below the message length limit for the template variables.
+const MAX_MESSAGE_LEN: usize = 1500;
+
+const DEFAULT_WELCOME: &str = "Welcome to {guild}, {user}!";
+const DEFAULT_GOODBYE: &str = "{username} has left {guild}.";
+
+/// Which of the two greetings a setting or command is about.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Welcome,
+    Goodbye,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match *self {
+            Kind::Welcome => "welcome",
+            Kind::Goodbye => "goodbye",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match *self {
+            Kind::Welcome => "Welcome",
+            Kind::Goodbye => "Goodbye",
+        }
+    }
+
+    fn default_message(&self) -> &'static str {
+        match *self {
+            Kind::Welcome => DEFAULT_WELCOME,
+            Kind::Goodbye => DEFAULT_GOODBYE,
+        }
+    }
+}
+
+/// Where and how a greeting gets posted. Nothing gets posted without a channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Greeting {
+    pub channel_id: Option<ChannelId>,
+    /// The template, or `None` for the default one.
+    pub message: Option<String>,
+    pub embed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub welcome: Greeting,
+    pub goodbye: Greeting,
+    /// A message to DM to new members.
+    pub dm_message: Option<String>,
+    /// A role to give to new members.
+    pub auto_role: Option<RoleId>,
+}
+
+impl Settings {
+    fn greeting(&mut self, kind: Kind) -> &mut Greeting {
+        match kind {
+            Kind::Welcome => &mut self.welcome,
+            Kind::Goodbye => &mut self.goodbye,
+        }
+    }
+}
+
+pub fn get_settings(conn: &Connection, guild_id: GuildId) -> rusqlite::Result<Settings> {
+    let mut stmt = conn.prepare(
+        "SELECT welcome_channel, welcome_message, welcome_embed, goodbye_channel, goodbye_message, goodbye_embed, \
+         dm_message, auto_role FROM welcome_settings WHERE guild_id = ?1")?;
+    let mut rows = stmt.query(&[&(guild_id.0 as i64)])?;
+    let row = match rows.next() {
+        Some(row) => row?,
+        None => return Ok(Settings::default()),
+    };
+
+    let channel = |i| row.get::<_, Option<i64>>(i).map(|id| ChannelId(id as u64));
+    Ok(Settings {
+        welcome: Greeting {
+            channel_id: channel(0),
+            message: row.get(1),
+            embed: row.get(2),
+        },
+        goodbye: Greeting {
+            channel_id: channel(3),
+            message: row.get(4),
+            embed: row.get(5),
+        },
+        dm_message: row.get(6),
+        auto_role: row.get::<_, Option<i64>>(7).map(|id| RoleId(id as u64)),
+    })
+}
+
+pub fn save_settings(conn: &Connection, guild_id: GuildId, settings: &Settings) -> rusqlite::Result<()> {
+    let channel = |g: &Greeting| g.channel_id.map(|id| id.0 as i64);
+    conn.execute(
+        "INSERT OR REPLACE INTO welcome_settings (guild_id, welcome_channel, welcome_message, welcome_embed, \
+         goodbye_channel, goodbye_message, goodbye_embed, dm_message, auto_role) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        &[&(guild_id.0 as i64), &channel(&settings.welcome), &settings.welcome.message, &settings.welcome.embed,
+          &channel(&settings.goodbye), &settings.goodbye.message, &settings.goodbye.embed, &settings.dm_message,
+          &settings.auto_role.map(|id| id.0 as i64)])?;
+    Ok(())
+}
+
+/// Fills in `{user}`, `{username}`, `{guild}` and `{member_count}` in a greeting.
+pub fn render(template: &str, user: &User, guild: &str, member_count: u64) -> String {
+    let text = template.replace("{user}", &user.mention())
+        .replace("{username}", &user.name)
+        .replace("{guild}", guild)
+        .replace("{member_count}", &member_count.to_string());
+    sanitize_mentions(&text)
+}
+
+fn guild_info(guild_id: GuildId) -> (String, u64) {
+    match guild_id.find() {
+        Some(guild) => {
+            let guild = guild.read();
+            (guild.name.clone(), guild.member_count)
+        },
+        None => (guild_id.to_string(), 0),
+    }
+}
+
+fn post(greeting: &Greeting, kind: Kind, user: &User, guild_id: GuildId) -> Result<(), CommandError> {
+    let channel_id = match greeting.channel_id {
+        Some(channel_id) => channel_id,
+        None => return Ok(()),
+    };
+    let (guild, member_count) = guild_info(guild_id);
+    let template = greeting.message.as_ref().map_or(kind.default_message(), String::as_str);
+    let text = render(template, user, &guild, member_count);
+
+    if greeting.embed {
+        let colour = if kind == Kind::Welcome { Colour::dark_green() } else { Colour::dark_grey() };
+        channel_id.send_message(|m| m.embed(|e| {
+            e.description(&text)
+                .colour(colour)
+                .thumbnail(&user.face())
+        }))?;
+    }
+    else {
+        channel_id.say(&text)?;
+    }
+    Ok(())
+}
+
+fn load(ctx: &Context, guild_id: GuildId) -> Option<Settings> {
+    let db = db::get(ctx)?;
+    let settings = get_settings(&db.lock().unwrap(), guild_id);
+    match settings {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            error!("Could not load welcome settings: {}", e);
+            None
+        }
+    }
+}
+
+/// Welcomes a new member, DMs them and gives them the auto-role, depending on the settings of the guild.
+pub fn member_joined(ctx: &Context, guild_id: GuildId, member: &Member) {
+    let settings = match load(ctx, guild_id) {
+        Some(settings) => settings,
+        None => return,
+    };
+    let user = member.user.read().clone();
+
+    if let Err(e) = post(&settings.welcome, Kind::Welcome, &user, guild_id) {
+        warn!("Could not post welcome message in guild {}: {}", guild_id, e);
+    }
+
+    if let Some(ref template) = settings.dm_message {
+        if !user.bot {
+            let (guild, member_count) = guild_info(guild_id);
+            let text = render(template, &user, &guild, member_count);
+            // Plenty of people don't take DMs from servers.
+            if let Err(e) = user.create_dm_channel().and_then(|dm| dm.id.say(&text)) {
+                debug!("Could not DM a welcome to {}: {}", user.id, e);
+            }
+        }
+    }
+
+    if let Some(role_id) = settings.auto_role {
+        if user.bot {
+            return;
+        }
+        let allowed = guild_id.find().map_or(false, |guild| {
+            let guild = guild.read();
+            match guild.roles.get(&role_id).map(|role| can_manage(&guild, CACHE.read().user.id, role)) {
+                Some(Ok(())) => true,
+                Some(Err(reason)) => {
+                    warn!("Can't give the auto-role in guild {}: {}", guild_id, reason);
+                    false
+                },
+                None => false,
+            }
+        });
+        if allowed {
+            if let Err(e) = http::add_member_role(guild_id.0, user.id.0, role_id.0) {
+                warn!("Could not give the auto-role to {} in guild {}: {}", user.id, guild_id, e);
+            }
+        }
+    }
+}
+
+/// Says goodbye to a member that left, if the guild has a goodbye channel.
+pub fn member_left(ctx: &Context, guild_id: GuildId, user: &User) {
+    if let Some(settings) = load(ctx, guild_id) {
+        if let Err(e) = post(&settings.goodbye, Kind::Goodbye, user, guild_id) {
+            warn!("Could not post goodbye message in guild {}: {}", guild_id, e);
+        }
+    }
+}
+
+/// Changes the settings of the guild a message was sent in.
+fn update<F: FnOnce(&mut Settings)>(ctx: &Context, msg: &Message, f: F) -> CommandResult {
+    let guild_id = msg.guild_id().unwrap();
+    let db = database(ctx)?;
+    let conn = db.lock().unwrap();
+    let mut settings = get_settings(&conn, guild_id)?;
+    f(&mut settings);
+    save_settings(&conn, guild_id, &settings)?;
+    Ok(())
+}
+
+fn is_off(arg: Option<&String>) -> bool {
+    arg.map_or(false, |a| a.eq_ignore_ascii_case("off"))
+}
+
+pub struct ShowSettings(Kind);
+
+impl Command for ShowSettings {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let mut settings = get_settings(&database(ctx)?.lock().unwrap(), msg.guild_id().unwrap())?;
+        let kind = self.0;
+        let (dm_message, auto_role) = (settings.dm_message.clone(), settings.auto_role);
+        let greeting = settings.greeting(kind);
+
+        let mut text = match greeting.channel_id {
+            Some(channel_id) => format!("{} messages are posted in {}", kind.title(), channel_id.mention()),
+            None => format!("{} messages are off", kind.title()),
+        };
+        text.push_str(if greeting.embed { " as embeds.\n" } else { ".\n" });
+        text.push_str(&format!("Message: {}\n", greeting.message.as_ref().map_or(kind.default_message(), String::as_str)));
+        if kind == Kind::Welcome {
+            text.push_str(&format!("DM: {}\n", dm_message.unwrap_or_else(|| "off".to_string())));
+            text.push_str(&format!("Auto-role: {}", auto_role.map_or("off".to_string(), |role| role.mention())));
+        }
+        msg.channel_id.say(&sanitize_mentions(&text))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Shows the current settings."
+    }
+}
+
+pub struct SetChannel(Kind);
+
+impl Command for SetChannel {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let channel_id = match args.get(1) {
+            _ if is_off(args.get(1)) => None,
+            Some(arg) => match parse_channel(arg) {
+                Some(id) if msg.guild().map_or(false, |g| g.read().channels.contains_key(&ChannelId(id))) => Some(ChannelId(id)),
+                _ => {
+                    msg.reply("That's not a channel in this server.")?;
+                    return Ok(());
+                }
+            },
+            None => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let kind = self.0;
+        update(ctx, msg, |settings| settings.greeting(kind).channel_id = channel_id)?;
+        match channel_id {
+            Some(channel_id) => msg.reply(&format!("{} messages will be posted in {}.", kind.title(), channel_id.mention()))?,
+            None => msg.reply(&format!("Turned off {} messages.", kind.name()))?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets the channel the messages are posted in, or turns them off."
+    }
+
+    fn usage(&self) -> &str {
+        "<#channel|off>"
+    }
+}
+
+pub struct SetMessage(Kind);
+
+impl Command for SetMessage {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let text = text_after(msg, 2);
+        if text.is_empty() {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+        if text.chars().count() > MAX_MESSAGE_LEN {
+            msg.reply(&format!("Messages can be at most {} characters long.", MAX_MESSAGE_LEN))?;
+            return Ok(());
+        }
+
+        let kind = self.0;
+        let message = if text.eq_ignore_ascii_case("default") { None } else { Some(text.to_string()) };
+        update(ctx, msg, |settings| settings.greeting(kind).message = message)?;
+        msg.reply(&format!("Updated the {} message.", kind.name()))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets the message, which can use {user}, {username}, {guild} and {member_count}. `default` goes back to the default one."
+    }
+
+    fn usage(&self) -> &str {
+        "<message|default>"
+    }
+}
+
+pub struct SetEmbed(Kind);
+
+impl Command for SetEmbed {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let embed = match args.get(1).map(|a| a.to_lowercase()) {
+            Some(ref a) if a == "on" => true,
+            Some(ref a) if a == "off" => false,
+            _ => {
+                msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+                return Ok(());
+            }
+        };
+
+        let kind = self.0;
+        update(ctx, msg, |settings| settings.greeting(kind).embed = embed)?;
+        msg.reply(&format!("{} messages will be posted as {}.", kind.title(), if embed { "embeds" } else { "plain text" }))?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets whether the messages are posted as embeds."
+    }
+
+    fn usage(&self) -> &str {
+        "<on|off>"
+    }
+}
+
+pub struct TestGreeting(Kind);
+
+impl Command for TestGreeting {
+    fn execute(&self, ctx: &mut Context, msg: &Message, _args: &Vec<String>) -> CommandResult {
+        let guild_id = msg.guild_id().unwrap();
+        let mut settings = get_settings(&database(ctx)?.lock().unwrap(), guild_id)?;
+        let mut greeting = settings.greeting(self.0).clone();
+        if greeting.channel_id.is_none() {
+            msg.reply(&format!("{} messages are off. Set a channel with `{} channel <#channel>` first.", self.0.title(), self.0.name()))?;
+            return Ok(());
+        }
+        greeting.channel_id = Some(msg.channel_id);
+        post(&greeting, self.0, &msg.author, guild_id)
+    }
+
+    fn description(&self) -> &str {
+        "Shows what the message looks like, in this channel."
+    }
+}
+
+pub struct SetDm;
+
+impl Command for SetDm {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let text = text_after(msg, 2);
+        if text.is_empty() {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+        if text.chars().count() > MAX_MESSAGE_LEN {
+            msg.reply(&format!("Messages can be at most {} characters long.", MAX_MESSAGE_LEN))?;
+            return Ok(());
+        }
+
+        let message = if text.eq_ignore_ascii_case("off") { None } else { Some(text.to_string()) };
+        let reply = if message.is_some() { "New members will get a DM." } else { "New members won't get a DM." };
+        update(ctx, msg, |settings| settings.dm_message = message)?;
+        msg.reply(reply)?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets a message to DM to new members, which can use the same variables as the welcome message."
+    }
+
+    fn usage(&self) -> &str {
+        "<message|off>"
+    }
+}
+
+pub struct SetAutoRole;
+
+impl Command for SetAutoRole {
+    fn execute(&self, ctx: &mut Context, msg: &Message, args: &Vec<String>) -> CommandResult {
+        let name = args[1..].join(" ");
+        if name.is_empty() {
+            msg.reply(&format!("Usage: `{} {}`", args[0], self.usage()))?;
+            return Ok(());
+        }
+
+        let role_id = if name.eq_ignore_ascii_case("off") {
+            None
+        }
+        else {
+            let guild = match msg.guild() {
+                Some(guild) => guild,
+                None => return Err(CommandError::Other("Guild not in cache".to_string())),
+            };
+            let checked = find_assignable_role(&guild.read(), msg.author.id, &name);
+            match checked {
+                Ok(role_id) => Some(role_id),
+                Err(reason) => {
+                    msg.reply(&reason)?;
+                    return Ok(());
+                }
+            }
+        };
+
+        update(ctx, msg, |settings| settings.auto_role = role_id)?;
+        match role_id {
+            Some(role_id) => msg.reply(&format!("New members will get {}.", role_id.mention()))?,
+            None => msg.reply("New members won't get a role.")?,
+        };
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Sets a role to give to new members, or turns it off."
+    }
+
+    fn usage(&self) -> &str {
+        "<role|off>"
+    }
+}
+
+fn greeting_group(kind: Kind, description: &str) -> CommandGroup {
+    let mut group = CommandGroup::new(description);
+    group.add_command("show", ShowSettings(kind));
+    group.add_command("channel", SetChannel(kind));
+    group.add_command("message", SetMessage(kind));
+    group.add_command("embed", SetEmbed(kind));
+    group.add_command("test", TestGreeting(kind));
+    group.set_default("show");
+    group.set_category("admin");
+    group.add_check(guild_only);
+    group.add_check(guild_admin_only);
+    group
+}
+
+pub fn welcome_group() -> CommandGroup {
+    let mut group = greeting_group(Kind::Welcome, "Sets up how new members get welcomed.");
+    group.add_command("dm", SetDm);
+    group.add_command("role", SetAutoRole);
+    group
+}
+
+pub fn goodbye_group() -> CommandGroup {
+    greeting_group(Kind::Goodbye, "Sets up the message posted when members leave.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    #[test]
+    fn render_greetings() {
+        let user: User = ::serde_json::from_value(json!({
+            "id": "5",
+            "username": "someone",
+            "discriminator": "0001",
+            "avatar": null,
+        })).unwrap();
+        assert_eq!(render(DEFAULT_WELCOME, &user, "Plank", 10), "Welcome to Plank, <@5>!");
+        assert_eq!(render("{username} is member #{member_count} of {guild}", &user, "Plank", 10),
+            "someone is member #10 of Plank");
+        assert_eq!(render("@everyone {unknown}", &user, "Plank", 10), "@\u{200b}everyone {unknown}");
+    }
+
+    #[test]
+    fn store_settings() {
+        let conn = db::open(":memory:").unwrap();
+        let guild = GuildId(1);
+        assert_eq!(get_settings(&conn, guild).unwrap(), Settings::default());
+
+        let mut settings = Settings::default();
+        settings.welcome.channel_id = Some(ChannelId(2));
+        settings.welcome.embed = true;
+        settings.goodbye.message = Some("bye {username}".to_string());
+        settings.auto_role = Some(RoleId(3));
+        save_settings(&conn, guild, &settings).unwrap();
+        assert_eq!(get_settings(&conn, guild).unwrap(), settings);
+
+        settings.greeting(Kind::Welcome).channel_id = None;
+        save_settings(&conn, guild, &settings).unwrap();
+        assert_eq!(get_settings(&conn, guild).unwrap().welcome.channel_id, None);
+        assert_eq!(get_settings(&conn, GuildId(2)).unwrap(), Settings::default());
+    }
+}
\ No newline at end of file
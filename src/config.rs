@@ -0,0 +1,516 @@
+use log::LevelFilter;
+use toml;
+use typemap;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Prefix for environment variables that override config values,
+/// e.g. `PLANKBOAT_BOT_TOKEN` or `PLANKBOAT_MYANIMELIST_USERNAME`.
+const ENV_PREFIX: &str = "PLANKBOAT_";
+
+/// Documentation for the config values, used when generating the example config.
+const DOCS: &[(&str, &str)] = &[
+    ("bot_token", "Bot token, required."),
+    ("shards", "The number of shards to use.\nLeave undefined to enable autosharding."),
+    ("database", "Path to the sqlite database."),
+    ("slash_commands", "Registers the commands as Discord slash commands."),
+    ("edit_window", "How many seconds after sending a command it can be edited to run it again.\nSet to 0 to disable."),
+    ("message_cache_size", "How many recent messages to remember, so the moderation log can show deleted and edited messages."),
+    ("owners", "User ids that can use the owner commands, in addition to the owner of the bot application."),
+    ("disabled_commands", "Commands that shouldn't be registered."),
+    ("myanimelist", "MyAnimeList login, used for the anime and manga commands.\nLeaving it empty will disable the commands."),
+    ("bot_log", "Posts guild joins and leaves, shard reconnects, command errors and startup and shutdown to a channel."),
+    ("bot_log.channel", "Id of the channel to post to. Leave undefined to disable."),
+    ("bot_log.max_per_minute", "Max amount of posts per minute, the rest are dropped to avoid flooding the channel."),
+    ("analytics", "Records command usage to the database for the stats command."),
+    ("analytics.retention_days", "How many days to keep recorded command usage for. Set to 0 to keep it forever."),
+    ("metrics", "Serves Prometheus metrics over HTTP at /metrics."),
+    ("metrics.address", "Address to serve the metrics on. Keep it local unless something else protects it."),
+    ("logging", "Logging settings. Log levels are off, error, warn, info, debug or trace."),
+    ("logging.level", "Log level for the bot itself. Other modules log warnings and errors unless set in modules."),
+    ("logging.file", "File to log to, leave empty to only log to stderr."),
+    ("logging.rotation", "When to start a new log file: never, daily or size."),
+    ("logging.max_size", "Size in megabytes a log file can grow to before it's rotated, when rotation is size."),
+    ("logging.keep", "How many old log files to keep. Set to 0 to keep all of them."),
+    ("logging.format", "Format of the log lines: text, or json for one JSON object per line."),
+    ("logging.modules", "Log levels for specific modules, e.g. serenity or plankboat::framework."),
+];
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bot_token: Option<String>,
+    pub shards: Option<u64>,
+    pub database: String,
+    pub slash_commands: bool,
+    pub edit_window: u64,
+    pub message_cache_size: usize,
+    pub owners: Vec<u64>,
+    pub disabled_commands: Vec<String>,
+    pub myanimelist: MyAnimeListConfig,
+    pub bot_log: BotLogConfig,
+    pub analytics: AnalyticsConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+}
+
+/// Key for the currently running config in the client data.
+pub struct ConfigContainer;
+
+impl typemap::Key for ConfigContainer {
+    type Value = Arc<Config>;
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MyAnimeListConfig {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotLogConfig {
+    pub channel: Option<u64>,
+    pub max_per_minute: usize,
+}
+
+impl Default for BotLogConfig {
+    fn default() -> BotLogConfig {
+        BotLogConfig {
+            channel: None,
+            max_per_minute: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyticsConfig {
+    pub enabled: bool,
+    pub retention_days: u64,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> AnalyticsConfig {
+        AnalyticsConfig {
+            enabled: true,
+            retention_days: 90,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            enabled: false,
+            address: "127.0.0.1:9184".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub file: String,
+    pub rotation: Rotation,
+    pub max_size: u64,
+    pub keep: usize,
+    pub format: LogFormat,
+    pub modules: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Daily,
+    Size,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: "info".to_string(),
+            file: "output.log".to_string(),
+            rotation: Rotation::Daily,
+            max_size: 10,
+            keep: 7,
+            format: LogFormat::Text,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bot_token: None,
+            shards: None,
+            database: "plankboat.sqlite".to_string(),
+            slash_commands: false,
+            edit_window: 60,
+            message_cache_size: 5000,
+            owners: Vec::new(),
+            disabled_commands: Vec::new(),
+            myanimelist: MyAnimeListConfig::default(),
+            bot_log: BotLogConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            metrics: MetricsConfig::default(),
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+/// Where the config comes from, so that it can be loaded again the same way.
+#[derive(Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    pub token: Option<String>,
+    pub shards: Option<String>,
+    pub log_level: Option<String>,
+    /// How many levels to raise the log level by, from repeating `-v`.
+    pub verbosity: u64,
+}
+
+impl typemap::Key for ConfigSource {
+    type Value = ConfigSource;
+}
+
+impl ConfigSource {
+    /// Loads the config file, applies the environment and command line overrides and validates the result.
+    pub fn load(&self) -> Result<Config, Vec<String>> {
+        let mut cfg = Config::load(&self.path).map_err(|e| vec![e])?;
+        let mut errors = cfg.apply_env(|name| env::var(name).ok());
+
+        if let Some(ref token) = self.token {
+            cfg.bot_token = Some(token.clone());
+        }
+
+        if let Some(ref shards) = self.shards {
+            match shards.parse() {
+                Ok(n) => cfg.shards = Some(n),
+                Err(_) => errors.push(format!("Invalid value for --shards: '{}'", shards)),
+            }
+        }
+
+        if let Some(ref level) = self.log_level {
+            cfg.logging.level = level.clone();
+        }
+
+        if self.verbosity > 0 {
+            if let Ok(level) = cfg.logging.level.parse() {
+                cfg.logging.level = raise_level(level, self.verbosity).to_string().to_lowercase();
+            }
+        }
+
+        errors.extend(cfg.validate());
+        if errors.is_empty() {
+            Ok(cfg)
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+impl MyAnimeListConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.username.is_empty() && !self.password.is_empty()
+    }
+}
+
+/// Raises a log level by some amount of steps, stopping at trace.
+fn raise_level(level: LevelFilter, by: u64) -> LevelFilter {
+    let levels = [LevelFilter::Off, LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+    let index = levels.iter().position(|&l| l == level).unwrap_or(0) + by as usize;
+    levels[::std::cmp::min(index, levels.len() - 1)]
+}
+
+fn parse_env<T: ::std::str::FromStr>(name: &str, value: &str, errors: &mut Vec<String>) -> Option<T> {
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            errors.push(format!("Invalid value for {}{}: '{}'", ENV_PREFIX, name, value));
+            None
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config from a file. A missing file gives the default config.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read config file '{}': {}", path.display(), e))?;
+
+        toml::from_str(&text)
+            .map_err(|e| format!("Could not parse config file '{}': {}", path.display(), e))
+    }
+
+    /// Overrides config values with environment variables. Returns any values that failed to parse.
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, var: F) -> Vec<String> {
+        let mut errors = Vec::new();
+        let get = |name: &str| var(&format!("{}{}", ENV_PREFIX, name));
+
+        if let Some(v) = get("BOT_TOKEN") {
+            self.bot_token = Some(v);
+        }
+        if let Some(v) = get("SHARDS") {
+            if let Some(n) = parse_env("SHARDS", &v, &mut errors) {
+                self.shards = Some(n);
+            }
+        }
+        if let Some(v) = get("DATABASE") {
+            self.database = v;
+        }
+        if let Some(v) = get("SLASH_COMMANDS") {
+            if let Some(b) = parse_env("SLASH_COMMANDS", &v, &mut errors) {
+                self.slash_commands = b;
+            }
+        }
+        if let Some(v) = get("EDIT_WINDOW") {
+            if let Some(n) = parse_env("EDIT_WINDOW", &v, &mut errors) {
+                self.edit_window = n;
+            }
+        }
+        if let Some(v) = get("MYANIMELIST_USERNAME") {
+            self.myanimelist.username = v;
+        }
+        if let Some(v) = get("MYANIMELIST_PASSWORD") {
+            self.myanimelist.password = v;
+        }
+        if let Some(v) = get("LOG_LEVEL") {
+            self.logging.level = v;
+        }
+        if let Some(v) = get("LOG_FILE") {
+            self.logging.file = v;
+        }
+
+        errors
+    }
+
+    /// Checks the config for errors, returning all of them.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        match self.bot_token {
+            Some(ref token) if !token.trim().is_empty() => {},
+            _ => errors.push(format!("bot_token is not set (set it in the config file or with {}BOT_TOKEN)", ENV_PREFIX)),
+        }
+        if self.shards == Some(0) {
+            errors.push("shards has to be at least 1, leave it undefined to use autosharding".to_string());
+        }
+        if self.database.trim().is_empty() {
+            errors.push("database can't be empty".to_string());
+        }
+        if self.myanimelist.username.is_empty() != self.myanimelist.password.is_empty() {
+            errors.push("myanimelist needs both a username and a password".to_string());
+        }
+        if self.bot_log.channel.is_some() && self.bot_log.max_per_minute == 0 {
+            errors.push("bot_log.max_per_minute has to be at least 1".to_string());
+        }
+        if self.metrics.enabled && self.metrics.address.parse::<SocketAddr>().is_err() {
+            errors.push(format!("metrics.address is not a valid address: '{}'", self.metrics.address));
+        }
+        if self.logging.level.parse::<LevelFilter>().is_err() {
+            errors.push(format!("logging.level is not a log level: '{}'", self.logging.level));
+        }
+        for (module, level) in self.logging.modules.iter() {
+            if level.parse::<LevelFilter>().is_err() {
+                errors.push(format!("logging.modules.{} is not a log level: '{}'", module, level));
+            }
+        }
+        if self.logging.rotation == Rotation::Size && self.logging.max_size == 0 {
+            errors.push("logging.max_size has to be at least 1 when rotating by size".to_string());
+        }
+
+        errors
+    }
+
+    /// Lists the values that differ between the configs but can't be changed without restarting.
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.bot_token != new.bot_token {
+            changed.push("bot_token");
+        }
+        if self.shards != new.shards {
+            changed.push("shards");
+        }
+        if self.database != new.database {
+            changed.push("database");
+        }
+        if self.slash_commands != new.slash_commands {
+            changed.push("slash_commands");
+        }
+        if self.edit_window != new.edit_window {
+            changed.push("edit_window");
+        }
+        if self.message_cache_size != new.message_cache_size {
+            changed.push("message_cache_size");
+        }
+        if self.bot_log != new.bot_log {
+            changed.push("bot_log");
+        }
+        if self.analytics.retention_days != new.analytics.retention_days {
+            changed.push("analytics.retention_days");
+        }
+        if self.metrics != new.metrics {
+            changed.push("metrics");
+        }
+        if self.logging != new.logging {
+            changed.push("logging");
+        }
+        changed
+    }
+
+    /// Copies over the values that can't be changed without restarting, so the config matches what's running.
+    pub fn keep_restart_values(&mut self, running: &Config) {
+        self.bot_token = running.bot_token.clone();
+        self.shards = running.shards;
+        self.database = running.database.clone();
+        self.slash_commands = running.slash_commands;
+        self.edit_window = running.edit_window;
+        self.message_cache_size = running.message_cache_size;
+        self.bot_log = running.bot_log.clone();
+        self.analytics.retention_days = running.analytics.retention_days;
+        self.metrics = running.metrics.clone();
+        self.logging = running.logging.clone();
+    }
+
+    /// Generates a commented out example config file from the config values and their docs.
+    pub fn example() -> String {
+        let mut example = Config {
+            bot_token: Some("Bot AAAAAAAAAAAAAAAAAAA".to_string()),
+            shards: Some(4),
+            owners: vec![123456789012345678],
+            disabled_commands: vec!["roulette".to_string()],
+            bot_log: BotLogConfig {
+                channel: Some(123456789012345678),
+                ..BotLogConfig::default()
+            },
+            myanimelist: MyAnimeListConfig {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            },
+            ..Config::default()
+        };
+        example.logging.modules.insert("serenity".to_string(), "info".to_string());
+
+        let mut out = String::new();
+        let mut section = String::new();
+        for line in toml::to_string(&example).unwrap().lines() {
+            if line.is_empty() {
+                continue;
+            }
+
+            let key = if line.starts_with('[') {
+                section = line.trim_matches(|c| c == '[' || c == ']').to_string();
+                section.clone()
+            }
+            else if section.is_empty() {
+                line.split('=').next().unwrap().trim().to_string()
+            }
+            else {
+                format!("{}.{}", section, line.split('=').next().unwrap().trim())
+            };
+
+            if let Some(&(_, doc)) = DOCS.iter().find(|&&(k, _)| k == key) {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                for doc_line in doc.lines() {
+                    out.push_str("# ");
+                    out.push_str(doc_line);
+                    out.push('\n');
+                }
+            }
+            out.push_str("# ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_is_valid() {
+        let uncommented: String = Config::example().lines()
+            .filter(|l| l.starts_with("# ") && (l.contains('=') || l[2..].starts_with('[')))
+            .map(|l| format!("{}\n", &l[2..]))
+            .collect();
+        let cfg: Config = toml::from_str(&uncommented).unwrap();
+        assert!(cfg.validate().is_empty());
+        assert_eq!(cfg.shards, Some(4));
+        assert!(cfg.myanimelist.is_enabled());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut cfg = Config::default();
+        let errors = cfg.apply_env(|name| match name {
+            "PLANKBOAT_BOT_TOKEN" => Some("token".to_string()),
+            "PLANKBOAT_SHARDS" => Some("many".to_string()),
+            "PLANKBOAT_EDIT_WINDOW" => Some("30".to_string()),
+            _ => None,
+        });
+        assert_eq!(cfg.bot_token, Some("token".to_string()));
+        assert_eq!(cfg.shards, None);
+        assert_eq!(cfg.edit_window, 30);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn verbosity_raises_level() {
+        assert_eq!(raise_level(LevelFilter::Info, 1), LevelFilter::Debug);
+        assert_eq!(raise_level(LevelFilter::Warn, 5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn restart_required_changes() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.edit_window = 10;
+        new.disabled_commands.push("roll".to_string());
+        assert_eq!(old.restart_required(&new), vec!["edit_window"]);
+    }
+
+    #[test]
+    fn validate_reports_all_errors() {
+        let mut cfg = Config::default();
+        cfg.shards = Some(0);
+        cfg.myanimelist.username = "user".to_string();
+        assert_eq!(cfg.validate().len(), 3);
+    }
+}
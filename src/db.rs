@@ -0,0 +1,219 @@
+use rusqlite::Connection;
+use rusqlite;
+use typemap;
+
+use serenity::client::Context;
+
+use std::sync::{Arc, Mutex};
+
+pub struct DatabaseContainer;
+
+impl typemap::Key for DatabaseContainer {
+    type Value = Arc<Mutex<Connection>>;
+}
+
+pub const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS unfurl_guilds (
+    guild_id INTEGER PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS command_settings (
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    enabled INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS command_channels (
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    channel_id INTEGER NOT NULL,
+    allow INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, name, channel_id)
+);
+
+CREATE TABLE IF NOT EXISTS command_log (
+    id INTEGER PRIMARY KEY,
+    command TEXT NOT NULL,
+    guild_id INTEGER,
+    channel_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    result TEXT NOT NULL,
+    at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS command_log_guild_at ON command_log (guild_id, at);
+CREATE INDEX IF NOT EXISTS command_log_at ON command_log (at);
+
+CREATE TABLE IF NOT EXISTS modlog_channels (
+    guild_id INTEGER PRIMARY KEY,
+    channel_id INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mod_cases (
+    guild_id INTEGER NOT NULL,
+    case_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    moderator_id INTEGER NOT NULL,
+    target_id INTEGER,
+    reason TEXT NOT NULL,
+    role_id INTEGER,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER,
+    lifted INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, case_id)
+);
+
+CREATE INDEX IF NOT EXISTS mod_cases_target ON mod_cases (guild_id, target_id);
+
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    key TEXT UNIQUE,
+    payload TEXT NOT NULL,
+    due_at INTEGER NOT NULL,
+    schedule TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_retries INTEGER NOT NULL DEFAULT 3,
+    last_error TEXT,
+    state TEXT NOT NULL DEFAULT 'pending'
+);
+
+CREATE INDEX IF NOT EXISTS jobs_due ON jobs (state, due_at);
+
+CREATE TABLE IF NOT EXISTS reminders (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    channel_id INTEGER,
+    target_id INTEGER,
+    text TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    due_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS reminders_user ON reminders (user_id, due_at);
+
+CREATE TABLE IF NOT EXISTS user_timezones (
+    user_id INTEGER PRIMARY KEY,
+    timezone TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tags (
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    owner_id INTEGER NOT NULL,
+    uses INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS auto_responses (
+    id INTEGER PRIMARY KEY,
+    guild_id INTEGER NOT NULL,
+    match_kind TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    response_kind TEXT NOT NULL,
+    response TEXT NOT NULL,
+    cooldown INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS auto_responses_guild ON auto_responses (guild_id);
+
+CREATE TABLE IF NOT EXISTS auto_response_channels (
+    response_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    PRIMARY KEY (response_id, channel_id)
+);
+
+CREATE TABLE IF NOT EXISTS polls (
+    id INTEGER PRIMARY KEY,
+    channel_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    author_id INTEGER NOT NULL,
+    question TEXT NOT NULL,
+    options TEXT NOT NULL,
+    multi INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    closes_at INTEGER,
+    closed INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS polls_message ON polls (message_id);
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+    poll_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    option INTEGER NOT NULL,
+    PRIMARY KEY (poll_id, user_id, option)
+);
+
+CREATE TABLE IF NOT EXISTS reaction_roles (
+    guild_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    emoji TEXT NOT NULL,
+    role_id INTEGER NOT NULL,
+    mode TEXT NOT NULL,
+    PRIMARY KEY (message_id, emoji)
+);
+
+CREATE INDEX IF NOT EXISTS reaction_roles_guild ON reaction_roles (guild_id);
+
+CREATE TABLE IF NOT EXISTS welcome_settings (
+    guild_id INTEGER PRIMARY KEY,
+    welcome_channel INTEGER,
+    welcome_message TEXT,
+    welcome_embed INTEGER NOT NULL DEFAULT 0,
+    goodbye_channel INTEGER,
+    goodbye_message TEXT,
+    goodbye_embed INTEGER NOT NULL DEFAULT 0,
+    dm_message TEXT,
+    auto_role INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS xp (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    xp INTEGER NOT NULL DEFAULT 0,
+    last_message_at INTEGER,
+    PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS xp_guild ON xp (guild_id, xp);
+
+CREATE TABLE IF NOT EXISTS level_rewards (
+    guild_id INTEGER NOT NULL,
+    level INTEGER NOT NULL,
+    role_id INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, level)
+);
+
+CREATE TABLE IF NOT EXISTS level_settings (
+    guild_id INTEGER PRIMARY KEY,
+    announce INTEGER NOT NULL DEFAULT 1,
+    channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS balances (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    balance INTEGER NOT NULL DEFAULT 0,
+    last_daily INTEGER,
+    PRIMARY KEY (guild_id, user_id)
+);
+"#;
+
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Fetches the shared database connection from the client data.
+pub fn get(ctx: &Context) -> Option<Arc<Mutex<Connection>>> {
+    let data = ctx.data.lock();
+    data.get::<DatabaseContainer>().map(Arc::clone)
+}
@@ -0,0 +1,132 @@
+use serenity;
+use serenity::builder::CreateEmbed;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, MessageId};
+use serde_json::Value;
+
+use chrono::Utc;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long after a command was invoked our messages in the channel count as its responses.
+const RESPONSE_TIMEOUT_SECS: u64 = 15;
+
+/// Max amount of invocations to keep track of.
+const MAX_TRACKED: usize = 1000;
+
+struct Invocation {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    at: Instant,
+    responses: Vec<MessageId>,
+    /// Responses from before the invocation was edited that haven't been replaced yet.
+    stale: VecDeque<MessageId>,
+}
+
+/// Keeps track of which of our messages were responses to which command invocations,
+/// so that responses can be replaced when an invocation is edited.
+pub struct EditTracker {
+    window: Duration,
+    invocations: Mutex<VecDeque<Invocation>>,
+}
+
+impl EditTracker {
+    pub fn new(window: Duration) -> EditTracker {
+        EditTracker {
+            window: window,
+            invocations: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Checks if a message is recent enough that editing it should re-run the command.
+    pub fn in_window(&self, message_id: MessageId) -> bool {
+        let age = Utc::now().naive_utc().signed_duration_since(message_id.created_at());
+        age.num_seconds() >= 0 && (age.num_seconds() as u64) <= self.window.as_secs()
+    }
+
+    /// Records that a message invoked a command. If the message has invoked a
+    /// command before, its old responses will get replaced by the new ones.
+    pub fn record_invocation(&self, channel_id: ChannelId, message_id: MessageId) {
+        let mut invocations = self.invocations.lock().unwrap();
+
+        let mut invocation = match invocations.iter().position(|i| i.message_id == message_id) {
+            Some(index) => invocations.remove(index).unwrap(),
+            None => Invocation {
+                channel_id: channel_id,
+                message_id: message_id,
+                at: Instant::now(),
+                responses: Vec::new(),
+                stale: VecDeque::new(),
+            },
+        };
+
+        invocation.at = Instant::now();
+        invocation.stale.extend(invocation.responses.drain(..));
+        invocations.push_back(invocation);
+
+        while invocations.len() > MAX_TRACKED {
+            invocations.pop_front();
+        }
+    }
+
+    /// Records one of our own messages as a response to the latest invocation in
+    /// the channel. Returns the old response it should replace, if there is one.
+    pub fn record_response(&self, channel_id: ChannelId, message_id: MessageId) -> Option<MessageId> {
+        let mut invocations = self.invocations.lock().unwrap();
+        let timeout = Duration::from_secs(RESPONSE_TIMEOUT_SECS);
+
+        let invocation = invocations.iter_mut().rev()
+            .find(|i| i.channel_id == channel_id && i.at.elapsed() <= timeout)?;
+
+        if let Some(old) = invocation.stale.pop_front() {
+            invocation.responses.push(old);
+            Some(old)
+        }
+        else {
+            invocation.responses.push(message_id);
+            None
+        }
+    }
+}
+
+/// Edits an old response to look like a new one, and deletes the new one.
+pub fn replace_response(new: &Message, old: MessageId) -> serenity::Result<()> {
+    let embed = new.embeds.first().cloned();
+    new.channel_id.edit_message(old, |m| {
+        let m = m.content(&new.content);
+        match embed {
+            Some(embed) => m.embed(|_| CreateEmbed::from(embed)),
+            None => {
+                let mut m = m;
+                m.0.insert("embed", Value::Null);
+                m
+            }
+        }
+    })?;
+    new.delete()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_invocation_replaces_responses() {
+        let tracker = EditTracker::new(Duration::from_secs(60));
+        let channel = ChannelId(1);
+
+        tracker.record_invocation(channel, MessageId(10));
+        assert_eq!(tracker.record_response(channel, MessageId(11)), None);
+        assert_eq!(tracker.record_response(ChannelId(2), MessageId(12)), None);
+
+        tracker.record_invocation(channel, MessageId(10));
+        assert_eq!(tracker.record_response(channel, MessageId(13)), Some(MessageId(11)));
+        assert_eq!(tracker.record_response(channel, MessageId(14)), None);
+
+        tracker.record_invocation(channel, MessageId(10));
+        assert_eq!(tracker.record_response(channel, MessageId(15)), Some(MessageId(11)));
+        assert_eq!(tracker.record_response(channel, MessageId(16)), Some(MessageId(14)));
+    }
+}
@@ -3,56 +3,135 @@ use regex::Regex;
 use serenity::framework::Framework;
 use serenity::client::Context;
 use serenity::model::channel::Message;
+use serenity::model::event::MessageUpdateEvent;
 use threadpool::ThreadPool;
+use typemap;
 
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use std::collections::HashMap;
+use commands::{Command, CommandMap, CommandError, CommandSource, Middleware, MessageState, Flow};
+use commands::settings;
+use botlog;
+use edits::{self, EditTracker};
+use metrics::METRICS;
 
-use commands::Command;
+/// Key for the shared command registry in the client data.
+pub struct CommandRegistry;
 
+impl typemap::Key for CommandRegistry {
+    type Value = Arc<RwLock<CommandMap>>;
+}
+
+/// Key for a handle to the framework in the client data, used to re-run edited commands.
+pub struct FrameworkContainer;
+
+impl typemap::Key for FrameworkContainer {
+    type Value = PlankFramework;
+}
+
+#[derive(Clone)]
 pub struct PlankFramework {
     command_prefix: &'static str,
-    commands: HashMap<String, Arc<Command>>,
+    commands: Arc<RwLock<CommandMap>>,
+    middleware: Arc<RwLock<Vec<Arc<Middleware>>>>,
+    sources: Arc<RwLock<Vec<Arc<CommandSource>>>>,
+    edits: Option<Arc<EditTracker>>,
+    commands_run: Arc<AtomicUsize>,
 }
 
 impl PlankFramework {
     pub fn new() -> PlankFramework {
         let fw = PlankFramework {
             command_prefix: "^",
-            commands: HashMap::new(),
+            commands: Arc::new(RwLock::new(CommandMap::new())),
+            middleware: Arc::new(RwLock::new(Vec::new())),
+            sources: Arc::new(RwLock::new(Vec::new())),
+            edits: None,
+            commands_run: Arc::new(AtomicUsize::new(0)),
         };
 
         fw
     }
 
     pub fn add_command<T: Command>(&mut self, name: &str, command: T) {
-        self.commands.insert(name.to_string(), Arc::new(command));
+        self.commands.write().unwrap().insert(name.to_string(), Arc::new(command));
     }
 
-    fn parse_command(prefix: &str, msg: &str) -> Option<Vec<String>> {
+    /// Removes a command, returning false if there was no command with that name.
+    pub fn remove_command(&mut self, name: &str) -> bool {
+        self.commands.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn prefix(&self) -> &str {
+        self.command_prefix
+    }
+
+    /// Gets a shared handle to the registered commands.
+    pub fn commands(&self) -> Arc<RwLock<CommandMap>> {
+        Arc::clone(&self.commands)
+    }
+
+    /// Adds a middleware to the end of the chain. Middleware gets called in the order it was added.
+    pub fn add_middleware<T: Middleware>(&mut self, middleware: T) {
+        self.middleware.write().unwrap().push(Arc::new(middleware));
+    }
+
+    /// Adds a place to look for commands that aren't registered, asked in the order they were added.
+    pub fn add_source<T: CommandSource>(&mut self, source: T) {
+        self.sources.write().unwrap().push(Arc::new(source));
+    }
+
+    /// Swaps in the commands, middleware and command sources from another framework,
+    /// so that every handle to this framework sees the new ones.
+    pub fn replace(&self, other: PlankFramework) {
+        let commands = ::std::mem::replace(&mut *other.commands.write().unwrap(), CommandMap::new());
+        let middleware = other.middleware.read().unwrap().clone();
+        let sources = other.sources.read().unwrap().clone();
+        *self.commands.write().unwrap() = commands;
+        *self.middleware.write().unwrap() = middleware;
+        *self.sources.write().unwrap() = sources;
+    }
+
+    /// How many commands have been run since the framework was created.
+    pub fn commands_run(&self) -> usize {
+        self.commands_run.load(Ordering::Relaxed)
+    }
+
+    /// Re-runs commands when their message is edited within `window` after being sent.
+    pub fn track_edits(&mut self, window: Duration) {
+        self.edits = Some(Arc::new(EditTracker::new(window)));
+    }
+
+    /// Splits a string into arguments, keeping quoted arguments together.
+    pub fn split_args(text: &str) -> Vec<String> {
         lazy_static! {
             static ref REGEX: Regex = Regex::new(r#"'.*?'|".*?"|\S+"#).unwrap();
         }
 
+        REGEX.captures_iter(text)
+            .map(|c| {
+                let s = String::from_str(c.get(0).unwrap().as_str()).unwrap();
+                
+                if s.starts_with("'") {
+                    s.replace("'", "")
+                }
+                else if s.starts_with("\"") {
+                    s.replace("\"", "")
+                }
+                else {
+                    s
+                }
+            }).collect()
+    }
+
+    fn parse_command(prefix: &str, msg: &str) -> Option<Vec<String>> {
         if msg.starts_with(prefix) {
             let cmd = &msg[prefix.len()..];
             if cmd.len() > 0 && !cmd.chars().next().unwrap().is_whitespace() {
-                let args: Vec<String> = REGEX.captures_iter(cmd)
-                    .map(|c| {
-                        let s = String::from_str(c.get(0).unwrap().as_str()).unwrap();
-                        
-                        if s.starts_with("'") {
-                            s.replace("'", "")
-                        }
-                        else if s.starts_with("\"") {
-                            s.replace("\"", "")
-                        }
-                        else {
-                            s
-                        }
-                    }).collect();
+                let args = PlankFramework::split_args(cmd);
                 if args.len() > 0 {
                     return Some(args);
                 }
@@ -62,27 +141,183 @@ impl PlankFramework {
     }
 }
 
-impl Framework for PlankFramework {
-    fn dispatch(&mut self, mut ctx: Context, msg: Message, pool: &ThreadPool) {
-        if let Some(cmd) = PlankFramework::parse_command(self.command_prefix, &msg.content) {
-            if let Some(command) = self.commands.get(&cmd[0]) {
+/// Stops messages from bots (including ourselves) from going any further.
+pub struct IgnoreBots;
+
+impl Middleware for IgnoreBots {
+    fn before(&self, _ctx: &mut Context, msg: &Message, _state: &mut MessageState) -> Result<Flow, CommandError> {
+        if msg.author.bot {
+            Ok(Flow::Stop)
+        }
+        else {
+            Ok(Flow::Continue)
+        }
+    }
+}
+
+impl PlankFramework {
+    fn find_command(&self, args: &Option<Vec<String>>) -> Option<Arc<Command>> {
+        if let Some(ref cmd) = *args {
+            if let Some(command) = self.commands.read().unwrap().get(&cmd[0]) {
                 if cmd.len() > 1 {
                     info!("Dispatching command '{}' with args: {:?}", &cmd[0], &cmd[1..]);
                 }
                 else {
                     info!("Dispatching command: {}", &cmd[0]);
                 }
-                let c = Arc::clone(command);
-                pool.execute(move || {
-                    if let Err(e) = c.execute(&mut ctx, &msg, &cmd){
-                        error!("{}", e);
-                    };
-                });
+                Some(Arc::clone(command))
             }
             else {
                 info!("Command not found: {:?}", &cmd);
+                None
             }
         }
+        else {
+            None
+        }
+    }
+
+    /// Looks for an unregistered command in the command sources.
+    fn find_in_sources(&self, ctx: &Context, msg: &Message, args: &Option<Vec<String>>) -> Option<Arc<Command>> {
+        let name = &args.as_ref()?[0];
+        let sources = self.sources.read().unwrap().clone();
+        let command = sources.iter().filter_map(|source| source.find(ctx, msg, name)).next();
+        if command.is_some() {
+            info!("Dispatching '{}' from a command source", name);
+        }
+        command
+    }
+
+    fn is_command(&self, args: &Option<Vec<String>>) -> bool {
+        args.as_ref().map_or(false, |args| self.commands.read().unwrap().contains_key(&args[0]))
+    }
+
+    /// Passes a message through the middleware chain and the command.
+    /// The command is looked up after the middleware has run, since middleware may change the arguments.
+    /// Returns whether the command succeeded, or `None` if no command was run.
+    pub fn run(&self, ctx: Context, msg: Message, args: Option<Vec<String>>) -> Option<bool> {
+        self.dispatch(ctx, msg, MessageState::new(args))
+    }
+
+    /// Runs a slash command like `run`, marking it so middleware that only cares about chat messages skips it.
+    pub fn run_interaction(&self, ctx: Context, msg: Message, args: Vec<String>) -> Option<bool> {
+        let mut state = MessageState::new(Some(args));
+        state.from_interaction = true;
+        self.dispatch(ctx, msg, state)
+    }
+
+    fn dispatch(&self, mut ctx: Context, msg: Message, mut state: MessageState) -> Option<bool> {
+        let middleware = self.middleware.read().unwrap().clone();
+
+        for m in middleware.iter() {
+            match m.before(&mut ctx, &msg, &mut state) {
+                Ok(Flow::Continue) => {},
+                Ok(Flow::Stop) => return None,
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        if let Some(args) = state.args.clone() {
+            let name = &args[0];
+            let command = self.find_command(&state.args)
+                .or_else(|| self.find_in_sources(&ctx, &msg, &state.args))
+                .filter(|c| {
+                    let allowed = settings::is_allowed(&ctx, &msg, name, &**c);
+                    if !allowed {
+                        info!("Command '{}' is not allowed in channel {}", name, msg.channel_id);
+                    }
+                    allowed
+                });
+
+            if let Some(c) = command {
+                self.commands_run.fetch_add(1, Ordering::Relaxed);
+                let start = Instant::now();
+                let result = c.execute(&mut ctx, &msg, &args);
+                METRICS.record_command(name, result.is_ok(), start.elapsed());
+                if let Err(ref e) = result {
+                    let id = botlog::error_id();
+                    error!("[{}] {}", id, e);
+                    if let Some(bot_log) = botlog::get(&ctx) {
+                        bot_log.command_error(&id, name, &msg, e);
+                    }
+                }
+                state.result = Some(result);
+            }
+        }
+
+        for m in middleware.iter() {
+            match m.after(&mut ctx, &msg, &state) {
+                Ok(Flow::Continue) => {},
+                Ok(Flow::Stop) => break,
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        state.result.map(|result| result.is_ok())
+    }
+
+    /// Re-runs the command in an edited message, replacing the old responses with the new ones.
+    pub fn redispatch(&self, ctx: Context, update: MessageUpdateEvent) {
+        let tracker = match self.edits {
+            Some(ref tracker) => tracker,
+            None => return,
+        };
+
+        if update.content.is_none() || !tracker.in_window(update.id) {
+            return;
+        }
+
+        let msg = match update.channel_id.message(update.id) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Could not fetch edited message: {}", e);
+                return;
+            }
+        };
+
+        if msg.author.bot {
+            return;
+        }
+
+        let args = PlankFramework::parse_command(self.command_prefix, &msg.content);
+        if self.is_command(&args) || self.find_in_sources(&ctx, &msg, &args).is_some() {
+            tracker.record_invocation(msg.channel_id, msg.id);
+            self.run(ctx, msg, args);
+        }
+    }
+}
+
+impl Framework for PlankFramework {
+    fn dispatch(&mut self, ctx: Context, msg: Message, pool: &ThreadPool) {
+        METRICS.record_event("MESSAGE_CREATE");
+
+        if let Some(ref tracker) = self.edits {
+            if msg.is_own() {
+                if let Some(old) = tracker.record_response(msg.channel_id, msg.id) {
+                    let msg = msg.clone();
+                    pool.execute(move || {
+                        if let Err(e) = edits::replace_response(&msg, old) {
+                            error!("Could not replace response: {}", e);
+                        }
+                    });
+                }
+            }
+        }
+
+        let args = PlankFramework::parse_command(self.command_prefix, &msg.content);
+        let is_command = self.is_command(&args);
+
+        let unknown = args.is_some() && !self.sources.read().unwrap().is_empty();
+        if !is_command && !unknown && self.middleware.read().unwrap().is_empty() {
+            return;
+        }
+
+        if let (true, Some(tracker)) = (is_command, self.edits.as_ref()) {
+            tracker.record_invocation(msg.channel_id, msg.id);
+        }
+
+        let fw = self.clone();
+        pool.execute(move || { fw.run(ctx, msg, args); });
     }
 }
 
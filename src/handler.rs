@@ -1,15 +1,27 @@
+use serenity::model::channel::{Message, Reaction};
 use serenity::model::gateway::Ready;
-use serenity::model::guild::{Guild, PartialGuild};
+use serenity::model::guild::{Guild, Member, PartialGuild};
+use serenity::model::user::User;
 use serenity::client::{Context, EventHandler};
 
 use serenity::prelude::RwLock;
 
-use serenity::model::event::ResumedEvent;
-use serenity::model::id::GuildId;
+use serenity::model::event::{ResumedEvent, MessageUpdateEvent};
+use serenity::model::id::{ChannelId, GuildId, MessageId};
 
 use serde_json;
+use serenity;
+
+use commands::CommandMap;
+use commands::{modlog, polls, reactionroles, welcome};
+use framework::{CommandRegistry, FrameworkContainer};
+use interactions::SlashCommands;
+use botlog;
+use scheduler::{self, Scheduler};
+use metrics::METRICS;
 
 use std::sync::Arc;
+use std::sync;
 
 pub struct PlankHandler;
 
@@ -19,29 +31,157 @@ impl PlankHandler {
     }
 }
 
+fn slash_commands(ctx: &Context) -> Option<(Arc<SlashCommands>, Arc<sync::RwLock<CommandMap>>)> {
+    let data = ctx.data.lock();
+    match (data.get::<SlashCommands>(), data.get::<CommandRegistry>()) {
+        (Some(slash), Some(registry)) => Some((Arc::clone(slash), Arc::clone(registry))),
+        _ => None,
+    }
+}
+
 impl EventHandler for PlankHandler {
 
     fn ready(&self, ctx: Context, ready: Ready) {
+        METRICS.record_event("READY");
         info!("{} is connected! (shard: {})", ready.user.name, ctx.shard_id);
+        if let Some(bot_log) = botlog::get(&ctx) {
+            bot_log.shard_ready(ctx.shard_id);
+        }
+        if let Some(scheduler) = scheduler::get(&ctx) {
+            Scheduler::start(&scheduler);
+        }
+
+        if ctx.shard_id != 0 {
+            return;
+        }
+
+        if let Some((slash, registry)) = slash_commands(&ctx) {
+            match serenity::http::get_current_application_info() {
+                Ok(app) => {
+                    if let Err(e) = slash.register(app.id.0, &registry.read().unwrap()) {
+                        error!("{}", e);
+                    }
+                },
+                Err(e) => error!("Could not get application info: {}", e),
+            }
+        }
     }
 
     fn resume(&self, ctx: Context, _resume: ResumedEvent) {
+        METRICS.record_event("RESUMED");
         info!("Resumed! (shard: {})", ctx.shard_id);
+        if let Some(bot_log) = botlog::get(&ctx) {
+            bot_log.shard_resumed(ctx.shard_id);
+        }
+    }
+
+    fn message(&self, ctx: Context, msg: Message) {
+        modlog::message_created(&ctx, &msg);
+    }
+
+    fn message_update(&self, ctx: Context, update: MessageUpdateEvent) {
+        METRICS.record_event("MESSAGE_UPDATE");
+        modlog::message_updated(&ctx, &update);
+        let fw = {
+            let data = ctx.data.lock();
+            data.get::<FrameworkContainer>().cloned()
+        };
+
+        if let Some(fw) = fw {
+            fw.redispatch(ctx, update);
+        }
+    }
+
+    fn message_delete(&self, ctx: Context, channel_id: ChannelId, message_id: MessageId) {
+        METRICS.record_event("MESSAGE_DELETE");
+        modlog::message_deleted(&ctx, channel_id, message_id);
+    }
+
+    fn message_delete_bulk(&self, ctx: Context, channel_id: ChannelId, message_ids: Vec<MessageId>) {
+        METRICS.record_event("MESSAGE_DELETE_BULK");
+        modlog::messages_deleted(&ctx, channel_id, &message_ids);
+    }
+
+    fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        METRICS.record_event("MESSAGE_REACTION_ADD");
+        polls::reaction_added(&ctx, &reaction);
+        reactionroles::reaction_added(&ctx, &reaction);
+    }
+
+    fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        METRICS.record_event("MESSAGE_REACTION_REMOVE");
+        polls::reaction_removed(&ctx, &reaction);
+        reactionroles::reaction_removed(&ctx, &reaction);
+    }
+
+    fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, member: Member) {
+        METRICS.record_event("GUILD_MEMBER_ADD");
+        modlog::member_joined(&ctx, guild_id, &member);
+        welcome::member_joined(&ctx, guild_id, &member);
+    }
+
+    fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, _member: Option<Member>) {
+        METRICS.record_event("GUILD_MEMBER_REMOVE");
+        modlog::member_left(&ctx, guild_id, &user);
+        welcome::member_left(&ctx, guild_id, &user);
+    }
+
+    fn guild_member_update(&self, ctx: Context, old: Option<Member>, new: Member) {
+        METRICS.record_event("GUILD_MEMBER_UPDATE");
+        modlog::member_updated(&ctx, old.as_ref(), &new);
+    }
+
+    fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, user: User) {
+        METRICS.record_event("GUILD_BAN_ADD");
+        modlog::member_banned(&ctx, guild_id, &user);
+    }
+
+    fn guild_ban_removal(&self, ctx: Context, guild_id: GuildId, user: User) {
+        METRICS.record_event("GUILD_BAN_REMOVE");
+        modlog::member_unbanned(&ctx, guild_id, &user);
     }
 
-    fn guild_create(&self, ctx: Context, guild: Guild, cached: bool) {
-        info!("Joined guild '{}' (shard: {}, cached: {})", guild.name, ctx.shard_id, cached);
+    fn guild_create(&self, ctx: Context, guild: Guild, is_new: bool) {
+        METRICS.record_event("GUILD_CREATE");
+        info!("Joined guild '{}' (shard: {}, new: {})", guild.name, ctx.shard_id, is_new);
+        // Guilds that we were already in get created when the shard connects, those aren't interesting.
+        if is_new {
+            if let Some(bot_log) = botlog::get(&ctx) {
+                bot_log.guild_joined(&guild.name, guild.id.0, guild.member_count);
+            }
+        }
     }
 
     fn guild_delete(&self, ctx: Context, part_guild: PartialGuild, _guildcache: Option<Arc<RwLock<Guild>>>) {
+        METRICS.record_event("GUILD_DELETE");
         info!("Left guild '{}' (shard: {})", part_guild.name, ctx.shard_id);
+        if let Some(bot_log) = botlog::get(&ctx) {
+            bot_log.guild_left(&part_guild.name, part_guild.id.0);
+        }
     }
 
     fn guild_unavailable(&self, ctx: Context, id: GuildId) {
-        info!("Guild '{}' is unavailable! (shard: {})", id, ctx.shard_id)
+        METRICS.record_event("GUILD_UNAVAILABLE");
+        info!("Guild '{}' is unavailable! (shard: {})", id, ctx.shard_id);
+        if let Some(bot_log) = botlog::get(&ctx) {
+            bot_log.guild_unavailable(id.0);
+        }
     }
 
-    fn unknown(&self, _ctx: Context, name: String, data: serde_json::Value) {
+    fn unknown(&self, ctx: Context, name: String, data: serde_json::Value) {
+        METRICS.record_event(&name);
+        if name == "INTERACTION_CREATE" {
+            let handles = {
+                let data = ctx.data.lock();
+                (data.get::<SlashCommands>().cloned(), data.get::<FrameworkContainer>().cloned())
+            };
+            if let (Some(slash), Some(fw)) = handles {
+                if let Err(e) = slash.handle(ctx, &fw, data) {
+                    error!("{}", e);
+                }
+                return;
+            }
+        }
         warn!("Uknown event '{}': {}", name, data);
     }
 }
\ No newline at end of file
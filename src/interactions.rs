@@ -0,0 +1,381 @@
+use commands::{Command, CommandMap, CommandError};
+use commands::settings;
+use framework::PlankFramework;
+use metrics::METRICS;
+
+use chrono::{FixedOffset, Utc};
+use reqwest;
+use reqwest::header::Authorization;
+use serde_json;
+use serde_json::Value;
+use typemap;
+
+use serenity::client::Context;
+use serenity::model::channel::{Message, MessageType};
+use serenity::model::id::{ChannelId, MessageId, RoleId, UserId};
+use serenity::model::user::User;
+use serenity::utils::{parse_role, parse_username};
+
+use std::sync::Arc;
+
+const API_URL: &str = "https://discord.com/api/v10";
+
+// Limits for the names and descriptions of slash commands and their options.
+const MAX_NAME_LEN: usize = 32;
+const MAX_DESCRIPTION_LEN: usize = 100;
+
+// https://discord.com/developers/docs/interactions/application-commands#application-command-object-application-command-option-type
+const OPTION_SUB_COMMAND: u64 = 1;
+const OPTION_SUB_COMMAND_GROUP: u64 = 2;
+const OPTION_STRING: u64 = 3;
+
+const INTERACTION_APPLICATION_COMMAND: u64 = 2;
+const RESPONSE_CHANNEL_MESSAGE: u64 = 4;
+const RESPONSE_DEFERRED_CHANNEL_MESSAGE: u64 = 5;
+const FLAG_EPHEMERAL: u64 = 1 << 6;
+
+/// Registers the commands from the framework as Discord application commands
+/// and routes the interactions back to them.
+pub struct SlashCommands {
+    token: String,
+}
+
+impl typemap::Key for SlashCommands {
+    type Value = Arc<SlashCommands>;
+}
+
+#[derive(Deserialize)]
+struct Interaction {
+    id: MessageId,
+    application_id: String,
+    #[serde(rename = "type")]
+    kind: u64,
+    token: String,
+    channel_id: Option<ChannelId>,
+    data: Option<InteractionData>,
+    member: Option<InteractionMember>,
+    user: Option<User>,
+}
+
+#[derive(Deserialize)]
+struct InteractionMember {
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct InteractionData {
+    name: String,
+    #[serde(default)]
+    options: Vec<InteractionOption>,
+}
+
+#[derive(Deserialize)]
+struct InteractionOption {
+    name: String,
+    #[serde(rename = "type")]
+    kind: u64,
+    value: Option<Value>,
+    #[serde(default)]
+    options: Vec<InteractionOption>,
+}
+
+fn valid_name(name: &str) -> bool {
+    name.len() > 0 && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c == '-' || c == '_' || c.is_lowercase() || c.is_numeric())
+}
+
+fn description(name: &str, command: &Command) -> String {
+    let mut desc = if command.description().is_empty() {
+        format!("Runs the {} command.", name)
+    }
+    else {
+        command.description().to_string()
+    };
+    if desc.chars().count() > MAX_DESCRIPTION_LEN {
+        desc = desc.chars().take(MAX_DESCRIPTION_LEN - 3).collect();
+        desc.push_str("...");
+    }
+    desc
+}
+
+fn args_option(command: &Command) -> Value {
+    let usage = if command.usage().is_empty() {
+        "Arguments for the command".to_string()
+    }
+    else {
+        command.usage().chars().take(MAX_DESCRIPTION_LEN).collect()
+    };
+    json!({
+        "type": OPTION_STRING,
+        "name": "args",
+        "description": usage,
+        "required": false,
+    })
+}
+
+/// Builds the options for a command. Discord only allows groups two levels
+/// deep, so any groups below that are left out.
+fn command_options(command: &Command, depth: usize) -> Vec<Value> {
+    if let Some(subcommands) = command.subcommands() {
+        let mut names: Vec<&String> = subcommands.keys().filter(|n| valid_name(n)).collect();
+        names.sort();
+        names.into_iter().filter_map(|name| {
+            let sub = &*subcommands[name];
+            if sub.subcommands().is_some() {
+                if depth > 0 {
+                    return None;
+                }
+                Some(json!({
+                    "type": OPTION_SUB_COMMAND_GROUP,
+                    "name": name,
+                    "description": description(name, sub),
+                    "options": command_options(sub, depth + 1),
+                }))
+            }
+            else {
+                Some(json!({
+                    "type": OPTION_SUB_COMMAND,
+                    "name": name,
+                    "description": description(name, sub),
+                    "options": [args_option(sub)],
+                }))
+            }
+        }).collect()
+    }
+    else {
+        vec![args_option(command)]
+    }
+}
+
+/// Turns the options of an interaction back into the arguments the command would get from a message.
+fn collect_args(options: &[InteractionOption], args: &mut Vec<String>) {
+    for option in options {
+        match option.kind {
+            OPTION_SUB_COMMAND | OPTION_SUB_COMMAND_GROUP => {
+                args.push(option.name.clone());
+                collect_args(&option.options, args);
+            },
+            OPTION_STRING => {
+                if let Some(Value::String(ref s)) = option.value {
+                    args.extend(PlankFramework::split_args(s));
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Finds the users and roles mentioned in the arguments, since Discord doesn't resolve mentions in string options.
+fn find_mentions(args: &[String]) -> (Vec<UserId>, Vec<RoleId>) {
+    let mut users = Vec::new();
+    let mut roles = Vec::new();
+    for arg in args {
+        if let Some(id) = parse_username(arg).map(UserId) {
+            if !users.contains(&id) {
+                users.push(id);
+            }
+        }
+        else if let Some(id) = parse_role(arg).map(RoleId) {
+            if !roles.contains(&id) {
+                roles.push(id);
+            }
+        }
+    }
+    (users, roles)
+}
+
+impl SlashCommands {
+    pub fn new(token: &str) -> SlashCommands {
+        let token = token.trim();
+        SlashCommands {
+            token: if token.starts_with("Bot ") {
+                token.to_string()
+            }
+            else {
+                format!("Bot {}", token)
+            },
+        }
+    }
+
+    /// Overwrites the global application commands with the ones in the command map.
+    pub fn register(&self, application_id: u64, commands: &CommandMap) -> Result<(), CommandError> {
+        let mut names: Vec<&String> = commands.keys().filter(|n| valid_name(n)).collect();
+        names.sort();
+
+        let body: Vec<Value> = names.into_iter().map(|name| {
+            let command = &*commands[name];
+            json!({
+                "name": name,
+                "description": description(name, command),
+                "options": command_options(command, 0),
+            })
+        }).collect();
+
+        let res = reqwest::Client::new()
+            .put(&format!("{}/applications/{}/commands", API_URL, application_id))
+            .header(Authorization(self.token.clone()))
+            .json(&body)
+            .send();
+        METRICS.record_api_request("discord", &res);
+        let mut res = res?;
+
+        if res.status().is_success() {
+            info!("Registered {} slash command(s)", body.len());
+            Ok(())
+        }
+        else {
+            Err(CommandError::Other(format!("Failed to register slash commands: {} {}", res.status(), res.text().unwrap_or_default())))
+        }
+    }
+
+    /// Handles an `INTERACTION_CREATE` gateway event, running the command through the framework
+    /// like a message would be.
+    pub fn handle(&self, ctx: Context, framework: &PlankFramework, event: Value) -> Result<(), CommandError> {
+        let interaction: Interaction = serde_json::from_value(event)
+            .map_err(|e| CommandError::Other(format!("Could not parse interaction: {}", e)))?;
+
+        if interaction.kind != INTERACTION_APPLICATION_COMMAND {
+            return Ok(());
+        }
+
+        let (data, channel_id) = match (interaction.data, interaction.channel_id) {
+            (Some(data), Some(channel_id)) => (data, channel_id),
+            _ => return Ok(()),
+        };
+
+        let author = match (interaction.member, interaction.user) {
+            (Some(member), _) => member.user,
+            (None, Some(user)) => user,
+            (None, None) => return Ok(()),
+        };
+
+        let mut args = vec![data.name.clone()];
+        collect_args(&data.options, &mut args);
+
+        let command = match framework.commands().read().unwrap().get(&args[0]) {
+            Some(command) => Arc::clone(command),
+            None => {
+                info!("Slash command not found: {:?}", &args);
+                return Ok(());
+            }
+        };
+
+        let (mentions, mention_roles) = find_mentions(&args[1..]);
+        let msg = Message {
+            id: interaction.id,
+            attachments: Vec::new(),
+            author: author,
+            channel_id: channel_id,
+            content: format!("/{}", args.join(" ")),
+            edited_timestamp: None,
+            embeds: Vec::new(),
+            kind: MessageType::Regular,
+            mention_everyone: false,
+            mention_roles: mention_roles,
+            mentions: mentions.into_iter().filter_map(|id| id.get().ok()).collect(),
+            nonce: Value::Null,
+            pinned: false,
+            reactions: Vec::new(),
+            timestamp: Utc::now().with_timezone(&FixedOffset::east(0)),
+            tts: false,
+            webhook_id: None,
+        };
+
+        if !settings::is_allowed(&ctx, &msg, &args[0], &*command) {
+            info!("Slash command '{}' is not allowed in channel {}", &args[0], channel_id);
+            return self.respond_privately(interaction.id, &interaction.token, "That command can't be used here.");
+        }
+
+        info!("Dispatching slash command '{}' with args: {:?}", &args[0], &args[1..]);
+
+        // Commands reply by posting in the channel themselves, so we just let
+        // Discord know we're working on it and clean up when they're done.
+        self.respond(interaction.id, &interaction.token)?;
+
+        // The framework logs command errors itself.
+        match framework.run_interaction(ctx, msg, args) {
+            Some(false) => self.edit_response(&interaction.application_id, &interaction.token, "Something went wrong while running that command."),
+            _ => self.delete_response(&interaction.application_id, &interaction.token),
+        }
+    }
+
+    fn respond(&self, id: MessageId, token: &str) -> Result<(), CommandError> {
+        let res = reqwest::Client::new()
+            .post(&format!("{}/interactions/{}/{}/callback", API_URL, id, token))
+            .json(&json!({ "type": RESPONSE_DEFERRED_CHANNEL_MESSAGE }))
+            .send();
+        check_status(res)
+    }
+
+    /// Responds with a message only the user who used the command can see.
+    fn respond_privately(&self, id: MessageId, token: &str, content: &str) -> Result<(), CommandError> {
+        let res = reqwest::Client::new()
+            .post(&format!("{}/interactions/{}/{}/callback", API_URL, id, token))
+            .json(&json!({
+                "type": RESPONSE_CHANNEL_MESSAGE,
+                "data": { "content": content, "flags": FLAG_EPHEMERAL },
+            }))
+            .send();
+        check_status(res)
+    }
+
+    fn edit_response(&self, application_id: &str, token: &str, content: &str) -> Result<(), CommandError> {
+        let res = reqwest::Client::new()
+            .patch(&format!("{}/webhooks/{}/{}/messages/@original", API_URL, application_id, token))
+            .json(&json!({ "content": content }))
+            .send();
+        check_status(res)
+    }
+
+    fn delete_response(&self, application_id: &str, token: &str) -> Result<(), CommandError> {
+        let res = reqwest::Client::new()
+            .delete(&format!("{}/webhooks/{}/{}/messages/@original", API_URL, application_id, token))
+            .send();
+        check_status(res)
+    }
+}
+
+fn check_status(res: reqwest::Result<reqwest::Response>) -> Result<(), CommandError> {
+    METRICS.record_api_request("discord", &res);
+    let res = res?;
+    if res.status().is_success() {
+        Ok(())
+    }
+    else {
+        Err(CommandError::Other(format!("Failed https request: {}", res.status())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_args_from_options() {
+        let options: Vec<InteractionOption> = serde_json::from_value(json!([
+            {
+                "type": 1,
+                "name": "on",
+                "options": [{ "type": 3, "name": "args", "value": "foo 'bar baz'" }],
+            }
+        ])).unwrap();
+        let mut args = vec!["unfurl".to_string()];
+        collect_args(&options, &mut args);
+        assert_eq!(args, vec!["unfurl", "on", "foo", "bar baz"]);
+    }
+
+    #[test]
+    fn mentions_in_args() {
+        let args: Vec<String> = vec!["<@1>", "hi", "<@!2>", "<@&3>", "<@1>", "@everyone"]
+            .into_iter().map(String::from).collect();
+        assert_eq!(find_mentions(&args), (vec![UserId(1), UserId(2)], vec![RoleId(3)]));
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(valid_name("roll"));
+        assert!(valid_name("set-game_2"));
+        assert!(!valid_name("Roll"));
+        assert!(!valid_name(""));
+    }
+}
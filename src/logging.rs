@@ -0,0 +1,218 @@
+use config::{LoggingConfig, LogFormat, Rotation};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use fern;
+use fern::colors::{Color, ColoredLevelConfig};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// Sets up the global logger from the logging config.
+pub fn setup(cfg: &LoggingConfig) -> Result<(), fern::InitError> {
+    let mut dispatch = fern::Dispatch::new()
+        .level(LevelFilter::Warn)
+        .level_for("plankboat", cfg.level.parse().unwrap_or(LevelFilter::Info));
+
+    for (module, level) in cfg.modules.iter() {
+        dispatch = dispatch.level_for(module.clone(), level.parse().unwrap_or(LevelFilter::Warn));
+    }
+
+    dispatch = dispatch.chain(formatter(cfg.format, true).chain(io::stderr()));
+
+    if !cfg.file.is_empty() {
+        let file = RotatingFile::open(&cfg.file, cfg.rotation, cfg.max_size * 1024 * 1024, cfg.keep)?;
+        dispatch = dispatch.chain(formatter(cfg.format, false).chain(Box::new(file) as Box<Log>));
+    }
+
+    dispatch.apply()?;
+    Ok(())
+}
+
+fn formatter(format: LogFormat, colored: bool) -> fern::Dispatch {
+    let log_colors = ColoredLevelConfig::new()
+        .trace(Color::White)
+        .debug(Color::Blue)
+        .info(Color::Green)
+        .warn(Color::Yellow)
+        .error(Color::Red);
+
+    fern::Dispatch::new().format(move |out, message, record| {
+        let time = Utc::now().format(TIME_FORMAT);
+        match format {
+            LogFormat::Json => out.finish(format_args!("{}", json!({
+                "time": time.to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": message.to_string(),
+            }))),
+            LogFormat::Text if colored => out.finish(format_args!(
+                "{} [{}] <{}> {}",
+                time,
+                log_colors.color(record.level()),
+                record.target(),
+                message
+            )),
+            LogFormat::Text => out.finish(format_args!(
+                "{} [{}] <{}> {}",
+                time,
+                record.level(),
+                record.target(),
+                message
+            )),
+        }
+    })
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+    date: NaiveDate,
+}
+
+/// A log file that gets renamed and replaced with a new one every day or when it grows too big.
+/// Old files get a timestamp appended to their name, and only the newest `keep` of them are kept.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_size: u64,
+    keep: usize,
+    current: Mutex<OpenFile>,
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, rotation: Rotation, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let current = RotatingFile::open_file(&path)?;
+        Ok(RotatingFile {
+            path: path,
+            rotation: rotation,
+            max_size: max_size,
+            keep: keep,
+            current: Mutex::new(current),
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<OpenFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let meta = file.metadata()?;
+        // An existing file keeps the date it was last written to, so that
+        // restarting on a new day still rotates yesterday's log.
+        let date = meta.modified()
+            .map(|t| DateTime::<Utc>::from(t).naive_utc().date())
+            .unwrap_or_else(|_| Utc::now().naive_utc().date());
+        Ok(OpenFile {
+            file: file,
+            size: meta.len(),
+            date: date,
+        })
+    }
+
+    fn needs_rotation(&self, current: &OpenFile, now: DateTime<Utc>, len: u64) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => now.naive_utc().date() != current.date,
+            Rotation::Size => current.size > 0 && current.size + len > self.max_size,
+        }
+    }
+
+    fn rotate(&self, current: &mut OpenFile, now: DateTime<Utc>) -> io::Result<()> {
+        let suffix = match self.rotation {
+            Rotation::Daily => current.date.format("%Y-%m-%d").to_string(),
+            _ => now.format("%Y-%m-%dT%H-%M-%S%.3f").to_string(),
+        };
+
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".");
+        rotated.push(suffix);
+        fs::rename(&self.path, &rotated)?;
+
+        *current = RotatingFile::open_file(&self.path)?;
+        current.date = now.naive_utc().date();
+        prune(&self.path, self.keep)
+    }
+}
+
+/// Deletes the oldest rotated files of a log so that only `keep` of them are left.
+fn prune(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let prefix = match path.file_name() {
+        Some(name) => format!("{}.", name.to_string_lossy()),
+        None => return Ok(()),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect();
+    rotated.sort();
+
+    let excess = rotated.len().saturating_sub(keep);
+    for old in rotated.into_iter().take(excess) {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+impl Log for RotatingFile {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = format!("{}\n", record.args());
+        let mut current = self.current.lock().unwrap();
+
+        let now = Utc::now();
+        if self.needs_rotation(&current, now, line.len() as u64) {
+            // Logging the error would end up back here, so it goes straight to stderr.
+            if let Err(e) = self.rotate(&mut current, now) {
+                eprintln!("Could not rotate log file '{}': {}", self.path.display(), e);
+            }
+        }
+
+        if current.file.write_all(line.as_bytes()).is_ok() {
+            current.size += line.len() as u64;
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.current.lock().unwrap().file.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn prune_keeps_newest() {
+        let dir = env::temp_dir().join(format!("plankboat-log-test-{}", Utc::now().timestamp_subsec_nanos()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &["bot.log", "bot.log.2018-01-01", "bot.log.2018-01-02", "bot.log.2018-01-03", "other.log.2018-01-01"] {
+            File::create(dir.join(name)).unwrap();
+        }
+
+        prune(&dir.join("bot.log"), 2).unwrap();
+
+        let mut left: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, vec!["bot.log", "bot.log.2018-01-02", "bot.log.2018-01-03", "other.log.2018-01-01"]);
+    }
+}
@@ -7,7 +7,7 @@ extern crate rand;
 extern crate fern;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 extern crate chrono;
 extern crate rusqlite;
 extern crate typemap;
@@ -21,37 +21,37 @@ extern crate toml;
 
 use clap::{Arg, App};
 use serenity::prelude::*;
-use std::io::{Read, Write};
 
 mod framework;
 mod handler;
 mod commands;
+mod db;
+mod interactions;
+mod edits;
+mod config;
+mod logging;
+mod metrics;
+mod botlog;
+mod scheduler;
+mod tz;
 
 use std::sync::{Arc, Mutex};
-
-struct DatabaseContainer;
-
-impl typemap::Key for DatabaseContainer {
-    type Value = Arc<Mutex<rusqlite::Connection>>;
-}
-
-#[derive(Deserialize)]
-struct Config {
-    bot_token: Option<String>,
-    shards: Option<u64>,
-    myanimelist: Option<MALcfg>,
-}
-
-#[derive(Deserialize)]
-struct MALcfg {
-    username: String,
-    password: String,
-}
+use std::time::Instant;
+use std::collections::HashSet;
+use serenity::model::id::UserId;
 
 fn main() {
     let matches = App::new("plankboat").version("0.1")
         .author("Adrian H. <adrian@tollyx.net>")
         .about("Crappy discord bot made by a madman")
+        .arg(Arg::with_name("config")
+            .value_name("FILE")
+            .long("config")
+            .short("c")
+            .help("Sets the config file to use. Defaults to config.toml"))
+        .arg(Arg::with_name("example-config")
+            .long("example-config")
+            .help("Prints an example config file and exits"))
         .arg(Arg::with_name("token")
             .value_name("TOKEN")
             .long("token")
@@ -62,67 +62,127 @@ fn main() {
             .long("shards")
             .short("s")
             .help("Sets the amount of shards to start. Will use autosharding if no value is set"))
+        .arg(Arg::with_name("log-level")
+            .value_name("LEVEL")
+            .long("log-level")
+            .help("Sets the log level of the bot (off, error, warn, info, debug or trace)"))
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .multiple(true)
+            .help("Raises the log level by one step, can be repeated"))
         .get_matches();
 
-    setup_logger().unwrap();
-    let mut cfgstr = String::new();
-    
-    if !std::path::Path::new("config.toml").exists() {
-        warn!("Could not find a config file! Creating an example config...");
-        std::fs::File::create("config.toml").expect("Could not create config file").write_all(
-br#"# Bot token, required.
-# bot_token = "Bot AAAAAAAAAAAAAAAAAAA"
-
-# The number of shards to use. 
-# Leave undefined to enable autosharding.
-# shards = 4
-
-# MyAnimeList login, used for the anime and manga commands. 
-# Leaving it undefined will disable the commands.
-# [myanimelist]
-# username = "user"
-# password = "pass"
-"#
-        ).unwrap();
+    if matches.is_present("example-config") {
+        print!("{}", config::Config::example());
         return;
     }
 
-    std::fs::File::open("config.toml").expect("Could not open config file").read_to_string(&mut cfgstr).unwrap();
-    
-    let mut cfg: Config = toml::from_str(&cfgstr).expect("Could not parse config file");
+    let source = config::ConfigSource {
+        path: matches.value_of("config").unwrap_or("config.toml").into(),
+        token: matches.value_of("token").map(String::from),
+        shards: matches.value_of("shards").map(String::from),
+        log_level: matches.value_of("log-level").map(String::from),
+        verbosity: matches.occurrences_of("verbose"),
+    };
 
-    if let Some(s) = matches.value_of("token") {
-        cfg.bot_token = Some(s.to_string());
-    }
+    // The logger is set up from the config, so errors in it can only go to stderr.
+    let cfg = match source.load() {
+        Ok(cfg) => cfg,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for e in errors {
+                eprintln!("    {}", e);
+            }
+            return;
+        }
+    };
 
-    if let Some(s) = matches.value_of("shards") {
-        cfg.shards = Some(s.parse().expect("Invalid shards value."));
+    if let Err(e) = logging::setup(&cfg.logging) {
+        eprintln!("Could not set up logging: {}", e);
+        return;
     }
 
-    if cfg.bot_token == None {
-        error!("Bot token not defined. Quitting...");
-        return;
+    if !source.path.exists() {
+        warn!("Could not find config file '{}', using defaults. Use --example-config to get an example.", source.path.display());
     }
 
-    let mut client = Client::new(&cfg.bot_token.unwrap(), handler::PlankHandler::new())
+    let token = cfg.bot_token.clone().unwrap();
+    let mut client = Client::new(&token, handler::PlankHandler::new())
         .expect("Error creating client");
     
     let mut fw = framework::PlankFramework::new();
-    fw.add_command("roll", commands::games::DiceRoll::new());
-    fw.add_command("roulette", commands::games::Roulette::new());
-    if let Some(mal) = cfg.myanimelist {
-        fw.add_command("anime", commands::myanimelist::AnimeCommand::new(&mal.username, &mal.password));
-        fw.add_command("manga", commands::myanimelist::MangaCommand::new(&mal.username, &mal.password));
+    setup_commands(&mut fw, &cfg);
+
+    if cfg.edit_window > 0 {
+        fw.track_edits(std::time::Duration::from_secs(cfg.edit_window));
     }
 
+    let registry = fw.commands();
+    let fw_handle = fw.clone();
     client.with_framework(fw);
     
+    let mut owners: HashSet<UserId> = cfg.owners.iter().map(|&id| UserId(id)).collect();
+    match serenity::http::get_current_application_info() {
+        Ok(info) => {
+            owners.insert(info.owner.id);
+        },
+        Err(e) => warn!("Could not get the owner of the bot: {}", e),
+    }
+
+    let shards = cfg.shards;
+    let bot_log = cfg.bot_log.channel
+        .map(|id| Arc::new(botlog::BotLog::new(serenity::model::id::ChannelId(id), cfg.bot_log.max_per_minute)));
+    let db = Arc::new(Mutex::new(db::open(&cfg.database).unwrap()));
+    let pool = client.threadpool.clone();
+
+    let scheduler = Arc::new(scheduler::Scheduler::new(Arc::clone(&db), pool.clone()));
+    scheduler.register(commands::moderation::LIFT_JOB, commands::moderation::LiftHandler::new(Arc::clone(&db)));
+    scheduler.register(commands::reminders::REMINDER_JOB, commands::reminders::ReminderHandler::new(Arc::clone(&db)));
+    scheduler.register(commands::polls::POLL_JOB, commands::polls::PollHandler::new(Arc::clone(&db)));
+    let pruning = if cfg.analytics.enabled && cfg.analytics.retention_days > 0 {
+        commands::stats::schedule_pruning(&scheduler, Arc::clone(&db), cfg.analytics.retention_days)
+    }
+    else {
+        scheduler.cancel_key(commands::stats::PRUNE_JOB).map(|_| ())
+    };
+    if let Err(e) = pruning {
+        error!("Could not schedule pruning command invocations: {}", e);
+    }
+
+    if cfg.metrics.enabled {
+        if let Err(e) = metrics::serve(&cfg.metrics.address, Arc::clone(&client.shard_manager), pool.clone()) {
+            error!("Could not serve metrics on {}: {}", cfg.metrics.address, e);
+        }
+    }
+
     {
         let mut data = client.data.lock();
-        data.insert::<DatabaseContainer>(Arc::new(Mutex::new(rusqlite::Connection::open("plankboat.sqlite").unwrap())));
+        data.insert::<commands::owner::StartTime>(Instant::now());
+        data.insert::<commands::owner::ShardManagerContainer>(Arc::clone(&client.shard_manager));
+        data.insert::<commands::Owners>(owners);
+        data.insert::<config::ConfigSource>(source);
+        data.insert::<framework::CommandRegistry>(registry);
+        data.insert::<framework::FrameworkContainer>(fw_handle);
+        if cfg.slash_commands {
+            data.insert::<interactions::SlashCommands>(Arc::new(interactions::SlashCommands::new(&token)));
+        }
+        data.insert::<db::DatabaseContainer>(Arc::clone(&db));
+        data.insert::<scheduler::Scheduler>(Arc::clone(&scheduler));
+        data.insert::<commands::autorespond::AutoResponses>(Arc::new(commands::autorespond::AutoResponses::new()));
+        if cfg.message_cache_size > 0 {
+            data.insert::<commands::modlog::MessageCache>(Arc::new(commands::modlog::MessageCache::new(cfg.message_cache_size)));
+        }
+        if let Some(ref bot_log) = bot_log {
+            data.insert::<botlog::BotLog>(Arc::clone(bot_log));
+        }
+        data.insert::<config::ConfigContainer>(Arc::new(cfg));
     }
-    
-    if let Some(s) = cfg.shards {
+
+    if let Some(ref bot_log) = bot_log {
+        bot_log.started();
+    }
+
+    if let Some(s) = shards {
         info!("Starting plankboat with {} shard(s)...", s);
         client.start_shards(s)
     }
@@ -130,45 +190,83 @@ br#"# Bot token, required.
         info!("Starting plankboat with autosharding...");
         client.start_autosharded()
     }.expect("Fatal error");
+
+    info!("Waiting for running commands to finish...");
+    pool.join();
+    if let Some(ref bot_log) = bot_log {
+        bot_log.shutting_down();
+    }
     info!("Exiting...");
 }
 
-fn setup_logger() -> Result<(), fern::InitError> {
-    use fern::colors::{Color, ColoredLevelConfig};
-    let log_colors = ColoredLevelConfig::new()
-        .trace(Color::White)
-        .debug(Color::Blue)
-        .info(Color::Green)
-        .warn(Color::Yellow)
-        .error(Color::Red);
-
-    fern::Dispatch::new()
-        .level(log::LevelFilter::Warn)
-        .level_for("plankboat", log::LevelFilter::Info)
-        .chain(fern::Dispatch::new()
-            .format(move |out, message, record| {
-                out.finish(format_args!(
-                    "{} [{}] <{}> {}",
-                    chrono::Local::now().format("%H:%M:%S"),
-                    log_colors.color(record.level()),
-                    record.target(),
-                    message
-                ))
-            })
-            .chain(std::io::stderr())
-        )
-        .chain(fern::Dispatch::new()
-            .format(move |out, message, record| {
-                out.finish(format_args!(
-                    "{} [{}] <{}> {}",
-                    chrono::Local::now().format("%H:%M:%S"),
-                    record.level(),
-                    record.target(),
-                    message
-                ))
-            })
-            .chain(fern::log_file("output.log")?)
-        )
-        .apply()?;
-    Ok(())
+/// Registers the commands and middleware that are enabled in the config.
+fn setup_commands(fw: &mut framework::PlankFramework, cfg: &config::Config) {
+    fw.add_middleware(framework::IgnoreBots);
+    let help = commands::help::HelpCommand::new(fw.prefix());
+    fw.add_command("help", help);
+    fw.add_command("reload", commands::owner::ReloadCommand::new(setup_commands));
+    fw.add_command("status", commands::owner::StatusCommand);
+    fw.add_command("guilds", commands::owner::GuildsCommand);
+    fw.add_command("leave", commands::owner::LeaveCommand);
+    fw.add_command("shutdown", commands::owner::ShutdownCommand);
+    fw.add_command("setgame", commands::owner::SetGameCommand);
+    fw.add_command("setstatus", commands::owner::SetStatusCommand);
+    fw.add_command("sql", commands::owner::SqlCommand);
+    fw.add_command("commands", commands::settings::settings_group());
+    fw.add_command("modlog", commands::modlog::modlog_group());
+    fw.add_command("kick", commands::moderation::ModerationCommand::new(commands::moderation::Action::Kick));
+    fw.add_command("ban", commands::moderation::ModerationCommand::new(commands::moderation::Action::Ban));
+    fw.add_command("unban", commands::moderation::ModerationCommand::new(commands::moderation::Action::Unban));
+    fw.add_command("mute", commands::moderation::ModerationCommand::new(commands::moderation::Action::Mute));
+    fw.add_command("unmute", commands::moderation::ModerationCommand::new(commands::moderation::Action::Unmute));
+    fw.add_command("warn", commands::moderation::ModerationCommand::new(commands::moderation::Action::Warn));
+    fw.add_command("purge", commands::moderation::PurgeCommand);
+    fw.add_command("case", commands::moderation::CaseCommand);
+    fw.add_command("cases", commands::moderation::CasesCommand);
+    fw.add_command("autorespond", commands::autorespond::autorespond_group());
+    fw.add_command("reactionrole", commands::reactionroles::reactionrole_group());
+    fw.add_command("welcome", commands::welcome::welcome_group());
+    fw.add_command("goodbye", commands::welcome::goodbye_group());
+    fw.add_command("levels", commands::levels::levels_group());
+    fw.add_command("rank", commands::levels::RankCommand);
+    fw.add_command("leaderboard", commands::levels::LeaderboardCommand);
+    fw.add_middleware(commands::levels::XpTracker);
+    fw.add_middleware(commands::autorespond::AutoResponder);
+    if cfg.analytics.enabled {
+        fw.add_middleware(commands::stats::CommandLog);
+        fw.add_command("stats", commands::stats::StatsCommand);
+    }
+    fw.add_command("remindme", commands::reminders::RemindMeCommand);
+    fw.add_command("remind", commands::reminders::RemindCommand);
+    fw.add_command("reminders", commands::reminders::reminders_group());
+    fw.add_command("tz", commands::time::tz_group());
+    fw.add_command("time", commands::time::TimeCommand);
+    fw.add_command("convert", commands::time::ConvertCommand);
+    fw.add_command("tag", commands::tags::tag_group());
+    fw.add_command("poll", commands::polls::PollCommand);
+    fw.add_command("endpoll", commands::polls::EndPollCommand);
+    fw.add_source(commands::tags::TagSource);
+    fw.add_command("roll", commands::games::DiceRoll::new());
+    fw.add_command("roulette", commands::games::Roulette::new());
+    fw.add_command("coinflip", commands::games::CoinFlip);
+    fw.add_command("slots", commands::games::Slots);
+    fw.add_command("daily", commands::economy::DailyCommand);
+    fw.add_command("balance", commands::economy::BalanceCommand);
+    fw.add_command("give", commands::economy::GiveCommand);
+    if cfg.myanimelist.is_enabled() {
+        let mal = &cfg.myanimelist;
+        fw.add_command("anime", commands::myanimelist::AnimeCommand::new(&mal.username, &mal.password));
+        fw.add_command("manga", commands::myanimelist::MangaCommand::new(&mal.username, &mal.password));
+        fw.add_command("unfurl", commands::myanimelist::unfurl_group());
+        fw.add_middleware(commands::myanimelist::LinkUnfurler::new(&mal.username, &mal.password));
+    }
+
+    for name in cfg.disabled_commands.iter() {
+        if name == "reload" {
+            warn!("The reload command can't be disabled");
+        }
+        else if !fw.remove_command(name) {
+            warn!("Unknown command in disabled_commands: {}", name);
+        }
+    }
 }
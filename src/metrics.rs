@@ -0,0 +1,243 @@
+use reqwest;
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::prelude::Mutex;
+use threadpool::ThreadPool;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Upper bounds in seconds of the command latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+lazy_static! {
+    /// The metrics of the bot, shared by everything that records them.
+    pub static ref METRICS: Metrics = Metrics::new();
+}
+
+#[derive(Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&b| value <= b) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The state of a shard at the time metrics are collected.
+pub struct ShardState {
+    pub id: u64,
+    pub stage: String,
+    pub latency: Option<Duration>,
+}
+
+/// Counters and histograms for the things the bot does, rendered in the Prometheus text format.
+pub struct Metrics {
+    commands: sync::Mutex<BTreeMap<(String, &'static str), u64>>,
+    latencies: sync::Mutex<BTreeMap<String, Histogram>>,
+    events: sync::Mutex<BTreeMap<String, u64>>,
+    api_requests: sync::Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            commands: sync::Mutex::new(BTreeMap::new()),
+            latencies: sync::Mutex::new(BTreeMap::new()),
+            events: sync::Mutex::new(BTreeMap::new()),
+            api_requests: sync::Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records a finished command, whether it failed and how long it took.
+    pub fn record_command(&self, name: &str, ok: bool, duration: Duration) {
+        let result = if ok { "ok" } else { "error" };
+        *self.commands.lock().unwrap().entry((name.to_string(), result)).or_insert(0) += 1;
+        self.latencies.lock().unwrap()
+            .entry(name.to_string())
+            .or_insert_with(Histogram::new)
+            .observe(seconds(duration));
+    }
+
+    /// Records a gateway event that reached the event handler.
+    pub fn record_event(&self, event: &str) {
+        *self.events.lock().unwrap().entry(event.to_string()).or_insert(0) += 1;
+    }
+
+    /// Records a request made with our own HTTP client to an API, by status code or `error` if it failed without one.
+    /// Requests serenity makes to Discord on its own aren't visible from here, so they aren't counted.
+    pub fn record_api_request(&self, provider: &'static str, res: &reqwest::Result<reqwest::Response>) {
+        let status = match *res {
+            Ok(ref res) => res.status().as_u16().to_string(),
+            Err(ref e) => e.status().map(|s| s.as_u16().to_string()).unwrap_or_else(|| "error".to_string()),
+        };
+        *self.api_requests.lock().unwrap().entry((provider, status)).or_insert(0) += 1;
+    }
+
+    /// Renders all the metrics, along with the shard and threadpool states.
+    pub fn render(&self, shards: &[ShardState], queued: usize, active: usize) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP plankboat_commands_total Commands run, by command and result.\n");
+        out.push_str("# TYPE plankboat_commands_total counter\n");
+        for (&(ref command, result), count) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(out, "plankboat_commands_total{{command=\"{}\",result=\"{}\"}} {}", escape(command), result, count);
+        }
+
+        out.push_str("# HELP plankboat_command_duration_seconds How long commands took to run.\n");
+        out.push_str("# TYPE plankboat_command_duration_seconds histogram\n");
+        for (command, histogram) in self.latencies.lock().unwrap().iter() {
+            let command = escape(command);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(out, "plankboat_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}", command, bound, cumulative);
+            }
+            let _ = writeln!(out, "plankboat_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}", command, histogram.count);
+            let _ = writeln!(out, "plankboat_command_duration_seconds_sum{{command=\"{}\"}} {}", command, histogram.sum);
+            let _ = writeln!(out, "plankboat_command_duration_seconds_count{{command=\"{}\"}} {}", command, histogram.count);
+        }
+
+        out.push_str("# HELP plankboat_events_total Gateway events handled, by event type.\n");
+        out.push_str("# TYPE plankboat_events_total counter\n");
+        for (event, count) in self.events.lock().unwrap().iter() {
+            let _ = writeln!(out, "plankboat_events_total{{event=\"{}\"}} {}", escape(event), count);
+        }
+
+        out.push_str("# HELP plankboat_api_requests_total Requests to external APIs and the interaction endpoints, \
+                      by provider and status code. Doesn't include serenity's own Discord requests.\n");
+        out.push_str("# TYPE plankboat_api_requests_total counter\n");
+        for (&(provider, ref status), count) in self.api_requests.lock().unwrap().iter() {
+            let _ = writeln!(out, "plankboat_api_requests_total{{provider=\"{}\",status=\"{}\"}} {}", provider, status, count);
+        }
+
+        out.push_str("# HELP plankboat_threadpool_queued_jobs Jobs waiting for a thread in the command threadpool.\n");
+        out.push_str("# TYPE plankboat_threadpool_queued_jobs gauge\n");
+        let _ = writeln!(out, "plankboat_threadpool_queued_jobs {}", queued);
+        out.push_str("# HELP plankboat_threadpool_active_jobs Jobs running in the command threadpool.\n");
+        out.push_str("# TYPE plankboat_threadpool_active_jobs gauge\n");
+        let _ = writeln!(out, "plankboat_threadpool_active_jobs {}", active);
+
+        out.push_str("# HELP plankboat_shard_connected Whether a shard is connected to the gateway, by connection stage.\n");
+        out.push_str("# TYPE plankboat_shard_connected gauge\n");
+        for shard in shards {
+            let connected = if shard.stage == "connected" { 1 } else { 0 };
+            let _ = writeln!(out, "plankboat_shard_connected{{shard=\"{}\",stage=\"{}\"}} {}", shard.id, shard.stage, connected);
+        }
+        out.push_str("# HELP plankboat_shard_latency_seconds Heartbeat latency of a shard.\n");
+        out.push_str("# TYPE plankboat_shard_latency_seconds gauge\n");
+        for shard in shards {
+            if let Some(latency) = shard.latency {
+                let _ = writeln!(out, "plankboat_shard_latency_seconds{{shard=\"{}\"}} {}", shard.id, seconds(latency));
+            }
+        }
+
+        out
+    }
+}
+
+/// Starts a thread serving the metrics over HTTP at `/metrics`.
+pub fn serve(address: &str, shard_manager: Arc<Mutex<ShardManager>>, pool: ThreadPool) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving metrics at http://{}/metrics", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &shard_manager, &pool));
+            if let Err(e) = result {
+                warn!("Could not serve metrics: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, shard_manager: &Mutex<ShardManager>, pool: &ThreadPool) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Read through the headers, we don't need any of them.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let shards: Vec<ShardState> = {
+                let manager = shard_manager.lock();
+                let runners = manager.runners.lock();
+                runners.iter().map(|(id, info)| ShardState {
+                    id: id.0,
+                    stage: info.stage.to_string(),
+                    latency: info.latency,
+                }).collect()
+            };
+            ("200 OK", METRICS.render(&shards, pool.queued_count(), pool.active_count()))
+        },
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_command("roll", true, Duration::from_millis(20));
+        metrics.record_command("roll", false, Duration::from_millis(200));
+        metrics.record_event("MESSAGE_CREATE");
+
+        let shards = vec![ShardState { id: 0, stage: "connected".to_string(), latency: Some(Duration::from_millis(50)) }];
+        let text = metrics.render(&shards, 1, 2);
+
+        assert!(text.contains("plankboat_commands_total{command=\"roll\",result=\"ok\"} 1\n"));
+        assert!(text.contains("plankboat_commands_total{command=\"roll\",result=\"error\"} 1\n"));
+        assert!(text.contains("plankboat_command_duration_seconds_bucket{command=\"roll\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("plankboat_command_duration_seconds_bucket{command=\"roll\",le=\"0.25\"} 2\n"));
+        assert!(text.contains("plankboat_command_duration_seconds_count{command=\"roll\"} 2\n"));
+        assert!(text.contains("plankboat_events_total{event=\"MESSAGE_CREATE\"} 1\n"));
+        assert!(text.contains("plankboat_threadpool_queued_jobs 1\n"));
+        assert!(text.contains("plankboat_shard_connected{shard=\"0\",stage=\"connected\"} 1\n"));
+        assert!(text.contains("plankboat_shard_latency_seconds{shard=\"0\"} 0.05\n"));
+    }
+}
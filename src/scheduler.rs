@@ -0,0 +1,480 @@
+use commands::CommandError;
+
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use rusqlite;
+use rusqlite::Connection;
+use serde_json::{self, Value};
+use threadpool::ThreadPool;
+use typemap;
+
+use serenity::client::Context;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Longest time to sleep between looking for due jobs, in case one was added by something else.
+const MAX_SLEEP_SECS: i64 = 60;
+
+/// Jobs that fail are retried after this many seconds, doubled for every attempt.
+const RETRY_DELAY_SECS: i64 = 30;
+
+/// Longest time to wait before retrying a failed job.
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+
+/// How many due jobs to pick up at once.
+const BATCH_SIZE: i64 = 50;
+
+/// How far ahead to look for the next time a cron schedule matches.
+const CRON_SEARCH_DAYS: i64 = 5 * 366;
+
+/// A cron schedule with the usual five fields: minute, hour, day of month, month and day of week.
+/// Times are in UTC.
+#[derive(Debug, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_number(value: &str, field: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("'{}' is not a number in the {} field", value, field))
+}
+
+/// Parses a cron field like `*`, `5`, `1-5`, `*/15` or `1,15,30` into a bit set of the values it matches.
+fn parse_field(value: &str, field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in value.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], parse_number(&part[i + 1..], field)?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("The step can't be 0 in the {} field", field));
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        }
+        else if let Some(i) = range.find('-') {
+            (parse_number(&range[..i], field)?, parse_number(&range[i + 1..], field)?)
+        }
+        else {
+            let start = parse_number(range, field)?;
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is out of range in the {} field, it has to be within {}-{}", part, field, min, max));
+        }
+
+        let mut i = start;
+        while i <= end {
+            bits |= 1 << i;
+            i += step;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Cron {
+    /// Parses a cron expression, or one of `@hourly`, `@daily`, `@weekly` and `@monthly`.
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 fields in the cron expression '{}', found {}", expression, fields.len()));
+        }
+
+        let mut weekdays = parse_field(fields[4], "day of week", 0, 7)?;
+        // Both 0 and 7 mean Sunday.
+        if has(weekdays, 7) {
+            weekdays |= 1;
+        }
+
+        Ok(Cron {
+            minutes: parse_field(fields[0], "minute", 0, 59)?,
+            hours: parse_field(fields[1], "hour", 0, 23)?,
+            days: parse_field(fields[2], "day of month", 1, 31)?,
+            months: parse_field(fields[3], "month", 1, 12)?,
+            weekdays: weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        // Like cron, a day matches either field if both are restricted.
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Finds the first time after `timestamp` that the schedule matches.
+    pub fn next_after(&self, timestamp: i64) -> Option<i64> {
+        let mut time = NaiveDateTime::from_timestamp((timestamp / 60 + 1) * 60, 0);
+        let limit = time + ChronoDuration::days(CRON_SEARCH_DAYS);
+
+        while time < limit {
+            let date = time.date();
+            if !has(self.months, date.month()) {
+                let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+                time = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0);
+            }
+            else if !self.matches_day(date) {
+                time = date.succ().and_hms(0, 0, 0);
+            }
+            else if !has(self.hours, time.hour()) {
+                time = date.and_hms(time.hour(), 0, 0) + ChronoDuration::hours(1);
+            }
+            else if !has(self.minutes, time.minute()) {
+                time = time + ChronoDuration::minutes(1);
+            }
+            else {
+                return Some(time.timestamp());
+            }
+        }
+        None
+    }
+}
+
+/// A job waiting to run, or running.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub payload: Value,
+    pub due_at: i64,
+    pub schedule: Option<String>,
+    pub attempts: i64,
+    pub max_retries: i64,
+}
+
+/// A job to add to the scheduler.
+pub struct NewJob {
+    kind: String,
+    key: Option<String>,
+    payload: Value,
+    due_at: i64,
+    schedule: Option<String>,
+    max_retries: i64,
+}
+
+impl NewJob {
+    /// A job that runs once, at a unix timestamp.
+    pub fn at(kind: &str, due_at: i64, payload: Value) -> NewJob {
+        NewJob {
+            kind: kind.to_string(),
+            key: None,
+            payload: payload,
+            due_at: due_at,
+            schedule: None,
+            max_retries: 3,
+        }
+    }
+
+    /// A job that runs every time a cron schedule matches.
+    pub fn recurring(kind: &str, schedule: &str, payload: Value) -> Result<NewJob, String> {
+        let due_at = Cron::parse(schedule)?
+            .next_after(Utc::now().timestamp())
+            .ok_or_else(|| format!("The cron schedule '{}' never matches", schedule))?;
+        Ok(NewJob {
+            schedule: Some(schedule.to_string()),
+            ..NewJob::at(kind, due_at, payload)
+        })
+    }
+
+    /// Sets a unique key for the job, so it can be cancelled without knowing its id.
+    /// Adding a job with the same key as another one replaces the old one.
+    pub fn key(mut self, key: &str) -> NewJob {
+        self.key = Some(key.to_string());
+        self
+    }
+}
+
+/// Runs the jobs of a kind.
+pub trait JobHandler: Send + Sync + 'static {
+    fn run(&self, job: &Job) -> Result<(), CommandError>;
+}
+
+impl<F> JobHandler for F where F: Fn(&Job) -> Result<(), CommandError> + Send + Sync + 'static {
+    fn run(&self, job: &Job) -> Result<(), CommandError> {
+        self(job)
+    }
+}
+
+pub fn add_job(conn: &Connection, job: &NewJob) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT OR REPLACE INTO jobs (kind, key, payload, due_at, schedule, max_retries) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[&job.kind, &job.key, &job.payload.to_string(), &job.due_at, &job.schedule, &job.max_retries])?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Marks the jobs that are due as running and returns them.
+pub fn claim_due(conn: &Connection, now: i64) -> rusqlite::Result<Vec<Job>> {
+    let mut jobs = Vec::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id, kind, payload, due_at, schedule, attempts, max_retries FROM jobs
+             WHERE state = 'pending' AND due_at <= ?1 ORDER BY due_at LIMIT ?2")?;
+        let rows = stmt.query_map(&[&now, &BATCH_SIZE], |row| {
+            let payload: String = row.get(2);
+            Job {
+                id: row.get(0),
+                kind: row.get(1),
+                payload: serde_json::from_str(&payload).unwrap_or(Value::Null),
+                due_at: row.get(3),
+                schedule: row.get(4),
+                attempts: row.get(5),
+                max_retries: row.get(6),
+            }
+        })?;
+        for row in rows {
+            jobs.push(row?);
+        }
+    }
+    for job in jobs.iter() {
+        conn.execute("UPDATE jobs SET state = 'running' WHERE id = ?1", &[&job.id])?;
+    }
+    Ok(jobs)
+}
+
+/// Records how a job went. Recurring jobs get scheduled for their next run,
+/// failed jobs get retried with a growing delay until they run out of retries.
+pub fn finish(conn: &Connection, job: &Job, result: Result<(), String>, now: i64) -> rusqlite::Result<()> {
+    let next_run = job.schedule.as_ref()
+        .and_then(|schedule| Cron::parse(schedule).ok())
+        .and_then(|cron| cron.next_after(now));
+
+    match result {
+        Err(ref e) if job.attempts < job.max_retries => {
+            let delay = (RETRY_DELAY_SECS << job.attempts.min(20)).min(MAX_RETRY_DELAY_SECS);
+            conn.execute(
+                "UPDATE jobs SET state = 'pending', attempts = attempts + 1, due_at = ?2, last_error = ?3 WHERE id = ?1",
+                &[&job.id, &(now + delay), e])?;
+        },
+        _ => {
+            let error = result.err();
+            match next_run {
+                Some(due_at) => conn.execute(
+                    "UPDATE jobs SET state = 'pending', attempts = 0, due_at = ?2, last_error = ?3 WHERE id = ?1",
+                    &[&job.id, &due_at, &error])?,
+                // Failed jobs are kept around so they can be looked into.
+                None if error.is_some() => conn.execute(
+                    "UPDATE jobs SET state = 'failed', last_error = ?2 WHERE id = ?1",
+                    &[&job.id, &error])?,
+                None => conn.execute("DELETE FROM jobs WHERE id = ?1", &[&job.id])?,
+            };
+        },
+    }
+    Ok(())
+}
+
+fn next_due(conn: &Connection) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT MIN(due_at) FROM jobs WHERE state = 'pending'", &[], |row| row.get(0))
+}
+
+/// Stores delayed and recurring jobs in the database and runs them on the
+/// threadpool when they are due, picking up where it left off after a restart.
+pub struct Scheduler {
+    db: Arc<Mutex<Connection>>,
+    handlers: RwLock<HashMap<String, Arc<JobHandler>>>,
+    pool: Mutex<Option<ThreadPool>>,
+    wake: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl typemap::Key for Scheduler {
+    type Value = Arc<Scheduler>;
+}
+
+/// Fetches the scheduler from the client data.
+pub fn get(ctx: &Context) -> Option<Arc<Scheduler>> {
+    let data = ctx.data.lock();
+    data.get::<Scheduler>().map(Arc::clone)
+}
+
+impl Scheduler {
+    pub fn new(db: Arc<Mutex<Connection>>, pool: ThreadPool) -> Scheduler {
+        Scheduler {
+            db: db,
+            handlers: RwLock::new(HashMap::new()),
+            pool: Mutex::new(Some(pool)),
+            wake: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+
+    /// Sets the handler that runs the jobs of a kind.
+    pub fn register<H: JobHandler>(&self, kind: &str, handler: H) {
+        self.handlers.write().unwrap().insert(kind.to_string(), Arc::new(handler));
+    }
+
+    /// Adds a job and returns its id.
+    pub fn schedule(&self, job: NewJob) -> rusqlite::Result<i64> {
+        let id = add_job(&self.db.lock().unwrap(), &job)?;
+        self.notify();
+        Ok(id)
+    }
+
+    /// Cancels the job with a key, returns whether there was one to cancel.
+    pub fn cancel_key(&self, key: &str) -> rusqlite::Result<bool> {
+        let n = self.db.lock().unwrap().execute("DELETE FROM jobs WHERE key = ?1 AND state != 'running'", &[&key])?;
+        Ok(n > 0)
+    }
+
+    fn notify(&self) {
+        let &(ref woken, ref condvar) = &*self.wake;
+        *woken.lock().unwrap() = true;
+        condvar.notify_one();
+    }
+
+    /// Starts running jobs. Only the first call does anything, so it's safe to call on every `ready`.
+    pub fn start(scheduler: &Arc<Scheduler>) {
+        let pool = match scheduler.pool.lock().unwrap().take() {
+            Some(pool) => pool,
+            None => return,
+        };
+
+        // Jobs that were running when the bot stopped never finished, so they get another go.
+        match scheduler.db.lock().unwrap().execute("UPDATE jobs SET state = 'pending' WHERE state = 'running'", &[]) {
+            Ok(0) => {},
+            Ok(n) => info!("Restarting {} job(s) that were interrupted", n),
+            Err(e) => error!("Could not reset interrupted jobs: {}", e),
+        }
+
+        let scheduler = Arc::clone(scheduler);
+        thread::spawn(move || loop {
+            if let Err(e) = scheduler.run_due(&pool) {
+                error!("Could not run scheduled jobs: {}", e);
+            }
+            scheduler.sleep();
+        });
+    }
+
+    fn run_due(&self, pool: &ThreadPool) -> rusqlite::Result<()> {
+        let jobs = claim_due(&self.db.lock().unwrap(), Utc::now().timestamp())?;
+        for job in jobs {
+            let handler = self.handlers.read().unwrap().get(&job.kind).map(Arc::clone);
+            let db = Arc::clone(&self.db);
+            pool.execute(move || {
+                let late = Utc::now().timestamp() - job.due_at;
+                if late > MAX_SLEEP_SECS {
+                    info!("Running {} job {}, {}s late", job.kind, job.id, late);
+                }
+                else {
+                    debug!("Running {} job {}", job.kind, job.id);
+                }
+                let result = match handler {
+                    Some(handler) => handler.run(&job).map_err(|e| e.to_string()),
+                    None => Err(format!("No handler for jobs of kind '{}'", job.kind)),
+                };
+                if let Err(ref e) = result {
+                    warn!("{} job {} failed (attempt {}): {}", job.kind, job.id, job.attempts + 1, e);
+                }
+                if let Err(e) = finish(&db.lock().unwrap(), &job, result, Utc::now().timestamp()) {
+                    error!("Could not update {} job {}: {}", job.kind, job.id, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Sleeps until the next job is due or a new one gets added.
+    fn sleep(&self) {
+        let now = Utc::now().timestamp();
+        let secs = match next_due(&self.db.lock().unwrap()) {
+            Ok(Some(due_at)) => (due_at - now).max(1).min(MAX_SLEEP_SECS),
+            _ => MAX_SLEEP_SECS,
+        };
+
+        let &(ref woken, ref condvar) = &*self.wake;
+        let mut woken = woken.lock().unwrap();
+        if !*woken {
+            woken = condvar.wait_timeout(woken, Duration::from_secs(secs as u64)).unwrap().0;
+        }
+        *woken = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn timestamp(y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0).timestamp()
+    }
+
+    #[test]
+    fn cron_next_run() {
+        let start = timestamp(2018, 1, 31, 12, 30);
+        assert_eq!(Cron::parse("@hourly").unwrap().next_after(start), Some(timestamp(2018, 1, 31, 13, 0)));
+        assert_eq!(Cron::parse("*/15 * * * *").unwrap().next_after(start), Some(timestamp(2018, 1, 31, 12, 45)));
+        assert_eq!(Cron::parse("0 9 1 * *").unwrap().next_after(start), Some(timestamp(2018, 2, 1, 9, 0)));
+        // 2018-02-05 was a monday.
+        assert_eq!(Cron::parse("0 8 * * 1-5").unwrap().next_after(timestamp(2018, 2, 3, 0, 0)), Some(timestamp(2018, 2, 5, 8, 0)));
+        assert_eq!(Cron::parse("0 0 30 2 *").unwrap().next_after(start), None);
+
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert_eq!(Cron::parse("0 0 * * 7"), Cron::parse("0 0 * * 0,7"));
+    }
+
+    #[test]
+    fn jobs_retry_and_recur() {
+        let conn = db::open(":memory:").unwrap();
+
+        let mut once = NewJob::at("once", 100, json!({"n": 1}));
+        once.max_retries = 1;
+        add_job(&conn, &once).unwrap();
+        let mut recurring = NewJob::recurring("hourly", "@hourly", Value::Null).unwrap();
+        recurring.due_at = 100;
+        add_job(&conn, &recurring.key("hourly")).unwrap();
+        add_job(&conn, &NewJob::at("later", 1000, Value::Null)).unwrap();
+
+        let jobs = claim_due(&conn, 100).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].payload["n"], 1);
+        assert!(claim_due(&conn, 100).unwrap().is_empty());
+
+        // The one-off job fails and gets retried, then fails for good.
+        let once = jobs.iter().find(|j| j.kind == "once").unwrap();
+        finish(&conn, once, Err("oops".to_string()), 100).unwrap();
+        let retried = claim_due(&conn, 100 + RETRY_DELAY_SECS).unwrap();
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].attempts, 1);
+        finish(&conn, &retried[0], Err("oops".to_string()), 200).unwrap();
+        let state: String = conn.query_row("SELECT state FROM jobs WHERE kind = 'once'", &[], |row| row.get(0)).unwrap();
+        assert_eq!(state, "failed");
+
+        // The recurring one moves on to the next hour.
+        let hourly = jobs.iter().find(|j| j.kind == "hourly").unwrap();
+        finish(&conn, hourly, Ok(()), 100).unwrap();
+        assert_eq!(next_due(&conn).unwrap(), Some(1000));
+        let due_at: i64 = conn.query_row("SELECT due_at FROM jobs WHERE key = 'hourly'", &[], |row| row.get(0)).unwrap();
+        assert_eq!(due_at, 3600);
+
+        // Adding a job with the same key replaces it.
+        add_job(&conn, &NewJob::at("hourly", 50, Value::Null).key("hourly")).unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM jobs WHERE key = 'hourly'", &[], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,628 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use rusqlite;
+use rusqlite::Connection;
+
+use serenity::model::id::UserId;
+
+/// Offsets further from UTC than this don't exist anywhere.
+const MAX_OFFSET_HOURS: i32 = 14;
+
+/// A timezone from the embedded database, with the rules it currently follows
+/// as a POSIX TZ string. Past changes to the rules aren't kept, so times
+/// from years ago may come out wrong, but it's small and works offline.
+#[derive(Debug, PartialEq)]
+pub struct Zone {
+    pub name: &'static str,
+    rule: &'static str,
+}
+
+pub const ZONES: &[Zone] = &[
+    Zone { name: "Europe/London", rule: "GMT0BST,M3.5.0/1,M10.5.0" },
+    Zone { name: "Europe/Dublin", rule: "GMT0IST,M3.5.0/1,M10.5.0" },
+    Zone { name: "Europe/Lisbon", rule: "WET0WEST,M3.5.0/1,M10.5.0" },
+    Zone { name: "Atlantic/Reykjavik", rule: "GMT0" },
+    Zone { name: "Atlantic/Canary", rule: "WET0WEST,M3.5.0/1,M10.5.0" },
+    Zone { name: "Europe/Paris", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Berlin", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Madrid", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Rome", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Amsterdam", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Brussels", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Luxembourg", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Zurich", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Vienna", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Prague", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Budapest", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Warsaw", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Stockholm", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Oslo", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Copenhagen", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Belgrade", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Zagreb", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Malta", rule: "CET-1CEST,M3.5.0,M10.5.0/3" },
+    Zone { name: "Europe/Helsinki", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Tallinn", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Riga", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Vilnius", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Kiev", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Kyiv", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Bucharest", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Sofia", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Athens", rule: "EET-2EEST,M3.5.0/3,M10.5.0/4" },
+    Zone { name: "Europe/Istanbul", rule: "<+03>-3" },
+    Zone { name: "Europe/Minsk", rule: "<+03>-3" },
+    Zone { name: "Europe/Moscow", rule: "MSK-3" },
+    Zone { name: "Africa/Casablanca", rule: "<+01>-1" },
+    Zone { name: "Africa/Lagos", rule: "WAT-1" },
+    Zone { name: "Africa/Cairo", rule: "EET-2EEST,M4.5.5/0,M10.5.4/24" },
+    Zone { name: "Africa/Johannesburg", rule: "SAST-2" },
+    Zone { name: "Africa/Nairobi", rule: "EAT-3" },
+    Zone { name: "Asia/Jerusalem", rule: "IST-2IDT,M3.4.4/26,M10.5.0" },
+    Zone { name: "Asia/Beirut", rule: "EET-2EEST,M3.5.0/0,M10.5.0/0" },
+    Zone { name: "Asia/Riyadh", rule: "<+03>-3" },
+    Zone { name: "Asia/Baghdad", rule: "<+03>-3" },
+    Zone { name: "Asia/Tehran", rule: "<+0330>-3:30" },
+    Zone { name: "Asia/Dubai", rule: "<+04>-4" },
+    Zone { name: "Asia/Baku", rule: "<+04>-4" },
+    Zone { name: "Asia/Kabul", rule: "<+0430>-4:30" },
+    Zone { name: "Asia/Karachi", rule: "PKT-5" },
+    Zone { name: "Asia/Tashkent", rule: "<+05>-5" },
+    Zone { name: "Asia/Almaty", rule: "<+05>-5" },
+    Zone { name: "Asia/Kolkata", rule: "IST-5:30" },
+    Zone { name: "Asia/Colombo", rule: "<+0530>-5:30" },
+    Zone { name: "Asia/Kathmandu", rule: "<+0545>-5:45" },
+    Zone { name: "Asia/Dhaka", rule: "<+06>-6" },
+    Zone { name: "Asia/Yangon", rule: "<+0630>-6:30" },
+    Zone { name: "Asia/Bangkok", rule: "<+07>-7" },
+    Zone { name: "Asia/Ho_Chi_Minh", rule: "<+07>-7" },
+    Zone { name: "Asia/Jakarta", rule: "WIB-7" },
+    Zone { name: "Asia/Novosibirsk", rule: "<+07>-7" },
+    Zone { name: "Asia/Shanghai", rule: "CST-8" },
+    Zone { name: "Asia/Hong_Kong", rule: "HKT-8" },
+    Zone { name: "Asia/Taipei", rule: "CST-8" },
+    Zone { name: "Asia/Singapore", rule: "<+08>-8" },
+    Zone { name: "Asia/Kuala_Lumpur", rule: "<+08>-8" },
+    Zone { name: "Asia/Manila", rule: "PST-8" },
+    Zone { name: "Australia/Perth", rule: "AWST-8" },
+    Zone { name: "Asia/Seoul", rule: "KST-9" },
+    Zone { name: "Asia/Tokyo", rule: "JST-9" },
+    Zone { name: "Australia/Darwin", rule: "ACST-9:30" },
+    Zone { name: "Australia/Adelaide", rule: "ACST-9:30ACDT,M10.1.0,M4.1.0/3" },
+    Zone { name: "Australia/Brisbane", rule: "AEST-10" },
+    Zone { name: "Australia/Sydney", rule: "AEST-10AEDT,M10.1.0,M4.1.0/3" },
+    Zone { name: "Australia/Melbourne", rule: "AEST-10AEDT,M10.1.0,M4.1.0/3" },
+    Zone { name: "Australia/Hobart", rule: "AEST-10AEDT,M10.1.0,M4.1.0/3" },
+    Zone { name: "Asia/Vladivostok", rule: "<+10>-10" },
+    Zone { name: "Pacific/Guam", rule: "ChST-10" },
+    Zone { name: "Pacific/Noumea", rule: "<+11>-11" },
+    Zone { name: "Pacific/Auckland", rule: "NZST-12NZDT,M9.5.0,M4.1.0/3" },
+    Zone { name: "Pacific/Fiji", rule: "<+12>-12" },
+    Zone { name: "Pacific/Tongatapu", rule: "<+13>-13" },
+    Zone { name: "Pacific/Kiritimati", rule: "<+14>-14" },
+    Zone { name: "Pacific/Pago_Pago", rule: "SST11" },
+    Zone { name: "Pacific/Honolulu", rule: "HST10" },
+    Zone { name: "America/Anchorage", rule: "AKST9AKDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Los_Angeles", rule: "PST8PDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Vancouver", rule: "PST8PDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Tijuana", rule: "PST8PDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Denver", rule: "MST7MDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Edmonton", rule: "MST7MDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Phoenix", rule: "MST7" },
+    Zone { name: "America/Chicago", rule: "CST6CDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Winnipeg", rule: "CST6CDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Regina", rule: "CST6" },
+    Zone { name: "America/Mexico_City", rule: "CST6" },
+    Zone { name: "America/Guatemala", rule: "CST6" },
+    Zone { name: "America/New_York", rule: "EST5EDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Toronto", rule: "EST5EDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Detroit", rule: "EST5EDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Havana", rule: "CST5CDT,M3.2.0/0,M11.1.0/1" },
+    Zone { name: "America/Panama", rule: "EST5" },
+    Zone { name: "America/Bogota", rule: "<-05>5" },
+    Zone { name: "America/Lima", rule: "<-05>5" },
+    Zone { name: "America/Caracas", rule: "<-04>4" },
+    Zone { name: "America/Halifax", rule: "AST4ADT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Puerto_Rico", rule: "AST4" },
+    Zone { name: "America/Santiago", rule: "<-04>4<-03>,M9.1.6/24,M4.1.6/24" },
+    Zone { name: "America/St_Johns", rule: "NST3:30NDT,M3.2.0,M11.1.0" },
+    Zone { name: "America/Sao_Paulo", rule: "<-03>3" },
+    Zone { name: "America/Argentina/Buenos_Aires", rule: "<-03>3" },
+    Zone { name: "America/Montevideo", rule: "<-03>3" },
+    Zone { name: "Atlantic/Azores", rule: "<-01>1<+00>,M3.5.0/0,M10.5.0/1" },
+];
+
+/// Common abbreviations, and the zone they are taken to mean. Most of them
+/// stand for a region rather than a fixed offset, so `PST` in the summer
+/// still follows daylight saving time like people tend to mean it.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("BST", "Europe/London"),
+    ("WET", "Europe/Lisbon"),
+    ("WEST", "Europe/Lisbon"),
+    ("CET", "Europe/Paris"),
+    ("CEST", "Europe/Paris"),
+    ("EET", "Europe/Helsinki"),
+    ("EEST", "Europe/Helsinki"),
+    ("MSK", "Europe/Moscow"),
+    ("WAT", "Africa/Lagos"),
+    ("SAST", "Africa/Johannesburg"),
+    ("EAT", "Africa/Nairobi"),
+    ("PKT", "Asia/Karachi"),
+    ("IST", "Asia/Kolkata"),
+    ("WIB", "Asia/Jakarta"),
+    ("HKT", "Asia/Hong_Kong"),
+    ("SGT", "Asia/Singapore"),
+    ("JST", "Asia/Tokyo"),
+    ("KST", "Asia/Seoul"),
+    ("AWST", "Australia/Perth"),
+    ("ACST", "Australia/Adelaide"),
+    ("ACDT", "Australia/Adelaide"),
+    ("AEST", "Australia/Sydney"),
+    ("AEDT", "Australia/Sydney"),
+    ("NZST", "Pacific/Auckland"),
+    ("NZDT", "Pacific/Auckland"),
+    ("HST", "Pacific/Honolulu"),
+    ("AKST", "America/Anchorage"),
+    ("AKDT", "America/Anchorage"),
+    ("PT", "America/Los_Angeles"),
+    ("PST", "America/Los_Angeles"),
+    ("PDT", "America/Los_Angeles"),
+    ("MT", "America/Denver"),
+    ("MST", "America/Denver"),
+    ("MDT", "America/Denver"),
+    ("CT", "America/Chicago"),
+    ("CST", "America/Chicago"),
+    ("CDT", "America/Chicago"),
+    ("ET", "America/New_York"),
+    ("EST", "America/New_York"),
+    ("EDT", "America/New_York"),
+    ("AST", "America/Halifax"),
+    ("ADT", "America/Halifax"),
+    ("NST", "America/St_Johns"),
+    ("NDT", "America/St_Johns"),
+    ("BRT", "America/Sao_Paulo"),
+    ("ART", "America/Argentina/Buenos_Aires"),
+];
+
+/// When daylight saving time starts or ends: on a weekday (0 is sunday) in a week of
+/// a month (5 is the last one), at a number of seconds after midnight local time.
+#[derive(Debug)]
+struct Transition {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i32,
+}
+
+#[derive(Debug)]
+struct Dst {
+    abbreviation: String,
+    offset: i32,
+    start: Transition,
+    end: Transition,
+}
+
+/// A parsed POSIX TZ string. Offsets are in seconds east of UTC, unlike in the string.
+#[derive(Debug)]
+struct Rule {
+    abbreviation: String,
+    offset: i32,
+    dst: Option<Dst>,
+}
+
+fn take_number(text: &mut &str) -> Option<i32> {
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    if digits == 0 {
+        return None;
+    }
+    let number = text[..digits].parse().ok()?;
+    *text = &text[digits..];
+    Some(number)
+}
+
+fn take_name(text: &mut &str) -> Option<String> {
+    if text.starts_with('<') {
+        let end = text.find('>')?;
+        let name = text[1..end].to_string();
+        *text = &text[end + 1..];
+        Some(name)
+    }
+    else {
+        let len = text.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(text.len());
+        if len < 3 {
+            return None;
+        }
+        let name = text[..len].to_string();
+        *text = &text[len..];
+        Some(name)
+    }
+}
+
+/// Takes a time like `-3`, `5:30` or `2:00:00`, in seconds.
+fn take_time(text: &mut &str) -> Option<i32> {
+    let sign = if text.starts_with('-') { -1 } else { 1 };
+    if text.starts_with('-') || text.starts_with('+') {
+        *text = &text[1..];
+    }
+
+    let mut seconds = take_number(text)? * 3600;
+    for &unit in &[60, 1] {
+        if !text.starts_with(':') {
+            break;
+        }
+        *text = &text[1..];
+        seconds += take_number(text)? * unit;
+    }
+    Some(sign * seconds)
+}
+
+fn take_transition(text: &mut &str) -> Option<Transition> {
+    if !text.starts_with('M') {
+        return None;
+    }
+    *text = &text[1..];
+    let month = take_number(text)? as u32;
+    if !text.starts_with('.') {
+        return None;
+    }
+    *text = &text[1..];
+    let week = take_number(text)? as u32;
+    if !text.starts_with('.') {
+        return None;
+    }
+    *text = &text[1..];
+    let weekday = take_number(text)? as u32;
+
+    let time = if text.starts_with('/') {
+        *text = &text[1..];
+        take_time(text)?
+    }
+    else {
+        2 * 3600
+    };
+
+    if month < 1 || month > 12 || week < 1 || week > 5 || weekday > 6 {
+        return None;
+    }
+    Some(Transition {
+        month: month,
+        week: week,
+        weekday: weekday,
+        time: time,
+    })
+}
+
+fn parse_rule(text: &str) -> Option<Rule> {
+    let mut text = text;
+    let abbreviation = take_name(&mut text)?;
+    let offset = -take_time(&mut text)?;
+    if text.is_empty() {
+        return Some(Rule {
+            abbreviation: abbreviation,
+            offset: offset,
+            dst: None,
+        });
+    }
+
+    let dst_abbreviation = take_name(&mut text)?;
+    let dst_offset = if text.starts_with(',') { offset + 3600 } else { -take_time(&mut text)? };
+    if !text.starts_with(',') {
+        return None;
+    }
+    text = &text[1..];
+    let start = take_transition(&mut text)?;
+    if !text.starts_with(',') {
+        return None;
+    }
+    text = &text[1..];
+    let end = take_transition(&mut text)?;
+    if !text.is_empty() {
+        return None;
+    }
+
+    Some(Rule {
+        abbreviation: abbreviation,
+        offset: offset,
+        dst: Some(Dst {
+            abbreviation: dst_abbreviation,
+            offset: dst_offset,
+            start: start,
+            end: end,
+        }),
+    })
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    NaiveDate::from_ymd(next_year, next_month, 1).pred().day()
+}
+
+/// The time of a transition in a year as a unix timestamp, given the offset in effect before it.
+fn transition_time(year: i32, transition: &Transition, offset: i32) -> i64 {
+    let first = NaiveDate::from_ymd(year, transition.month, 1).weekday().num_days_from_sunday();
+    let mut day = 1 + (transition.weekday + 7 - first) % 7 + (transition.week - 1) * 7;
+    while day > days_in_month(year, transition.month) {
+        day -= 7;
+    }
+    NaiveDate::from_ymd(year, transition.month, day).and_hms(0, 0, 0).timestamp() + (transition.time - offset) as i64
+}
+
+impl Rule {
+    /// The offset and abbreviation in effect at a point in time.
+    fn at(&self, timestamp: i64) -> (i32, &str) {
+        let dst = match self.dst {
+            Some(ref dst) => dst,
+            None => return (self.offset, &self.abbreviation),
+        };
+
+        let year = NaiveDateTime::from_timestamp(timestamp + self.offset as i64, 0).year();
+        let start = transition_time(year, &dst.start, self.offset);
+        let end = transition_time(year, &dst.end, dst.offset);
+        // Daylight saving time spans new year in the southern hemisphere.
+        let in_dst = if start < end {
+            timestamp >= start && timestamp < end
+        }
+        else {
+            timestamp < end || timestamp >= start
+        };
+
+        if in_dst {
+            (dst.offset, &dst.abbreviation)
+        }
+        else {
+            (self.offset, &self.abbreviation)
+        }
+    }
+}
+
+impl Zone {
+    fn rule(&self) -> Rule {
+        // Every rule in the table is checked by the tests, so this can't really fail.
+        parse_rule(self.rule).unwrap_or(Rule {
+            abbreviation: "UTC".to_string(),
+            offset: 0,
+            dst: None,
+        })
+    }
+}
+
+/// Finds a zone by its name (`Europe/Paris`), its city (`paris`, `new york`) or an abbreviation (`CET`).
+pub fn find_zone(name: &str) -> Option<&'static Zone> {
+    let name = name.trim().replace(' ', "_");
+    let by_name = |name: &str| ZONES.iter().find(|zone| zone.name.eq_ignore_ascii_case(name));
+
+    if let Some(zone) = by_name(&name) {
+        return Some(zone);
+    }
+    if let Some(&(_, zone)) = ABBREVIATIONS.iter().find(|&&(abbreviation, _)| abbreviation.eq_ignore_ascii_case(&name)) {
+        return by_name(zone);
+    }
+    ZONES.iter().find(|zone| zone.name.rsplit('/').next().map_or(false, |city| city.eq_ignore_ascii_case(&name)))
+}
+
+/// A timezone that users can pick, so times can be read and shown in their local time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tz {
+    /// A fixed offset from UTC, in seconds.
+    Fixed(i32),
+    /// A zone from the embedded database, which may have daylight saving time.
+    Named(&'static Zone),
+}
+
+/// Parses an offset like `+2`, `-05:30` or `+0530` into seconds.
+fn parse_offset(text: &str) -> Option<i32> {
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let rest = &text[1..];
+    let (hours, minutes) = if let Some(i) = rest.find(':') {
+        (&rest[..i], &rest[i + 1..])
+    }
+    else if rest.len() == 4 && rest.bytes().all(|b| b.is_ascii_digit()) {
+        rest.split_at(2)
+    }
+    else {
+        (rest, "0")
+    };
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > MAX_OFFSET_HOURS || minutes >= 60 || hours < 0 || minutes < 0 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+impl Tz {
+    pub fn utc() -> Tz {
+        Tz::Fixed(0)
+    }
+
+    /// Parses a zone name like `Europe/Paris`, `tokyo` or `PST`, or an offset like `UTC+2`, `GMT-05:30` or `+0200`.
+    pub fn parse(name: &str) -> Option<Tz> {
+        let upper = name.trim().to_uppercase();
+        match upper.as_str() {
+            "UTC" | "GMT" | "Z" => return Some(Tz::utc()),
+            _ => {},
+        }
+
+        let offset = if upper.starts_with("UTC") || upper.starts_with("GMT") {
+            &upper[3..]
+        }
+        else {
+            &upper[..]
+        };
+        parse_offset(offset).map(Tz::Fixed).or_else(|| find_zone(name).map(Tz::Named))
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Tz::Fixed(0) => "UTC".to_string(),
+            Tz::Fixed(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                format!("UTC{}{:02}:{:02}", sign, offset / 3600, offset / 60 % 60)
+            },
+            Tz::Named(zone) => zone.name.to_string(),
+        }
+    }
+
+    /// The offset from UTC in seconds at a point in time.
+    pub fn offset_at(&self, timestamp: i64) -> i32 {
+        match *self {
+            Tz::Fixed(offset) => offset,
+            Tz::Named(zone) => zone.rule().at(timestamp).0,
+        }
+    }
+
+    /// The abbreviation in use at a point in time, like `CEST`. Zones without
+    /// one get their offset, like `+03`.
+    pub fn abbreviation_at(&self, timestamp: i64) -> String {
+        match *self {
+            Tz::Fixed(_) => self.name(),
+            Tz::Named(zone) => zone.rule().at(timestamp).1.to_string(),
+        }
+    }
+
+    /// Converts a unix timestamp to the local time in this timezone.
+    pub fn to_local(&self, timestamp: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp(timestamp + self.offset_at(timestamp) as i64, 0)
+    }
+
+    /// Converts a local time in this timezone to a unix timestamp. Times that happen
+    /// twice when the clocks go back are taken the first time, and times that are
+    /// skipped when the clocks go forward are moved ahead by the difference.
+    pub fn to_utc(&self, local: NaiveDateTime) -> i64 {
+        let rule = match *self {
+            Tz::Fixed(offset) => return local.timestamp() - offset as i64,
+            Tz::Named(zone) => zone.rule(),
+        };
+
+        let standard = rule.offset;
+        let daylight = rule.dst.as_ref().map_or(standard, |dst| dst.offset);
+        for &offset in &[daylight, standard] {
+            let timestamp = local.timestamp() - offset as i64;
+            if rule.at(timestamp).0 == offset {
+                return timestamp;
+            }
+        }
+        local.timestamp() - standard as i64
+    }
+
+    /// Formats a unix timestamp as a local time, with the abbreviation of the timezone.
+    pub fn format(&self, timestamp: i64) -> String {
+        format!("{} {}", self.to_local(timestamp).format("%Y-%m-%d %H:%M"), self.abbreviation_at(timestamp))
+    }
+}
+
+/// Gets the timezone a user has picked, if they have.
+pub fn find_user_timezone(conn: &Connection, user_id: UserId) -> rusqlite::Result<Option<Tz>> {
+    let mut stmt = conn.prepare("SELECT timezone FROM user_timezones WHERE user_id = ?1")?;
+    let mut rows = stmt.query(&[&(user_id.0 as i64)])?;
+    match rows.next() {
+        Some(row) => {
+            let name: String = row?.get(0);
+            Ok(Tz::parse(&name))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Gets the timezone a user has picked, UTC if they haven't.
+pub fn user_timezone(conn: &Connection, user_id: UserId) -> rusqlite::Result<Tz> {
+    find_user_timezone(conn, user_id).map(|tz| tz.unwrap_or_else(Tz::utc))
+}
+
+/// Gets the timezones of all the users that have picked one.
+pub fn all_user_timezones(conn: &Connection) -> rusqlite::Result<Vec<(UserId, Tz)>> {
+    let mut stmt = conn.prepare("SELECT user_id, timezone FROM user_timezones")?;
+    let rows = stmt.query_map(&[], |row| (UserId(row.get::<_, i64>(0) as u64), row.get::<_, String>(1)))?;
+    let mut timezones = Vec::new();
+    for row in rows {
+        let (user_id, name) = row?;
+        if let Some(tz) = Tz::parse(&name) {
+            timezones.push((user_id, tz));
+        }
+    }
+    Ok(timezones)
+}
+
+pub fn set_user_timezone(conn: &Connection, user_id: UserId, tz: Tz) -> rusqlite::Result<()> {
+    conn.execute("INSERT OR REPLACE INTO user_timezones (user_id, timezone) VALUES (?1, ?2)",
+        &[&(user_id.0 as i64), &tz.name()])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn parse_fixed_offsets() {
+        assert_eq!(Tz::parse("utc"), Some(Tz::Fixed(0)));
+        assert_eq!(Tz::parse("UTC+2"), Some(Tz::Fixed(7200)));
+        assert_eq!(Tz::parse("gmt-05:30"), Some(Tz::Fixed(-19800)));
+        assert_eq!(Tz::parse("+0530"), Some(Tz::Fixed(19800)));
+        assert_eq!(Tz::parse("UTC+15"), None);
+        assert_eq!(Tz::parse("+1é2"), None);
+        assert_eq!(Tz::parse("UTC-é1"), None);
+        assert_eq!(Tz::parse("Mars"), None);
+
+        assert_eq!(Tz::Fixed(-19800).name(), "UTC-05:30");
+        assert_eq!(Tz::parse(&Tz::Fixed(3600).name()), Some(Tz::Fixed(3600)));
+
+        let local = NaiveDate::from_ymd(2018, 5, 1).and_hms(18, 0, 0);
+        let tz = Tz::Fixed(7200);
+        assert_eq!(tz.to_utc(local), local.timestamp() - 7200);
+        assert_eq!(tz.to_local(tz.to_utc(local)), local);
+    }
+
+    #[test]
+    fn embedded_rules_parse() {
+        for zone in ZONES {
+            assert!(parse_rule(zone.rule).is_some(), "bad rule for {}", zone.name);
+        }
+        for &(abbreviation, zone) in ABBREVIATIONS {
+            assert!(find_zone(zone).is_some(), "{} points to unknown zone {}", abbreviation, zone);
+        }
+    }
+
+    #[test]
+    fn find_zones() {
+        assert_eq!(Tz::parse("europe/paris").map(|tz| tz.name()), Some("Europe/Paris".to_string()));
+        assert_eq!(Tz::parse("New York").map(|tz| tz.name()), Some("America/New_York".to_string()));
+        assert_eq!(Tz::parse("pst").map(|tz| tz.name()), Some("America/Los_Angeles".to_string()));
+        assert_eq!(Tz::parse("Atlantis"), None);
+    }
+
+    #[test]
+    fn daylight_saving_time() {
+        let ts = |y, m, d, h, min| NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0).timestamp();
+        let paris = Tz::parse("Europe/Paris").unwrap();
+        assert_eq!(paris.offset_at(ts(2018, 1, 15, 12, 0)), 3600);
+        assert_eq!(paris.offset_at(ts(2018, 7, 1, 12, 0)), 7200);
+        // The clocks went forward at 01:00 UTC on 2018-03-25 and back at 01:00 UTC on 2018-10-28.
+        assert_eq!(paris.offset_at(ts(2018, 3, 25, 1, 0) - 1), 3600);
+        assert_eq!(paris.offset_at(ts(2018, 3, 25, 1, 0)), 7200);
+        assert_eq!(paris.offset_at(ts(2018, 10, 28, 1, 0) - 1), 7200);
+        assert_eq!(paris.offset_at(ts(2018, 10, 28, 1, 0)), 3600);
+        assert_eq!(paris.abbreviation_at(ts(2018, 7, 1, 12, 0)), "CEST");
+
+        let sydney = Tz::parse("Australia/Sydney").unwrap();
+        assert_eq!(sydney.offset_at(ts(2018, 1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(sydney.offset_at(ts(2018, 7, 1, 0, 0)), 10 * 3600);
+
+        let new_york = Tz::parse("EST").unwrap();
+        assert_eq!(new_york.offset_at(ts(2018, 3, 11, 7, 0) - 1), -5 * 3600);
+        assert_eq!(new_york.offset_at(ts(2018, 3, 11, 7, 0)), -4 * 3600);
+        assert_eq!(Tz::parse("Kathmandu").unwrap().offset_at(0), 5 * 3600 + 45 * 60);
+
+        // 02:30 didn't happen in Paris on 2018-03-25, and 02:30 happened twice on 2018-10-28.
+        let local = |y, m, d, h, min| NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0);
+        assert_eq!(paris.to_utc(local(2018, 7, 1, 14, 0)), ts(2018, 7, 1, 12, 0));
+        assert_eq!(paris.to_utc(local(2018, 3, 25, 2, 30)), ts(2018, 3, 25, 1, 30));
+        assert_eq!(paris.to_utc(local(2018, 10, 28, 2, 30)), ts(2018, 10, 28, 0, 30));
+    }
+}